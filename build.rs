@@ -1,5 +1,5 @@
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 fn main() {
@@ -7,9 +7,10 @@ fn main() {
     println!("cargo:rerun-if-changed=runtime/lox_runtime.h");
 
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is always set");
-    let runtime_dir = Path::new(&manifest_dir).join("runtime");
-    let source = runtime_dir.join("lox_runtime.c");
-    let obj_output = runtime_dir.join("lox_runtime.o");
+    let source = Path::new(&manifest_dir).join("runtime/lox_runtime.c");
+    // Build products go to OUT_DIR so `cargo build` never touches the checkout.
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR is always set"));
+    let obj_output = out_dir.join("lox_runtime.o");
 
     let cc = env::var("CC").unwrap_or_else(|_| "gcc".to_string());
 
@@ -25,4 +26,22 @@ fn main() {
     }
 
     println!("cargo:rustc-env=LOX_RUNTIME_OBJ={}", obj_output.display());
+
+    // Also archive the runtime and link it into this crate, so `--jit` can
+    // point JIT-compiled code at the runtime functions in our own process.
+    let archive = out_dir.join("liblox_runtime.a");
+    let _ = std::fs::remove_file(&archive);
+    let status = Command::new("ar")
+        .arg("crs")
+        .arg(&archive)
+        .arg(&obj_output)
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run `ar`: {e}"));
+
+    if !status.success() {
+        panic!("ar failed to build {}", archive.display());
+    }
+
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+    println!("cargo:rustc-link-lib=static=lox_runtime");
 }
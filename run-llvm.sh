@@ -16,7 +16,10 @@ fi
 
 input="$1"
 script_dir="$(cd "$(dirname "$0")" && pwd)"
-runtime_obj="$script_dir/runtime/lox_runtime.o"
+# build.rs compiles the runtime into Cargo's OUT_DIR; use the newest one.
+find_runtime_obj() {
+    ls -t "$script_dir"/target/debug/build/vibe-lox-*/out/lox_runtime.o 2>/dev/null | head -n 1 || true
+}
 
 # Strip any .ll or .lox extension to get the base name
 base="${input%.ll}"
@@ -28,15 +31,17 @@ lox_file="${base}.lox"
 if [[ -f "$ll_file" ]]; then
     : # already compiled
 elif [[ -f "$lox_file" ]]; then
-    if [[ ! -f "$runtime_obj" ]]; then
-        cargo -q build --manifest-path "$script_dir/Cargo.toml" >&2
-    fi
-
     cargo -q run --manifest-path "$script_dir/Cargo.toml" -- -q --compile-llvm "$lox_file" >&2
 else
     echo "Error: neither $ll_file nor $lox_file found." >&2
     exit 1
 fi
 
+runtime_obj="$(find_runtime_obj)"
+if [[ -z "$runtime_obj" ]]; then
+    cargo -q build --manifest-path "$script_dir/Cargo.toml" >&2
+    runtime_obj="$(find_runtime_obj)"
+fi
+
 # Run via lli
 lli --extra-object "$runtime_obj" "$ll_file"
@@ -40,50 +40,60 @@ static double payload_to_double(int64_t payload) {
   return d;
 }
 
-void lox_print(LoxValue value) {
+static void print_value(LoxValue value) {
   switch (value.tag) {
   case TAG_NIL:
-    printf("nil\n");
+    printf("nil");
     break;
   case TAG_BOOL:
-    printf("%s\n", value.payload ? "true" : "false");
+    printf("%s", value.payload ? "true" : "false");
     break;
   case TAG_NUMBER: {
     double d = payload_to_double(value.payload);
     /* Print integers without trailing .0, matching Lox semantics */
     if (d == floor(d) && !isinf(d) && fabs(d) < 1e15) {
-      printf("%.0f\n", d);
+      printf("%.0f", d);
     } else {
-      printf("%g\n", d);
+      printf("%g", d);
     }
     break;
   }
   case TAG_STRING: {
     const char *s = (const char *)(intptr_t)value.payload;
-    printf("%s\n", s);
+    printf("%s", s);
     break;
   }
   case TAG_FUNCTION: {
     LoxClosure *closure = (LoxClosure *)(intptr_t)value.payload;
-    printf("<fn %s>\n", closure->name ? closure->name : "?");
+    printf("<fn %s(%d)>", closure->name ? closure->name : "?", closure->arity);
     break;
   }
   case TAG_CLASS: {
     LoxClassDesc *klass = (LoxClassDesc *)(intptr_t)value.payload;
-    printf("%s\n", klass->name);
+    printf("<class %s>", klass->name);
     break;
   }
   case TAG_INSTANCE: {
     LoxInstance *inst = (LoxInstance *)(intptr_t)value.payload;
-    printf("%s instance\n", inst->klass->name);
+    printf("%s instance", inst->klass->name);
     break;
   }
   default:
-    printf("<unknown value tag %d>\n", value.tag);
+    printf("<unknown value tag %d>", value.tag);
     break;
   }
 }
 
+void lox_print(LoxValue value) {
+  print_value(value);
+  putchar('\n');
+}
+
+void lox_print_part(LoxValue value) {
+  print_value(value);
+  putchar(' ');
+}
+
 LoxValue lox_global_get(const char *name, int64_t name_len) {
   GlobalEntry *entry = find_global(name, name_len);
   if (entry) {
@@ -242,6 +252,16 @@ LoxValue lox_bind_method(LoxValue instance, LoxClosure *method) {
   return v;
 }
 
+int8_t lox_instance_of(LoxValue value, LoxValue klass) {
+  if (value.tag != TAG_INSTANCE) return 0;
+  LoxInstance *inst = (LoxInstance *)(intptr_t)value.payload;
+  LoxClassDesc *target = (LoxClassDesc *)(intptr_t)klass.payload;
+  for (LoxClassDesc *k = inst->klass; k != NULL; k = k->superclass) {
+    if (k == target) return 1;
+  }
+  return 0;
+}
+
 LoxValue lox_instance_get_property(LoxValue instance, const char *name,
                                     int64_t name_len) {
   LoxInstance *inst = extract_instance(instance);
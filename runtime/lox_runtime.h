@@ -42,6 +42,7 @@ typedef struct {
 typedef LoxValue LoxCell;
 
 void lox_print(LoxValue value);
+void lox_print_part(LoxValue value);
 LoxValue lox_global_get(const char *name, int64_t name_len);
 void lox_global_set(const char *name, int64_t name_len, LoxValue value);
 int8_t lox_value_truthy(LoxValue value);
@@ -89,6 +90,9 @@ void lox_instance_set_field(LoxValue instance, const char *name,
                              int64_t name_len, LoxValue value);
 LoxClosure *lox_class_find_method(LoxClassDesc *klass, const char *name);
 LoxValue lox_bind_method(LoxValue instance, LoxClosure *method);
+/* Whether `value` is an instance of `klass` (a TAG_CLASS value) or one of
+ * its subclasses. Non-instances are never instances of anything. */
+int8_t lox_instance_of(LoxValue value, LoxValue klass);
 
 /* Native functions */
 LoxValue lox_clock(void);
@@ -0,0 +1,163 @@
+//! Whole-program queries over a parsed and resolved [`Program`].
+
+use std::collections::BTreeSet;
+
+use crate::ast::{Decl, Expr, Function, Program, Stmt};
+use crate::interpreter::callable::NativeFunction;
+use crate::interpreter::resolver::Locals;
+
+/// Global names `program` reads but never declares at top level, sorted and
+/// without duplicates: its external dependencies. Natives are always
+/// defined, so they are left out.
+///
+/// A read is a `Variable` expression the resolver didn't bind to a local
+/// (it has no entry in `locals`), or the superclass of a top-level class.
+/// Nested classes look their superclass up by name too, but it may be a
+/// local, so those are not reported.
+pub fn free_globals(program: &Program, locals: &Locals) -> Vec<String> {
+    let declared: BTreeSet<&str> = program
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            Decl::Class(c) => Some(c.name.as_str()),
+            Decl::Fun(f) => Some(f.function.name.as_str()),
+            Decl::Var(v) => Some(v.name.as_str()),
+            Decl::Statement(_) => None,
+        })
+        .collect();
+    let mut reads = Reads {
+        locals,
+        names: BTreeSet::new(),
+    };
+    for decl in &program.declarations {
+        if let Decl::Class(c) = decl
+            && let Some(superclass) = &c.superclass
+        {
+            reads.names.insert(superclass.clone());
+        }
+        reads.decl(decl);
+    }
+    reads
+        .names
+        .into_iter()
+        .filter(|name| !declared.contains(name.as_str()))
+        .filter(|name| NativeFunction::ALL.iter().all(|n| n.name() != name))
+        .collect()
+}
+
+/// Collects the name of every unresolved `Variable` read.
+struct Reads<'a> {
+    locals: &'a Locals,
+    names: BTreeSet<String>,
+}
+
+impl Reads<'_> {
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Class(c) => c.methods.iter().for_each(|m| self.function(m)),
+            Decl::Fun(f) => self.function(&f.function),
+            Decl::Var(v) => {
+                if let Some(initializer) = &v.initializer {
+                    self.expr(initializer);
+                }
+            }
+            Decl::Statement(s) => self.stmt(s),
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        function.body.iter().for_each(|d| self.decl(d));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(s) => self.expr(&s.expression),
+            Stmt::Print(s) => s.expressions.iter().for_each(|e| self.expr(e)),
+            Stmt::Return(s) => {
+                if let Some(value) = &s.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Block(s) => s.declarations.iter().for_each(|d| self.decl(d)),
+            Stmt::If(s) => {
+                self.expr(&s.condition);
+                self.stmt(&s.then_branch);
+                if let Some(else_branch) = &s.else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While(s) => {
+                self.expr(&s.condition);
+                self.stmt(&s.body);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Variable(v) => {
+                if !self.locals.contains_key(&v.id) {
+                    self.names.insert(v.name.clone());
+                }
+            }
+            Expr::Binary(e) => {
+                self.expr(&e.left);
+                self.expr(&e.right);
+            }
+            Expr::Logical(e) => {
+                self.expr(&e.left);
+                self.expr(&e.right);
+            }
+            Expr::Unary(e) => self.expr(&e.operand),
+            Expr::Grouping(e) => self.expr(&e.expression),
+            Expr::Assign(e) => self.expr(&e.value),
+            Expr::Call(e) => {
+                self.expr(&e.callee);
+                e.arguments.iter().for_each(|a| self.expr(a));
+            }
+            Expr::Get(e) => self.expr(&e.object),
+            Expr::Set(e) => {
+                self.expr(&e.object);
+                self.expr(&e.value);
+            }
+            Expr::Literal(_) | Expr::This(_) | Expr::Super(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::resolver::Resolver;
+    use crate::parser::Parser;
+    use crate::scanner;
+    use rstest::rstest;
+
+    fn deps(source: &str) -> Vec<String> {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        free_globals(&program, &locals)
+    }
+
+    #[rstest]
+    #[case::undeclared_and_native("print clock() + foo;", vec!["foo"])]
+    #[case::declared_later("fun f() { return later; } var later = 1;", vec![])]
+    #[case::locals_and_params("fun f(a) { var b = a; { print b + c; } }", vec!["c"])]
+    #[case::sorted_and_deduplicated("print z; print a; print z;", vec!["a", "z"])]
+    #[case::nested_function(
+        "fun outer() { fun inner() { return helper(); } return inner; }",
+        vec!["helper"]
+    )]
+    #[case::superclass("class B < Base { m() { return super.m(); } }", vec!["Base"])]
+    #[case::in_method_and_condition(
+        "class A { m() { if (flag) return this.x ?? fallback; } }",
+        vec!["fallback", "flag"]
+    )]
+    #[case::assignment_only("x = 1;", vec![])]
+    fn free_globals_lists_undeclared_reads(#[case] source: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(deps(source), expected);
+    }
+}
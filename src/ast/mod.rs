@@ -1,5 +1,8 @@
+pub mod normalize;
 pub mod printer;
 
+pub use normalize::strip_groupings;
+
 use serde::Serialize;
 
 use crate::scanner::token::Span;
@@ -22,6 +25,17 @@ pub enum Decl {
     Statement(Stmt),
 }
 
+impl Decl {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Class(d) => d.span,
+            Self::Fun(d) => d.span,
+            Self::Var(d) => d.span,
+            Self::Statement(s) => s.span(),
+        }
+    }
+}
+
 // Serde cannot nest two #[serde(tag = "type")] enums as a newtype variant
 // without emitting duplicate "type" keys. DeclSerHelper handles the three
 // struct-wrapping variants so their "type" tags are emitted correctly, while
@@ -63,6 +77,8 @@ pub struct FunDecl {
 pub struct VarDecl {
     pub name: String,
     pub initializer: Option<Expr>,
+    /// Declared with `const`: the resolver rejects any assignment to it.
+    pub is_const: bool,
     pub span: Span,
 }
 
@@ -85,6 +101,19 @@ pub enum Stmt {
     While(WhileStmt),
 }
 
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Expression(s) => s.span,
+            Self::Print(s) => s.span,
+            Self::Return(s) => s.span,
+            Self::Block(s) => s.span,
+            Self::If(s) => s.span,
+            Self::While(s) => s.span,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ExprStmt {
     pub expression: Expr,
@@ -93,7 +122,8 @@ pub struct ExprStmt {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct PrintStmt {
-    pub expression: Expr,
+    /// Values to print, separated by single spaces on one line.
+    pub expressions: Vec<Expr>,
     pub span: Span,
 }
 
@@ -196,6 +226,10 @@ pub enum BinaryOp {
     Multiply,
     #[strum(serialize = "/")]
     Divide,
+    #[strum(serialize = "%")]
+    Modulo,
+    #[strum(serialize = "**")]
+    Power,
     #[strum(serialize = "==")]
     Equal,
     #[strum(serialize = "!=")]
@@ -208,6 +242,9 @@ pub enum BinaryOp {
     Greater,
     #[strum(serialize = ">=")]
     GreaterEqual,
+    /// `x is C`: whether `x` is an instance of class `C` or a subclass.
+    #[strum(serialize = "is")]
+    Is,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -268,6 +305,9 @@ pub struct AssignExpr {
 pub enum LogicalOp {
     And,
     Or,
+    /// `a ?? b`: `a` unless it is nil, otherwise `b`.
+    #[strum(serialize = "??")]
+    Coalesce,
 }
 
 #[derive(Debug, Clone, Serialize)]
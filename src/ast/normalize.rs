@@ -0,0 +1,150 @@
+//! Rewrites over a parsed [`Program`] that simplify its shape without
+//! changing what it means.
+
+use super::{Decl, Expr, Function, Program, Stmt};
+use crate::scanner::token::Span;
+
+/// Replace every `Grouping(inner)` with `inner`, giving the unwrapped
+/// expression the grouping's span so diagnostics still cover the
+/// parentheses. Nested groupings collapse to a single expression.
+pub fn strip_groupings(program: &mut Program) {
+    for decl in &mut program.declarations {
+        strip_decl(decl);
+    }
+}
+
+fn strip_decl(decl: &mut Decl) {
+    match decl {
+        Decl::Class(class) => class.methods.iter_mut().for_each(strip_function),
+        Decl::Fun(fun) => strip_function(&mut fun.function),
+        Decl::Var(var) => {
+            if let Some(initializer) = &mut var.initializer {
+                strip_expr(initializer);
+            }
+        }
+        Decl::Statement(stmt) => strip_stmt(stmt),
+    }
+}
+
+fn strip_function(function: &mut Function) {
+    function.body.iter_mut().for_each(strip_decl);
+}
+
+fn strip_stmt(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expression(s) => strip_expr(&mut s.expression),
+        Stmt::Print(s) => s.expressions.iter_mut().for_each(strip_expr),
+        Stmt::Return(s) => {
+            if let Some(value) = &mut s.value {
+                strip_expr(value);
+            }
+        }
+        Stmt::Block(s) => s.declarations.iter_mut().for_each(strip_decl),
+        Stmt::If(s) => {
+            strip_expr(&mut s.condition);
+            strip_stmt(&mut s.then_branch);
+            if let Some(else_branch) = &mut s.else_branch {
+                strip_stmt(else_branch);
+            }
+        }
+        Stmt::While(s) => {
+            strip_expr(&mut s.condition);
+            strip_stmt(&mut s.body);
+        }
+    }
+}
+
+fn strip_expr(expr: &mut Expr) {
+    if let Expr::Grouping(group) = expr {
+        let span = group.span;
+        let mut inner = std::mem::replace(
+            &mut *group.expression,
+            Expr::This(super::ThisExpr { id: 0, span }),
+        );
+        strip_expr(&mut inner);
+        set_span(&mut inner, span);
+        *expr = inner;
+        return;
+    }
+    match expr {
+        Expr::Binary(e) => {
+            strip_expr(&mut e.left);
+            strip_expr(&mut e.right);
+        }
+        Expr::Unary(e) => strip_expr(&mut e.operand),
+        Expr::Assign(e) => strip_expr(&mut e.value),
+        Expr::Logical(e) => {
+            strip_expr(&mut e.left);
+            strip_expr(&mut e.right);
+        }
+        Expr::Call(e) => {
+            strip_expr(&mut e.callee);
+            e.arguments.iter_mut().for_each(strip_expr);
+        }
+        Expr::Get(e) => strip_expr(&mut e.object),
+        Expr::Set(e) => {
+            strip_expr(&mut e.object);
+            strip_expr(&mut e.value);
+        }
+        Expr::Grouping(_)
+        | Expr::Literal(_)
+        | Expr::Variable(_)
+        | Expr::This(_)
+        | Expr::Super(_) => {}
+    }
+}
+
+fn set_span(expr: &mut Expr, span: Span) {
+    let slot = match expr {
+        Expr::Binary(e) => &mut e.span,
+        Expr::Unary(e) => &mut e.span,
+        Expr::Literal(e) => &mut e.span,
+        Expr::Grouping(e) => &mut e.span,
+        Expr::Variable(e) => &mut e.span,
+        Expr::Assign(e) => &mut e.span,
+        Expr::Logical(e) => &mut e.span,
+        Expr::Call(e) => &mut e.span,
+        Expr::Get(e) => &mut e.span,
+        Expr::Set(e) => &mut e.span,
+        Expr::This(e) => &mut e.span,
+        Expr::Super(e) => &mut e.span,
+    };
+    *slot = span;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::printer::to_sexp;
+    use crate::parser::Parser;
+    use crate::scanner;
+
+    fn parse(source: &str) -> Program {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        Parser::new(tokens).parse().expect("parse should succeed")
+    }
+
+    #[test]
+    fn stripped_program_has_no_groupings() {
+        let mut program = parse(
+            "var a = ((1)); print (a + (2)) * 3; \
+             fun f(x) { if ((x)) return (-(x)); while ((false)) {} }",
+        );
+        assert!(to_sexp(&program).contains("(group "));
+        strip_groupings(&mut program);
+        let sexp = to_sexp(&program);
+        assert!(!sexp.contains("(group "), "{sexp}");
+        assert!(sexp.contains("(* (+ a 2) 3)"), "{sexp}");
+    }
+
+    #[test]
+    fn unwrapped_expression_keeps_the_grouping_span() {
+        let mut program = parse("print ((1 + 2));");
+        strip_groupings(&mut program);
+        let Decl::Statement(Stmt::Print(print)) = &program.declarations[0] else {
+            panic!("expected a print statement");
+        };
+        assert!(matches!(print.expressions[0], Expr::Binary(_)));
+        assert_eq!(print.expressions[0].span(), Span::new(6, 9));
+    }
+}
@@ -30,7 +30,7 @@ fn sexp_decl(buf: &mut String, decl: &Decl) {
         }
         Decl::Fun(f) => sexp_function(buf, &f.function),
         Decl::Var(v) => {
-            buf.push_str("(var ");
+            buf.push_str(if v.is_const { "(const " } else { "(var " });
             buf.push_str(&v.name);
             if let Some(ref init) = v.initializer {
                 buf.push(' ');
@@ -64,8 +64,11 @@ fn sexp_stmt(buf: &mut String, stmt: &Stmt) {
     match stmt {
         Stmt::Expression(e) => sexp_expr(buf, &e.expression),
         Stmt::Print(p) => {
-            buf.push_str("(print ");
-            sexp_expr(buf, &p.expression);
+            buf.push_str("(print");
+            for expr in &p.expressions {
+                buf.push(' ');
+                sexp_expr(buf, expr);
+            }
             buf.push(')');
         }
         Stmt::Return(r) => {
@@ -233,11 +236,11 @@ mod tests {
     fn json_statement_uses_inner_type_not_statement_wrapper() {
         let program = Program {
             declarations: vec![Decl::Statement(Stmt::Print(PrintStmt {
-                expression: Expr::Literal(LiteralExpr {
+                expressions: vec![Expr::Literal(LiteralExpr {
                     id: 0,
                     value: LiteralValue::String("hello".to_string()),
                     span: Span::new(6, 7),
-                }),
+                })],
                 span: Span::new(0, 14),
             }))],
         };
@@ -260,6 +263,7 @@ mod tests {
                     value: LiteralValue::Number(42.0),
                     span: Span::new(8, 2),
                 })),
+                is_const: false,
                 span: Span::new(0, 11),
             })],
         };
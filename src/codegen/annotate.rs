@@ -0,0 +1,101 @@
+//! Interleave `; line N: <source>` comments into textual LLVM IR, so
+//! `--compile-llvm --annotate` output can be read against the Lox source.
+
+use std::collections::HashMap;
+
+/// Insert a `; line N: <source text>` comment before each instruction whose
+/// `!dbg` location starts a new source line within its function.
+///
+/// Relies on the module having been built with line-table debug info;
+/// instructions without a location are left unannotated.
+pub fn annotate_ir(ir: &str, source: &str) -> String {
+    let locations = debug_location_lines(ir);
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut out = String::with_capacity(ir.len());
+    let mut last_line = None;
+    for ir_line in ir.lines() {
+        if ir_line.starts_with("define ") {
+            last_line = None;
+        }
+        if let Some(&line) = dbg_ref(ir_line).and_then(|id| locations.get(id))
+            && last_line != Some(line)
+        {
+            let indent = &ir_line[..ir_line.len() - ir_line.trim_start().len()];
+            let text = line
+                .checked_sub(1)
+                .and_then(|index| source_lines.get(index))
+                .map_or("", |text| text.trim());
+            out.push_str(&format!("{indent}; line {line}: {text}\n"));
+            last_line = Some(line);
+        }
+        out.push_str(ir_line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Map each `!N = !DILocation(line: L, ...)` metadata id `N` to its line `L`.
+fn debug_location_lines(ir: &str) -> HashMap<&str, usize> {
+    ir.lines()
+        .filter_map(|line| {
+            let (id, rest) = line.split_once(" = !DILocation(line: ")?;
+            let line = leading_digits(rest).parse().ok()?;
+            Some((id.strip_prefix('!')?, line))
+        })
+        .collect()
+}
+
+/// The metadata id `N` of an instruction's `!dbg !N` attachment.
+fn dbg_ref(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("!dbg !")?;
+    Some(leading_digits(rest))
+}
+
+fn leading_digits(s: &str) -> &str {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IR: &str = "\
+define i32 @main() !dbg !5 {
+entry:
+  %a = alloca i64, align 8, !dbg !9
+  store i64 1, ptr %a, align 8, !dbg !9
+  %b = load i64, ptr %a, align 8, !dbg !10
+  ret i32 0
+}
+!9 = !DILocation(line: 1, scope: !5)
+!10 = !DILocation(line: 2, scope: !5)
+";
+
+    #[test]
+    fn comments_precede_each_new_line() {
+        let annotated = annotate_ir(IR, "var a = 1;\n  print a;\n");
+        assert_eq!(
+            annotated,
+            "\
+define i32 @main() !dbg !5 {
+entry:
+  ; line 1: var a = 1;
+  %a = alloca i64, align 8, !dbg !9
+  store i64 1, ptr %a, align 8, !dbg !9
+  ; line 2: print a;
+  %b = load i64, ptr %a, align 8, !dbg !10
+  ret i32 0
+}
+!9 = !DILocation(line: 1, scope: !5)
+!10 = !DILocation(line: 2, scope: !5)
+"
+        );
+    }
+
+    #[test]
+    fn ir_without_line_tables_is_unchanged() {
+        let ir = "define i32 @main() {\nentry:\n  ret i32 0\n}\n";
+        assert_eq!(annotate_ir(ir, "print 1;"), ir);
+    }
+}
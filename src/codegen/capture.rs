@@ -7,15 +7,27 @@ use crate::ast::*;
 /// Produced by analyzing the AST before codegen. Used to determine:
 /// - Which local variables need heap-allocated cells instead of stack allocas
 /// - Which captured variables each function needs in its environment
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct CaptureInfo {
-    /// Variable names that are captured by at least one inner function,
-    /// keyed by the declaring function (empty string = top-level).
+    captured_vars: HashSet<CapturedVar>,
+    function_captures: HashMap<String, Vec<String>>,
+}
+
+impl CaptureInfo {
+    /// Variables captured by at least one inner function, each paired with
+    /// the function that declares it (empty string = top-level).
     /// These variables must use cells instead of allocas.
-    pub captured_vars: HashSet<CapturedVar>,
+    pub fn captured_vars(&self) -> &HashSet<CapturedVar> {
+        &self.captured_vars
+    }
 
-    /// For each function (by name), the list of captured variable names
-    /// it references from enclosing scopes, in order.
-    pub function_captures: HashMap<String, Vec<String>>,
+    /// For each function (by name), the captured variable names it references
+    /// from enclosing scopes, in first-reference order. Functions that only
+    /// pass a capture through to a nested closure list it too; functions that
+    /// capture nothing have no entry.
+    pub fn function_captures(&self) -> &HashMap<String, Vec<String>> {
+        &self.function_captures
+    }
 }
 
 /// Identifies a captured variable by its name and the function it's declared in.
@@ -181,7 +193,11 @@ impl CaptureAnalyzer {
     fn visit_stmt(&mut self, stmt: &Stmt) {
         match stmt {
             Stmt::Expression(e) => self.visit_expr(&e.expression),
-            Stmt::Print(p) => self.visit_expr(&p.expression),
+            Stmt::Print(p) => {
+                for expr in &p.expressions {
+                    self.visit_expr(expr);
+                }
+            }
             Stmt::Return(r) => {
                 if let Some(ref val) = r.value {
                     self.visit_expr(val);
@@ -243,3 +259,72 @@ impl CaptureAnalyzer {
 pub fn analyze_captures(program: &Program) -> CaptureInfo {
     CaptureAnalyzer::new().analyze(program)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner;
+    use rstest::rstest;
+
+    fn analyze(source: &str) -> CaptureInfo {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        analyze_captures(&program)
+    }
+
+    fn captured(pairs: &[(&str, &str)]) -> HashSet<CapturedVar> {
+        pairs
+            .iter()
+            .map(|(var_name, declaring_function)| CapturedVar {
+                var_name: var_name.to_string(),
+                declaring_function: declaring_function.to_string(),
+            })
+            .collect()
+    }
+
+    fn captures(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(function, names)| {
+                let names = names.iter().map(|name| name.to_string()).collect();
+                (function.to_string(), names)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inner_function_captures_outer_local() {
+        let info = analyze("fun outer() { var x = 1; fun inner() { return x; } }");
+        assert_eq!(info.captured_vars(), &captured(&[("x", "outer")]));
+        assert_eq!(info.function_captures(), &captures(&[("inner", &["x"])]));
+    }
+
+    #[rstest]
+    #[case::globals_are_not_captured("var g = 1; fun f() { return g; }")]
+    #[case::own_locals_and_params("fun f(a) { var b = a; return b; }")]
+    #[case::empty_program("")]
+    fn no_captures(#[case] source: &str) {
+        assert_eq!(analyze(source), CaptureInfo::default());
+    }
+
+    #[test]
+    fn intermediate_functions_pass_captures_through() {
+        let info = analyze(
+            "fun a() { var x = 1; var y = 2; \
+               fun b() { fun c() { return y + x; } return y; } }",
+        );
+        assert_eq!(info.captured_vars(), &captured(&[("x", "a"), ("y", "a")]));
+        assert_eq!(
+            info.function_captures(),
+            &captures(&[("b", &["y", "x"]), ("c", &["y", "x"])])
+        );
+    }
+
+    #[test]
+    fn parameters_and_assignments_are_captured() {
+        let info = analyze("fun counter(n) { fun inc() { n = n + 1; return n; } return inc; }");
+        assert_eq!(info.captured_vars(), &captured(&[("n", "counter")]));
+        assert_eq!(info.function_captures(), &captures(&[("inc", &["n"])]));
+    }
+}
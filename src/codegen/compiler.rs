@@ -3,7 +3,12 @@ use std::collections::HashMap;
 use inkwell::AddressSpace;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
+use inkwell::debug_info::{
+    AsDIScope, DICompileUnit, DIFlags, DIFlagsConstants, DWARFEmissionKind, DWARFSourceLanguage,
+    DebugInfoBuilder,
+};
+use inkwell::intrinsics::Intrinsic;
+use inkwell::module::{FlagBehavior, Module};
 use inkwell::values::{
     BasicMetadataValueEnum, BasicValueEnum, FunctionValue, PointerValue, StructValue,
 };
@@ -56,6 +61,8 @@ pub struct CodeGen<'ctx> {
     return_target: Option<(PointerValue<'ctx>, inkwell::basic_block::BasicBlock<'ctx>)>,
     /// Source text of the program, used to compute line numbers from spans.
     source: String,
+    /// DWARF builder and compile unit, present when debug info is enabled.
+    debug_info: Option<(DebugInfoBuilder<'ctx>, DICompileUnit<'ctx>)>,
 }
 
 impl<'ctx> CodeGen<'ctx> {
@@ -83,12 +90,57 @@ impl<'ctx> CodeGen<'ctx> {
             current_lox_fn: String::new(),
             return_target: None,
             source: source.to_string(),
+            debug_info: None,
         }
     }
 
+    /// Emit DWARF line tables tying instructions back to lines of `filename`.
+    ///
+    /// Each Lox function (and `main`) gets a subprogram; instructions are
+    /// tagged with the line of the statement they were generated for.
+    pub fn enable_debug_info(&mut self, filename: &str) {
+        let path = std::path::Path::new(filename);
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(filename);
+        let directory = path
+            .parent()
+            .and_then(|dir| dir.to_str())
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or(".");
+
+        let debug_metadata_version = self.context.i32_type().const_int(3, false);
+        self.module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            debug_metadata_version,
+        );
+        self.debug_info = Some(self.module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            file_name,
+            directory,
+            "vibe-lox",
+            false,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::LineTablesOnly,
+            0,
+            false,
+            false,
+            "",
+            "",
+        ));
+    }
+
     /// Emit LLVM IR for a Lox program, returning the LLVM module.
     pub fn emit(mut self, program: &Program) -> anyhow::Result<Module<'ctx>> {
         self.emit_main(program)?;
+        if let Some((debug_builder, _)) = &self.debug_info {
+            debug_builder.finalize();
+        }
         Ok(self.module)
     }
 
@@ -105,6 +157,7 @@ impl<'ctx> CodeGen<'ctx> {
         let entry = self.context.append_basic_block(main_fn, "entry");
         self.builder.position_at_end(entry);
         self.current_fn = Some(main_fn);
+        self.attach_debug_subprogram(main_fn, "main", 1);
 
         // Register native clock() function
         self.register_native_clock()?;
@@ -244,6 +297,7 @@ impl<'ctx> CodeGen<'ctx> {
     }
 
     fn compile_var_decl(&mut self, var_decl: &VarDecl) -> anyhow::Result<()> {
+        self.set_debug_location(var_decl.span.offset);
         let value = match &var_decl.initializer {
             Some(expr) => self.compile_expr(expr)?,
             None => self.lox_value.build_nil(&self.builder),
@@ -254,7 +308,7 @@ impl<'ctx> CodeGen<'ctx> {
             self.emit_global_set(&var_decl.name, value);
         } else {
             // Check if this variable is captured by an inner function
-            let is_captured = self.captures.captured_vars.contains(&CapturedVar {
+            let is_captured = self.captures.captured_vars().contains(&CapturedVar {
                 var_name: var_decl.name.clone(),
                 declaring_function: self.current_lox_fn.clone(),
             });
@@ -287,6 +341,7 @@ impl<'ctx> CodeGen<'ctx> {
     }
 
     fn compile_stmt(&mut self, stmt: &Stmt) -> anyhow::Result<()> {
+        self.set_debug_location(stmt.span().offset);
         match stmt {
             Stmt::Print(print_stmt) => self.compile_print_stmt(print_stmt),
             Stmt::Expression(expr_stmt) => self.compile_expr_stmt(expr_stmt),
@@ -298,9 +353,20 @@ impl<'ctx> CodeGen<'ctx> {
     }
 
     fn compile_print_stmt(&mut self, stmt: &PrintStmt) -> anyhow::Result<()> {
-        let value = self.compile_expr(&stmt.expression)?;
+        // Evaluate every argument before printing any, matching the other backends.
+        let values = stmt
+            .expressions
+            .iter()
+            .map(|expr| self.compile_expr(expr))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (last, rest) = values.split_last().expect("print has at least one value");
+        for value in rest {
+            self.builder
+                .build_call(self.runtime.lox_print_part, &[(*value).into()], "")
+                .expect("call lox_print_part");
+        }
         self.builder
-            .build_call(self.runtime.lox_print, &[value.into()], "")
+            .build_call(self.runtime.lox_print, &[(*last).into()], "")
             .expect("call lox_print");
         Ok(())
     }
@@ -326,7 +392,7 @@ impl<'ctx> CodeGen<'ctx> {
         // Determine which variables this function captures from enclosing scopes
         let captured_names = self
             .captures
-            .function_captures
+            .function_captures()
             .get(fn_name)
             .cloned()
             .unwrap_or_default();
@@ -352,6 +418,9 @@ impl<'ctx> CodeGen<'ctx> {
         // Set up for compiling the function body
         self.current_fn = Some(llvm_fn);
         self.current_lox_fn = fn_name.clone();
+        let fn_line = self.line_from_offset(function.span.offset);
+        self.attach_debug_subprogram(llvm_fn, &llvm_fn_name, fn_line);
+        self.set_debug_location(function.span.offset);
 
         let entry_bb = self.context.append_basic_block(llvm_fn, "entry");
         let exit_bb = self.context.append_basic_block(llvm_fn, "exit");
@@ -406,7 +475,7 @@ impl<'ctx> CodeGen<'ctx> {
                 .into_struct_value();
 
             // Check if this parameter is captured
-            let is_captured = self.captures.captured_vars.contains(&CapturedVar {
+            let is_captured = self.captures.captured_vars().contains(&CapturedVar {
                 var_name: param_name.clone(),
                 declaring_function: fn_name.clone(),
             });
@@ -472,6 +541,7 @@ impl<'ctx> CodeGen<'ctx> {
         if let Some(bb) = saved_insert_block {
             self.builder.position_at_end(bb);
         }
+        self.set_debug_location(fun_decl.span.offset);
 
         // Build the closure struct and store as a function value
         // Collect cell pointers for the environment
@@ -482,7 +552,7 @@ impl<'ctx> CodeGen<'ctx> {
             self.emit_global_set(fn_name, closure_val);
         } else {
             // Check if function name is captured
-            let is_captured = self.captures.captured_vars.contains(&CapturedVar {
+            let is_captured = self.captures.captured_vars().contains(&CapturedVar {
                 var_name: fn_name.clone(),
                 declaring_function: self.current_lox_fn.clone(),
             });
@@ -634,7 +704,7 @@ impl<'ctx> CodeGen<'ctx> {
         // Determine captured variables from enclosing scopes (excluding this/super)
         let captured_names = self
             .captures
-            .function_captures
+            .function_captures()
             .get(method_name)
             .cloned()
             .unwrap_or_default();
@@ -664,6 +734,9 @@ impl<'ctx> CodeGen<'ctx> {
         // Set up for method body
         self.current_fn = Some(llvm_fn);
         self.current_lox_fn = method_name.clone();
+        let method_line = self.line_from_offset(method.span.offset);
+        self.attach_debug_subprogram(llvm_fn, &llvm_fn_name, method_line);
+        self.set_debug_location(method.span.offset);
 
         let entry_bb = self.context.append_basic_block(llvm_fn, "entry");
         let exit_bb = self.context.append_basic_block(llvm_fn, "exit");
@@ -761,7 +834,7 @@ impl<'ctx> CodeGen<'ctx> {
                 .expect("parameter exists")
                 .into_struct_value();
 
-            let is_captured = self.captures.captured_vars.contains(&CapturedVar {
+            let is_captured = self.captures.captured_vars().contains(&CapturedVar {
                 var_name: param_name.clone(),
                 declaring_function: method_name.clone(),
             });
@@ -849,6 +922,7 @@ impl<'ctx> CodeGen<'ctx> {
         if let Some(bb) = saved_insert_block {
             self.builder.position_at_end(bb);
         }
+        self.set_debug_location(method.span.offset);
 
         // Build the method closure with env: [this_cell, super_cell?, captures...]
         // For "this": placeholder null cell (filled by lox_bind_method at call time)
@@ -1586,15 +1660,51 @@ impl<'ctx> CodeGen<'ctx> {
             BinaryOp::Subtract => self.compile_numeric_binop(left, right, "sub", line),
             BinaryOp::Multiply => self.compile_numeric_binop(left, right, "mul", line),
             BinaryOp::Divide => self.compile_numeric_binop(left, right, "div", line),
+            BinaryOp::Modulo => self.compile_numeric_binop(left, right, "rem", line),
+            BinaryOp::Power => self.compile_numeric_binop(left, right, "pow", line),
             BinaryOp::Less => self.compile_comparison(left, right, "lt", line),
             BinaryOp::LessEqual => self.compile_comparison(left, right, "le", line),
             BinaryOp::Greater => self.compile_comparison(left, right, "gt", line),
             BinaryOp::GreaterEqual => self.compile_comparison(left, right, "ge", line),
             BinaryOp::Equal => self.compile_equality(left, right, false),
             BinaryOp::NotEqual => self.compile_equality(left, right, true),
+            BinaryOp::Is => Ok(self.compile_is(left, right, line)),
         }
     }
 
+    /// `value is klass`: the class operand is type-checked here, and the
+    /// runtime walks the instance's superclass chain.
+    fn compile_is(
+        &mut self,
+        value: StructValue<'ctx>,
+        klass: StructValue<'ctx>,
+        line: u32,
+    ) -> StructValue<'ctx> {
+        let tag = self.lox_value.extract_tag(&self.builder, klass);
+        let class_tag = self
+            .context
+            .i8_type()
+            .const_int(u64::from(super::types::TAG_CLASS), false);
+        let is_class = self
+            .builder
+            .build_int_compare(inkwell::IntPredicate::EQ, tag, class_tag, "is_class")
+            .expect("check right operand is class");
+        self.emit_type_check(is_class, "right operand of 'is' must be a class", line);
+
+        let result = self
+            .builder
+            .build_call(
+                self.runtime.lox_instance_of,
+                &[value.into(), klass.into()],
+                "instance_of",
+            )
+            .expect("call lox_instance_of")
+            .try_as_basic_value()
+            .unwrap_basic()
+            .into_int_value();
+        self.lox_value.build_bool_from_i1(&self.builder, result)
+    }
+
     fn compile_add(
         &mut self,
         left: StructValue<'ctx>,
@@ -1740,6 +1850,22 @@ impl<'ctx> CodeGen<'ctx> {
                 .builder
                 .build_float_div(lhs, rhs, "div")
                 .expect("float div"),
+            "rem" => self
+                .builder
+                .build_float_rem(lhs, rhs, "rem")
+                .expect("float rem"),
+            "pow" => {
+                let pow = Intrinsic::find("llvm.pow")
+                    .expect("llvm.pow intrinsic exists")
+                    .get_declaration(&self.module, &[self.context.f64_type().into()])
+                    .expect("declare llvm.pow.f64");
+                self.builder
+                    .build_call(pow, &[lhs.into(), rhs.into()], "pow")
+                    .expect("call llvm.pow")
+                    .try_as_basic_value()
+                    .unwrap_basic()
+                    .into_float_value()
+            }
             _ => unreachable!("unknown numeric binop: {op_name}"),
         };
 
@@ -1924,7 +2050,6 @@ impl<'ctx> CodeGen<'ctx> {
         let current_fn = self.current_fn.expect("must be inside a function");
 
         let left = self.compile_expr(&logical.left)?;
-        let left_truthy = self.emit_truthy(left);
 
         let rhs_bb = self.context.append_basic_block(current_fn, "log_rhs");
         let merge_bb = self.context.append_basic_block(current_fn, "log_merge");
@@ -1935,16 +2060,33 @@ impl<'ctx> CodeGen<'ctx> {
         match logical.operator {
             LogicalOp::And => {
                 // Short-circuit: if left is falsy, skip right and use left
+                let left_truthy = self.emit_truthy(left);
                 self.builder
                     .build_conditional_branch(left_truthy, rhs_bb, merge_bb)
                     .expect("and short-circuit branch");
             }
             LogicalOp::Or => {
                 // Short-circuit: if left is truthy, skip right and use left
+                let left_truthy = self.emit_truthy(left);
                 self.builder
                     .build_conditional_branch(left_truthy, merge_bb, rhs_bb)
                     .expect("or short-circuit branch");
             }
+            LogicalOp::Coalesce => {
+                // Short-circuit: only a nil left evaluates the right
+                let tag = self.lox_value.extract_tag(&self.builder, left);
+                let nil_tag = self
+                    .context
+                    .i8_type()
+                    .const_int(u64::from(super::types::TAG_NIL), false);
+                let is_nil = self
+                    .builder
+                    .build_int_compare(inkwell::IntPredicate::EQ, tag, nil_tag, "is_nil")
+                    .expect("check left is nil");
+                self.builder
+                    .build_conditional_branch(is_nil, rhs_bb, merge_bb)
+                    .expect("coalesce short-circuit branch");
+            }
         }
 
         // Evaluate right operand
@@ -2163,14 +2305,56 @@ impl<'ctx> CodeGen<'ctx> {
 
     // --- Runtime error emission ---
 
+    /// Attach a DWARF subprogram for `function` when debug info is enabled.
+    fn attach_debug_subprogram(&self, function: FunctionValue<'ctx>, name: &str, line: u32) {
+        let Some((debug_builder, compile_unit)) = &self.debug_info else {
+            return;
+        };
+        let file = compile_unit.get_file();
+        let subroutine_type =
+            debug_builder.create_subroutine_type(file, None, &[], DIFlags::PUBLIC);
+        let subprogram = debug_builder.create_function(
+            compile_unit.as_debug_info_scope(),
+            name,
+            None,
+            file,
+            line,
+            subroutine_type,
+            true,
+            true,
+            line,
+            DIFlags::PUBLIC,
+            false,
+        );
+        function.set_subprogram(subprogram);
+    }
+
+    /// Tag subsequently built instructions with the source line at `offset`.
+    ///
+    /// No-op unless debug info is enabled and the current function has a
+    /// subprogram attached.
+    fn set_debug_location(&self, offset: usize) {
+        let Some((debug_builder, _)) = &self.debug_info else {
+            return;
+        };
+        let Some(subprogram) = self.current_fn.and_then(|f| f.get_subprogram()) else {
+            self.builder.unset_current_debug_location();
+            return;
+        };
+        let line = self.line_from_offset(offset);
+        let location = debug_builder.create_debug_location(
+            self.context,
+            line,
+            0,
+            subprogram.as_debug_info_scope(),
+            None,
+        );
+        self.builder.set_current_debug_location(location);
+    }
+
     /// Convert a byte offset in the source to a 1-based line number.
     fn line_from_offset(&self, offset: usize) -> u32 {
-        let clamped = offset.min(self.source.len());
-        self.source[..clamped]
-            .chars()
-            .filter(|&c| c == '\n')
-            .count() as u32
-            + 1
+        u32::try_from(crate::error::offset_to_line(&self.source, offset)).unwrap_or(u32::MAX)
     }
 
     /// Emit a call to `lox_runtime_error(message, message_len, line)` which
@@ -2291,6 +2475,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn comparison_less_equal() {
+        let ir = compile_to_ir("var a = 1; var b = 2; print a <= b;");
+        assert!(
+            ir.contains("fcmp ole"),
+            "should contain ordered less-or-equal"
+        );
+    }
+
+    #[test]
+    fn comparison_greater_equal() {
+        let ir = compile_to_ir("var a = 2; var b = 1; print a >= b;");
+        assert!(
+            ir.contains("fcmp oge"),
+            "should contain ordered greater-or-equal"
+        );
+    }
+
+    #[test]
+    fn arithmetic_modulo() {
+        let ir = compile_to_ir("var a = 7; var b = 3; print a % b;");
+        assert!(ir.contains("frem"), "should contain float remainder");
+    }
+
+    #[test]
+    fn arithmetic_power() {
+        let ir = compile_to_ir("var a = 2; var b = 10; print a ** b;");
+        assert!(
+            ir.contains("@llvm.pow.f64"),
+            "should call the pow intrinsic"
+        );
+    }
+
+    #[test]
+    fn is_operator() {
+        let ir = compile_to_ir("class A {} var a = A(); print a is A;");
+        assert!(
+            ir.contains("lox_instance_of"),
+            "should call lox_instance_of"
+        );
+        assert!(
+            ir.contains("is_class"),
+            "should type-check the class operand"
+        );
+    }
+
     #[test]
     fn equality() {
         let ir = compile_to_ir("var a = 1; var b = 1; print a == b;");
@@ -2404,6 +2634,13 @@ mod tests {
         assert!(ir.contains("log_merge"), "should have merge block for or");
     }
 
+    #[test]
+    fn logical_coalesce() {
+        let ir = compile_to_ir("var a = nil; var b = 1; print a ?? b;");
+        assert!(ir.contains("is_nil"), "should test the left tag for nil");
+        assert!(ir.contains("log_rhs"), "should have rhs block for ??");
+    }
+
     #[test]
     fn nested_if() {
         let ir = compile_to_ir(
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use inkwell::OptimizationLevel;
+use inkwell::context::Context;
+use inkwell::targets::{InitializationConfig, Target};
+
+use crate::ast::Program;
+
+/// Declares the C runtime functions linked into this binary (see `build.rs`)
+/// and lists their addresses so the JIT can resolve the module's `lox_*`
+/// declarations to them. The signatures here are placeholders: the functions
+/// are only ever called from JIT-compiled code, never from Rust.
+macro_rules! runtime_symbols {
+    ($($name:ident),* $(,)?) => {
+        unsafe extern "C" {
+            $(fn $name();)*
+        }
+
+        /// `(symbol, address)` for every runtime function JIT code may call.
+        fn runtime_symbols() -> Vec<(&'static str, usize)> {
+            vec![$((stringify!($name), $name as *const () as usize)),*]
+        }
+    };
+}
+
+runtime_symbols!(
+    lox_print,
+    lox_print_part,
+    lox_global_get,
+    lox_global_set,
+    lox_value_truthy,
+    lox_runtime_error,
+    lox_alloc_closure,
+    lox_alloc_cell,
+    lox_cell_get,
+    lox_cell_set,
+    lox_string_concat,
+    lox_string_equal,
+    lox_alloc_class,
+    lox_class_add_method,
+    lox_alloc_instance,
+    lox_instance_get_property,
+    lox_instance_set_field,
+    lox_class_find_method,
+    lox_bind_method,
+    lox_clock,
+    lox_read_line,
+    lox_to_number,
+    lox_instance_of,
+);
+
+unsafe extern "C" {
+    /// libc `fflush`; passing null flushes every open output stream.
+    fn fflush(stream: *mut std::ffi::c_void) -> i32;
+}
+
+/// Compile a Lox program to LLVM IR and run it in-process with LLVM's JIT.
+///
+/// Returns the exit code from the generated `main`. Runtime errors inside
+/// the program terminate the process, exactly as in a native executable.
+pub fn jit_run(program: &Program, source: &str) -> Result<i32> {
+    Target::initialize_native(&InitializationConfig::default())
+        .map_err(|msg| anyhow::anyhow!("initialize native target: {msg}"))?;
+
+    let context = Context::create();
+    let module = super::compile_to_module(&context, program, source)?;
+    let engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .map_err(|msg| anyhow::anyhow!("create JIT execution engine: {msg}"))?;
+
+    for (name, address) in runtime_symbols() {
+        if let Some(function) = module.get_function(name) {
+            engine.add_global_mapping(&function, address);
+        }
+    }
+
+    // SAFETY: `main` is emitted by `CodeGen::emit_main` as `i32 main()`.
+    let main = unsafe { engine.get_function::<unsafe extern "C" fn() -> i32>("main") }
+        .map_err(|e| anyhow::anyhow!("look up JIT-compiled main: {e}"))?;
+    // SAFETY: every external symbol the module references is mapped above.
+    let exit_code = unsafe { main.call() };
+
+    // The runtime prints through C stdio, whose buffer Rust doesn't flush.
+    // SAFETY: fflush(NULL) is always valid.
+    unsafe { fflush(std::ptr::null_mut()) };
+
+    Ok(exit_code)
+}
@@ -1,5 +1,7 @@
+pub mod annotate;
 pub mod capture;
 pub mod compiler;
+pub mod jit;
 pub mod native;
 pub mod runtime;
 pub mod types;
@@ -9,10 +11,28 @@ use std::collections::HashMap;
 use anyhow::Result;
 use inkwell::context::Context;
 use inkwell::module::Module;
+use inkwell::passes::PassBuilderOptions;
 
 use crate::ast::{ExprId, Program};
 use crate::interpreter::resolver::Resolver;
 
+pub use jit::jit_run;
+
+/// Settings applied when lowering a program to LLVM IR.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Optimization level passed to [`optimize`] (0-3).
+    pub opt_level: u8,
+    /// Source filename to emit DWARF line tables for; `None` disables debug info.
+    pub debug_info: Option<String>,
+    /// Interleave `; line N: <source>` comments into the textual IR. The
+    /// comments come from the line tables, so this turns on debug info too.
+    pub annotate: bool,
+    /// Target triple the module will be emitted for, so optimization uses
+    /// that target's cost model; `None` means the host.
+    pub target: Option<String>,
+}
+
 /// Compile a Lox AST to an LLVM Module for further processing.
 ///
 /// Runs the resolver and capture analysis, then generates LLVM IR.
@@ -20,20 +40,75 @@ pub fn compile_to_module<'ctx>(
     context: &'ctx Context,
     program: &Program,
     source: &str,
+) -> Result<Module<'ctx>> {
+    compile_to_module_with_options(context, program, source, &CompileOptions::default())
+}
+
+/// Like [`compile_to_module`], but applies `options` (debug info, then optimization).
+pub fn compile_to_module_with_options<'ctx>(
+    context: &'ctx Context,
+    program: &Program,
+    source: &str,
+    options: &CompileOptions,
 ) -> Result<Module<'ctx>> {
     let locals = resolve(program)?;
     let captures = capture::analyze_captures(program);
-    let codegen = compiler::CodeGen::new(context, "lox", locals, captures, source);
-    codegen.emit(program)
+    let mut codegen = compiler::CodeGen::new(context, "lox", locals, captures, source);
+    if let Some(filename) = &options.debug_info {
+        codegen.enable_debug_info(filename);
+    }
+    let module = codegen.emit(program)?;
+    optimize(&module, options.opt_level, options.target.as_deref())?;
+    Ok(module)
 }
 
 /// Compile a Lox AST to LLVM IR and return the IR as a string.
 ///
 /// Runs the resolver and capture analysis, then generates LLVM IR.
 pub fn compile(program: &Program, source: &str) -> Result<String> {
+    compile_with_options(program, source, &CompileOptions::default())
+}
+
+/// Like [`compile`], but applies `options` before printing.
+pub fn compile_with_options(
+    program: &Program,
+    source: &str,
+    options: &CompileOptions,
+) -> Result<String> {
     let context = Context::create();
-    let module = compile_to_module(&context, program, source)?;
-    Ok(module.print_to_string().to_string())
+    let mut options = options.clone();
+    if options.annotate {
+        options
+            .debug_info
+            .get_or_insert_with(|| "<input>".to_string());
+    }
+    let module = compile_to_module_with_options(&context, program, source, &options)?;
+    let ir = module.print_to_string().to_string();
+    if options.annotate {
+        Ok(annotate::annotate_ir(&ir, source))
+    } else {
+        Ok(ir)
+    }
+}
+
+/// Run LLVM's standard `default<ON>` pass pipeline over the module.
+///
+/// Level 0 leaves the module untouched; levels 1-3 include mem2reg, which
+/// promotes the codegen's per-variable allocas to SSA registers. `target`
+/// is the triple the module will be emitted for, or `None` for the host.
+pub fn optimize(module: &Module, opt_level: u8, target: Option<&str>) -> Result<()> {
+    if opt_level == 0 {
+        return Ok(());
+    }
+    anyhow::ensure!(opt_level <= 3, "invalid optimization level {opt_level}");
+    let machine = native::create_target_machine(target)?;
+    module
+        .run_passes(
+            &format!("default<O{opt_level}>"),
+            &machine,
+            PassBuilderOptions::create(),
+        )
+        .map_err(|msg| anyhow::anyhow!("run optimization passes: {msg}"))
 }
 
 fn resolve(program: &Program) -> Result<HashMap<ExprId, usize>> {
@@ -42,3 +117,108 @@ fn resolve(program: &Program) -> Result<HashMap<ExprId, usize>> {
         .resolve(program)
         .map_err(|errors| anyhow::anyhow!("resolution errors: {:?}", errors))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner;
+
+    fn parse(source: &str) -> Program {
+        let tokens = scanner::scan(source).expect("scan succeeds");
+        Parser::new(tokens).parse().expect("parse succeeds")
+    }
+
+    fn compile_at(source: &str, opt_level: u8) -> String {
+        let options = CompileOptions {
+            opt_level,
+            ..CompileOptions::default()
+        };
+        compile_with_options(&parse(source), source, &options).expect("compile succeeds")
+    }
+
+    #[test]
+    fn optimization_removes_allocas() {
+        let source = "fun f(a) { var b = a + 1; var c = b * 2; return c; } print f(1);";
+        let count = |ir: &str| ir.matches(" alloca ").count();
+        let unoptimized = count(&compile_at(source, 0));
+        let optimized = count(&compile_at(source, 2));
+        assert!(
+            optimized < unoptimized,
+            "expected fewer allocas at -O2 ({optimized}) than -O0 ({unoptimized})"
+        );
+    }
+
+    #[test]
+    fn optimization_uses_the_requested_target() {
+        let triple = inkwell::targets::TargetMachine::get_default_triple();
+        let options = CompileOptions {
+            opt_level: 2,
+            target: Some(
+                triple
+                    .as_str()
+                    .to_str()
+                    .expect("triple is UTF-8")
+                    .to_string(),
+            ),
+            ..CompileOptions::default()
+        };
+        let source = "var a = 1; print a + 2;";
+        compile_with_options(&parse(source), source, &options).expect("compile succeeds");
+
+        let options = CompileOptions {
+            opt_level: 2,
+            target: Some("not-a-real-target".to_string()),
+            ..CompileOptions::default()
+        };
+        assert!(compile_with_options(&parse(source), source, &options).is_err());
+    }
+
+    #[test]
+    fn invalid_opt_level_is_rejected() {
+        let options = CompileOptions {
+            opt_level: 4,
+            ..CompileOptions::default()
+        };
+        assert!(compile_with_options(&parse("print 1;"), "print 1;", &options).is_err());
+    }
+
+    #[test]
+    fn debug_info_emits_compile_unit_and_subprograms() {
+        let source = "fun add(a, b) {\n  return a + b;\n}\nprint add(1, 2);\n";
+        let options = CompileOptions {
+            debug_info: Some("examples/add.lox".to_string()),
+            ..CompileOptions::default()
+        };
+        let ir = compile_with_options(&parse(source), source, &options).expect("compile succeeds");
+        assert!(ir.contains("!llvm.dbg.cu"), "missing compile unit:\n{ir}");
+        assert!(ir.contains("filename: \"add.lox\""), "missing file:\n{ir}");
+        assert!(
+            ir.contains("DISubprogram(name: \"lox_fn_add\""),
+            "missing subprogram:\n{ir}"
+        );
+        assert!(
+            ir.contains("!DILocation(line: 2"),
+            "missing line info:\n{ir}"
+        );
+    }
+
+    #[test]
+    fn annotate_adds_source_line_comments() {
+        let source = "var a = 1;\nprint a;\n";
+        let options = CompileOptions {
+            annotate: true,
+            ..CompileOptions::default()
+        };
+        let ir = compile_with_options(&parse(source), source, &options).expect("compile succeeds");
+        assert!(ir.contains("; line 1: var a = 1;"), "missing line 1:\n{ir}");
+        assert!(ir.contains("; line 2: print a;"), "missing line 2:\n{ir}");
+    }
+
+    #[test]
+    fn debug_info_is_off_by_default() {
+        let source = "print 1;";
+        let ir = compile(&parse(source), source).expect("compile succeeds");
+        assert!(!ir.contains("!llvm.dbg.cu"));
+    }
+}
@@ -5,16 +5,21 @@ use anyhow::{Context, Result, bail};
 use inkwell::OptimizationLevel;
 use inkwell::module::Module;
 use inkwell::targets::{
-    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine,
+    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple,
 };
 
 /// Compile an LLVM module to a native ELF executable.
 ///
 /// Emits an object file from the module, links it with the Lox C runtime,
-/// and produces a self-contained executable.
-pub fn compile_to_executable(module: &Module, output_path: &Path) -> Result<()> {
+/// and produces a self-contained executable. `target` overrides the host
+/// target triple.
+pub fn compile_to_executable(
+    module: &Module,
+    output_path: &Path,
+    target: Option<&str>,
+) -> Result<()> {
     let obj_path = output_path.with_extension("o");
-    emit_object_file(module, &obj_path)?;
+    compile_to_object(module, &obj_path, target)?;
 
     let link_result = link_executable(&obj_path, output_path);
 
@@ -24,30 +29,12 @@ pub fn compile_to_executable(module: &Module, output_path: &Path) -> Result<()>
     link_result
 }
 
-/// Emit an object file from an LLVM module using the host target.
-fn emit_object_file(module: &Module, obj_path: &Path) -> Result<()> {
-    Target::initialize_native(&InitializationConfig::default())
-        .map_err(|msg| anyhow::anyhow!("initialize native target: {msg}"))?;
-
-    let triple = TargetMachine::get_default_triple();
-    let target = Target::from_triple(&triple)
-        .map_err(|msg| anyhow::anyhow!("get target from triple: {msg}"))?;
-
-    let cpu = TargetMachine::get_host_cpu_name();
-    let features = TargetMachine::get_host_cpu_features();
-
-    let machine = target
-        .create_target_machine(
-            &triple,
-            cpu.to_str().expect("host CPU name is valid UTF-8"),
-            features
-                .to_str()
-                .expect("host CPU features are valid UTF-8"),
-            OptimizationLevel::Default,
-            RelocMode::PIC,
-            CodeModel::Default,
-        )
-        .ok_or_else(|| anyhow::anyhow!("create target machine for {}", triple))?;
+/// Compile an LLVM module to a relocatable object file (`.o`) without linking.
+///
+/// `target` overrides the host target triple, e.g. `aarch64-unknown-linux-gnu`.
+pub fn compile_to_object(module: &Module, obj_path: &Path, target: Option<&str>) -> Result<()> {
+    let machine = create_target_machine(target)?;
+    let triple = machine.get_triple();
 
     module.set_triple(&triple);
     module.set_data_layout(&machine.get_target_data().get_data_layout());
@@ -60,6 +47,54 @@ fn emit_object_file(module: &Module, obj_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Create a `TargetMachine` for `triple`, or for the host when `None`.
+///
+/// The host machine is tuned for the local CPU; an explicit triple uses the
+/// target's generic CPU since the host's features may not apply to it.
+pub(crate) fn create_target_machine(triple: Option<&str>) -> Result<TargetMachine> {
+    let config = InitializationConfig::default();
+    let (triple, cpu, features) = match triple {
+        None => {
+            Target::initialize_native(&config)
+                .map_err(|msg| anyhow::anyhow!("LLVM native target is not available: {msg}"))?;
+            let cpu = TargetMachine::get_host_cpu_name();
+            let features = TargetMachine::get_host_cpu_features();
+            (
+                TargetMachine::get_default_triple(),
+                cpu.to_str()
+                    .expect("host CPU name is valid UTF-8")
+                    .to_string(),
+                features
+                    .to_str()
+                    .expect("host CPU features are valid UTF-8")
+                    .to_string(),
+            )
+        }
+        Some(triple) => {
+            Target::initialize_all(&config);
+            (
+                TargetTriple::create(triple),
+                "generic".to_string(),
+                String::new(),
+            )
+        }
+    };
+
+    let target = Target::from_triple(&triple)
+        .map_err(|msg| anyhow::anyhow!("get target from triple {triple}: {msg}"))?;
+
+    target
+        .create_target_machine(
+            &triple,
+            &cpu,
+            &features,
+            OptimizationLevel::Default,
+            RelocMode::PIC,
+            CodeModel::Default,
+        )
+        .ok_or_else(|| anyhow::anyhow!("create target machine for {}", triple))
+}
+
 /// Link an object file with the Lox runtime to produce an executable.
 fn link_executable(obj_path: &Path, output_path: &Path) -> Result<()> {
     let cc = std::env::var("CC").unwrap_or_else(|_| "gcc".to_string());
@@ -9,6 +9,7 @@ use super::types::LoxValueType;
 /// These correspond to functions implemented in `runtime/lox_runtime.c`.
 pub struct RuntimeDecls<'ctx> {
     pub lox_print: FunctionValue<'ctx>,
+    pub lox_print_part: FunctionValue<'ctx>,
     pub lox_global_get: FunctionValue<'ctx>,
     pub lox_global_set: FunctionValue<'ctx>,
     pub lox_value_truthy: FunctionValue<'ctx>,
@@ -29,6 +30,7 @@ pub struct RuntimeDecls<'ctx> {
     pub lox_clock: FunctionValue<'ctx>,
     pub lox_read_line: FunctionValue<'ctx>,
     pub lox_to_number: FunctionValue<'ctx>,
+    pub lox_instance_of: FunctionValue<'ctx>,
 }
 
 impl<'ctx> RuntimeDecls<'ctx> {
@@ -45,6 +47,9 @@ impl<'ctx> RuntimeDecls<'ctx> {
         let lox_print_ty = void_type.fn_type(&[lv_type.into()], false);
         let lox_print = module.add_function("lox_print", lox_print_ty, None);
 
+        // void lox_print_part(LoxValue value)
+        let lox_print_part = module.add_function("lox_print_part", lox_print_ty, None);
+
         // LoxValue lox_global_get(i8* name, i64 name_len)
         let lox_global_get_ty = lv_type.fn_type(&[ptr_type.into(), i64_type.into()], false);
         let lox_global_get = module.add_function("lox_global_get", lox_global_get_ty, None);
@@ -159,8 +164,13 @@ impl<'ctx> RuntimeDecls<'ctx> {
         let lox_to_number_ty = lv_type.fn_type(&[lv_type.into()], false);
         let lox_to_number = module.add_function("lox_to_number", lox_to_number_ty, None);
 
+        // i1 lox_instance_of(LoxValue value, LoxValue klass)
+        let lox_instance_of_ty = i1_type.fn_type(&[lv_type.into(), lv_type.into()], false);
+        let lox_instance_of = module.add_function("lox_instance_of", lox_instance_of_ty, None);
+
         Self {
             lox_print,
+            lox_print_part,
             lox_global_get,
             lox_global_set,
             lox_value_truthy,
@@ -181,6 +191,7 @@ impl<'ctx> RuntimeDecls<'ctx> {
             lox_clock,
             lox_read_line,
             lox_to_number,
+            lox_instance_of,
         }
     }
 }
@@ -10,30 +10,50 @@ use crate::scanner::token::Span;
 
 #[derive(Error, Debug, Diagnostic)]
 pub enum CompileError {
-    #[error("scan error: {message}")]
+    #[error("scan error{}: {message}", format_location(.location))]
     #[diagnostic(code(lox::scan))]
     Scan {
         message: String,
+        /// Line and column of `span`, known once source code is attached.
+        location: Option<LineColumn>,
         #[label("here")]
         span: SourceSpan,
         #[source_code]
         src: miette::NamedSource<String>,
     },
 
-    #[error("parse error: {message}")]
+    #[error("parse error{}: {message}", format_location(.location))]
     #[diagnostic(code(lox::parse))]
     Parse {
         message: String,
+        /// Line and column of `span`, known once source code is attached.
+        location: Option<LineColumn>,
         #[label("here")]
         span: SourceSpan,
         #[source_code]
         src: miette::NamedSource<String>,
     },
 
-    #[error("resolution error: {message}")]
+    #[error("resolution error{}: {message}", format_location(.location))]
     #[diagnostic(code(lox::resolve))]
     Resolve {
         message: String,
+        /// Line and column of `span`, known once source code is attached.
+        location: Option<LineColumn>,
+        #[label("here")]
+        span: SourceSpan,
+        #[source_code]
+        src: miette::NamedSource<String>,
+    },
+
+    /// A construct that parsed and resolved but can't be encoded in bytecode,
+    /// e.g. a jump whose offset doesn't fit in its 16-bit operand.
+    #[error("compile error{}: {message}", format_location(.location))]
+    #[diagnostic(code(lox::compile))]
+    Compile {
+        message: String,
+        /// Line and column of `span`, known once source code is attached.
+        location: Option<LineColumn>,
         #[label("here")]
         span: SourceSpan,
         #[source_code]
@@ -45,6 +65,7 @@ impl CompileError {
     pub fn scan(message: impl Into<String>, offset: usize, len: usize) -> Self {
         Self::Scan {
             message: message.into(),
+            location: None,
             span: SourceSpan::new(offset.into(), len),
             src: miette::NamedSource::new("input", String::new()),
         }
@@ -53,6 +74,7 @@ impl CompileError {
     pub fn parse(message: impl Into<String>, offset: usize, len: usize) -> Self {
         Self::Parse {
             message: message.into(),
+            location: None,
             span: SourceSpan::new(offset.into(), len),
             src: miette::NamedSource::new("input", String::new()),
         }
@@ -61,37 +83,221 @@ impl CompileError {
     pub fn resolve(message: impl Into<String>, offset: usize, len: usize) -> Self {
         Self::Resolve {
             message: message.into(),
+            location: None,
             span: SourceSpan::new(offset.into(), len),
             src: miette::NamedSource::new("input", String::new()),
         }
     }
 
-    /// Attach source code for fancy miette diagnostics
+    pub fn compile(message: impl Into<String>, offset: usize, len: usize) -> Self {
+        Self::Compile {
+            message: message.into(),
+            location: None,
+            span: SourceSpan::new(offset.into(), len),
+            src: miette::NamedSource::new("input", String::new()),
+        }
+    }
+
+    /// Attach source code for fancy miette diagnostics and a `line:column`
+    /// position in the error message.
     pub fn with_source_code(self, name: impl Into<String>, source: impl Into<String>) -> Self {
-        let name_str = name.into();
         let source_str = source.into();
+        let location = Some(line_column(&source_str, self.span().offset()));
+        let src = named_source(name, source_str);
         match self {
             Self::Scan { message, span, .. } => Self::Scan {
                 message,
+                location,
                 span,
-                src: miette::NamedSource::new(name_str, source_str),
+                src,
             },
             Self::Parse { message, span, .. } => Self::Parse {
                 message,
+                location,
                 span,
-                src: miette::NamedSource::new(name_str, source_str),
+                src,
             },
             Self::Resolve { message, span, .. } => Self::Resolve {
                 message,
+                location,
+                span,
+                src,
+            },
+            Self::Compile { message, span, .. } => Self::Compile {
+                message,
+                location,
                 span,
-                src: miette::NamedSource::new(name_str, source_str),
+                src,
             },
         }
     }
+
+    /// The labelled source span of this error.
+    pub fn span(&self) -> SourceSpan {
+        match self {
+            Self::Scan { span, .. }
+            | Self::Parse { span, .. }
+            | Self::Resolve { span, .. }
+            | Self::Compile { span, .. } => *span,
+        }
+    }
+}
+
+/// Source code to attach to a diagnostic. miette only draws a label at the
+/// very end of the source if the last line is terminated, e.g. "expected
+/// ';'" on a final `print x`, so a missing final newline is added.
+fn named_source(name: impl Into<String>, mut source: String) -> miette::NamedSource<String> {
+    if !source.ends_with('\n') {
+        source.push('\n');
+    }
+    miette::NamedSource::new(name.into(), source)
+}
+
+/// A compile-time problem that doesn't stop the program from running, such
+/// as unreachable code. Reported like a [`CompileError`], at warning level.
+#[derive(Error, Debug, Diagnostic)]
+#[error("warning{}: {message}", format_location(.location))]
+#[diagnostic(code(lox::warning), severity(Warning))]
+pub struct CompileWarning {
+    message: String,
+    /// Line and column of `span`, known once source code is attached.
+    location: Option<LineColumn>,
+    #[label("here")]
+    span: SourceSpan,
+    #[source_code]
+    src: miette::NamedSource<String>,
+}
+
+impl CompileWarning {
+    pub fn new(message: impl Into<String>, offset: usize, len: usize) -> Self {
+        Self {
+            message: message.into(),
+            location: None,
+            span: SourceSpan::new(offset.into(), len),
+            src: miette::NamedSource::new("input", String::new()),
+        }
+    }
+
+    /// Attach source code for fancy miette diagnostics and a `line:column`
+    /// position in the message.
+    pub fn with_source_code(self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        let source = source.into();
+        Self {
+            location: Some(line_column(&source, self.span.offset())),
+            src: named_source(name, source),
+            ..self
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The labelled source span of this warning.
+    pub fn span(&self) -> SourceSpan {
+        self.span
+    }
+
+    /// The same problem as a resolution error, for when warnings are denied.
+    pub fn into_error(self) -> CompileError {
+        CompileError::resolve(self.message, self.span.offset(), self.span.len())
+    }
+}
+
+/// A 1-based line and column (counted in characters, with tabs expanded to
+/// the next tab stop) within source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineColumn {
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Tab stop width used for columns when `LOX_TAB_WIDTH` is not set. Matches
+/// miette's default, so reported columns agree with where the caret is drawn.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Tab stop width for diagnostics: `LOX_TAB_WIDTH=N` (N >= 1), otherwise
+/// [`DEFAULT_TAB_WIDTH`].
+pub fn tab_width() -> usize {
+    resolve_tab_width(std::env::var("LOX_TAB_WIDTH").ok().as_deref())
+}
+
+fn resolve_tab_width(explicit: Option<&str>) -> usize {
+    explicit
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&width| width > 0)
+        .unwrap_or(DEFAULT_TAB_WIDTH)
+}
+
+/// Convert a byte offset into a 1-based line and column against `source`,
+/// using the configured [`tab_width`].
+pub fn line_column(source: &str, offset: usize) -> LineColumn {
+    line_column_with_tab_width(source, offset, tab_width())
+}
+
+/// Like [`line_column`], with an explicit tab stop width.
+pub fn line_column_with_tab_width(source: &str, offset: usize, tab_width: usize) -> LineColumn {
+    let mut end = offset.min(source.len());
+    while !source.is_char_boundary(end) {
+        end -= 1;
+    }
+    let before = &source[..end];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let tab_width = tab_width.max(1);
+    let width = before[line_start..].chars().fold(0, |width, c| {
+        if c == '\t' {
+            (width / tab_width + 1) * tab_width
+        } else {
+            width + 1
+        }
+    });
+    LineColumn {
+        line: before.matches('\n').count() as u32 + 1,
+        column: width as u32 + 1,
+    }
+}
+
+fn format_location(location: &Option<LineColumn>) -> String {
+    location
+        .map(|loc| format!(" at {}:{}", loc.line, loc.column))
+        .unwrap_or_default()
 }
 
 // ============= Runtime errors (simple, no miette) =============
 
+/// Failure from evaluating source text end to end: it either didn't compile
+/// or raised an error while running.
+#[derive(Error, Debug)]
+pub enum EvalError {
+    #[error("{}", format_compile_errors(.0))]
+    Compile(Vec<CompileError>),
+
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
+}
+
+impl From<Vec<CompileError>> for EvalError {
+    fn from(errors: Vec<CompileError>) -> Self {
+        Self::Compile(errors)
+    }
+}
+
+fn format_compile_errors(errors: &[CompileError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Default limit on nested Lox calls before a "stack overflow" runtime error.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Build the runtime error reported when the call depth limit is exceeded.
+pub fn stack_overflow_message(max_depth: usize) -> String {
+    format!("stack overflow (max call depth {max_depth} exceeded)")
+}
+
 /// A single frame in the Lox call stack, captured at the point of a runtime error.
 #[derive(Debug, Clone)]
 pub struct StackFrame {
@@ -133,6 +339,23 @@ impl RuntimeError {
         }
     }
 
+    /// Locate this error at `span` unless it already has a location, e.g. to
+    /// report an error raised inside a native function at its call site.
+    pub fn or_span(self, span: Span) -> Self {
+        match self {
+            Self::Error {
+                message,
+                span: None,
+                backtrace,
+            } => Self::Error {
+                message,
+                span: Some(span),
+                backtrace,
+            },
+            other => other,
+        }
+    }
+
     /// Attach a call-stack backtrace to this error.
     pub fn with_backtrace(self, frames: Vec<StackFrame>) -> Self {
         match self {
@@ -201,21 +424,53 @@ impl RuntimeError {
     }
 }
 
+/// Number of frames printed when `LOX_BACKTRACE=1` and no explicit limit is set.
+pub const DEFAULT_BACKTRACE_LIMIT: usize = 32;
+
 /// Format the backtrace portion for display. Returns empty string if no frames.
-pub fn format_backtrace(frames: &[StackFrame]) -> String {
+///
+/// At most `limit` frames (innermost first) are printed; the rest are
+/// summarized as `... N more frames`. `None` prints every frame.
+pub fn format_backtrace(frames: &[StackFrame], limit: Option<usize>) -> String {
     if frames.is_empty() {
         return String::new();
     }
+    let shown = limit.map_or(frames.len(), |limit| limit.min(frames.len()));
     let mut out = String::from("stack backtrace:\n");
-    for (i, frame) in frames.iter().enumerate() {
+    for (i, frame) in frames[..shown].iter().enumerate() {
         out.push_str(&format!(
             "  {}: {}()\t\t[line {}]\n",
             i, frame.function_name, frame.line
         ));
     }
+    let hidden = frames.len() - shown;
+    if hidden > 0 {
+        let noun = if hidden == 1 { "frame" } else { "frames" };
+        out.push_str(&format!("  ... {hidden} more {noun}\n"));
+    }
     out
 }
 
+/// Maximum number of backtrace frames to print.
+///
+/// `LOX_BACKTRACE_LIMIT=N` sets the cap explicitly (0 means unlimited);
+/// otherwise `LOX_BACKTRACE=full` prints every frame and anything else uses
+/// [`DEFAULT_BACKTRACE_LIMIT`].
+pub fn backtrace_limit() -> Option<usize> {
+    let explicit = std::env::var("LOX_BACKTRACE_LIMIT").ok();
+    let full = std::env::var("LOX_BACKTRACE").is_ok_and(|v| v == "full");
+    resolve_backtrace_limit(explicit.as_deref(), full)
+}
+
+fn resolve_backtrace_limit(explicit: Option<&str>, full: bool) -> Option<usize> {
+    match explicit.and_then(|v| v.trim().parse::<usize>().ok()) {
+        Some(0) => None,
+        Some(n) => Some(n),
+        None if full => None,
+        None => Some(DEFAULT_BACKTRACE_LIMIT),
+    }
+}
+
 /// Returns true if the user has opted into backtraces via LOX_BACKTRACE env var.
 pub fn backtrace_enabled() -> bool {
     matches!(
@@ -224,13 +479,11 @@ pub fn backtrace_enabled() -> bool {
     )
 }
 
-/// Calculate line number from byte offset in source
-fn offset_to_line(source: &str, offset: usize) -> usize {
-    source[..offset.min(source.len())]
-        .chars()
-        .filter(|&c| c == '\n')
-        .count()
-        + 1
+/// 1-based line number of byte `offset` in `source`. An offset past the end
+/// counts as the last line, and one inside a multibyte character as that
+/// character's line, so any span offset is safe to pass.
+pub fn offset_to_line(source: &str, offset: usize) -> usize {
+    line_column(source, offset).line as usize
 }
 
 // ============= Tests =============
@@ -238,6 +491,7 @@ fn offset_to_line(source: &str, offset: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
 
     #[test]
     fn compile_error_implements_diagnostic() {
@@ -246,6 +500,71 @@ mod tests {
         assert!(diag.code().is_some());
     }
 
+    #[test]
+    fn scan_error_on_second_line_reports_column() {
+        let source = "var a = 1;\nvar b = @;\n";
+        let errors = crate::scanner::scan(source).expect_err("'@' is not a valid token");
+        let error = errors
+            .into_iter()
+            .next()
+            .expect("at least one scan error")
+            .with_source_code("test.lox", source);
+        let message = error.to_string();
+        assert!(message.starts_with("scan error at 2:9: "), "got: {message}");
+    }
+
+    #[test]
+    fn compile_error_without_source_has_no_location() {
+        let err = CompileError::parse("expected ';'", 5, 1);
+        assert_eq!(err.to_string(), "parse error: expected ';'");
+    }
+
+    #[rstest]
+    #[case("abc", 0, 1, 1)]
+    #[case("abc", 2, 1, 3)]
+    #[case("a\nbc", 2, 2, 1)]
+    #[case("a\nbc", 3, 2, 2)]
+    #[case("é\nxé y", 6, 2, 3)]
+    #[case("abc", 99, 1, 4)]
+    fn line_column_cases(
+        #[case] source: &str,
+        #[case] offset: usize,
+        #[case] line: u32,
+        #[case] column: u32,
+    ) {
+        assert_eq!(line_column(source, offset), LineColumn { line, column });
+    }
+
+    #[rstest]
+    #[case::one_tab_default_width("\tx", 1, 4, 5)]
+    #[case::one_tab_width_one("\tx", 1, 1, 2)]
+    #[case::one_tab_width_eight("\tx", 1, 8, 9)]
+    #[case::tab_after_text("ab\tx", 3, 4, 5)]
+    #[case::tab_on_a_stop("abcd\tx", 5, 4, 9)]
+    #[case::two_tabs("\t\tx", 2, 4, 9)]
+    #[case::second_line("a\n\tvar x = @;", 11, 4, 13)]
+    fn line_column_expands_tabs(
+        #[case] source: &str,
+        #[case] offset: usize,
+        #[case] tab_width: usize,
+        #[case] column: u32,
+    ) {
+        assert_eq!(
+            line_column_with_tab_width(source, offset, tab_width).column,
+            column
+        );
+    }
+
+    #[rstest]
+    #[case(None, DEFAULT_TAB_WIDTH)]
+    #[case(Some("8"), 8)]
+    #[case(Some("1"), 1)]
+    #[case(Some("0"), DEFAULT_TAB_WIDTH)]
+    #[case(Some("wide"), DEFAULT_TAB_WIDTH)]
+    fn tab_width_resolution(#[case] explicit: Option<&str>, #[case] expected: usize) {
+        assert_eq!(resolve_tab_width(explicit), expected);
+    }
+
     #[test]
     fn compile_error_with_source() {
         let err =
@@ -253,6 +572,18 @@ mod tests {
         assert!(matches!(err, CompileError::Parse { .. }));
     }
 
+    #[test]
+    fn error_at_end_of_unterminated_last_line_renders_a_label() {
+        let source = "print 1";
+        let err = CompileError::parse("expected ';'", source.len(), 0)
+            .with_source_code("test.lox", source);
+        let mut rendered = String::new();
+        miette::GraphicalReportHandler::new_themed(miette::GraphicalTheme::unicode_nocolor())
+            .render_report(&mut rendered, &err)
+            .expect("render to a string");
+        assert!(rendered.contains("here"), "{rendered}");
+    }
+
     #[test]
     fn compile_error_all_variants() {
         let _scan = CompileError::scan("test", 0, 1);
@@ -330,6 +661,13 @@ mod tests {
         assert_eq!(offset_to_line(source, 100), 1); // Past end, still line 1
     }
 
+    #[test]
+    fn offset_to_line_inside_multibyte_char() {
+        let source = "\"é\"\n\"ü\"";
+        assert_eq!(offset_to_line(source, 2), 1); // Between the bytes of 'é'
+        assert_eq!(offset_to_line(source, 7), 2); // Between the bytes of 'ü'
+    }
+
     #[test]
     fn runtime_error_with_backtrace() {
         let err = RuntimeError::new("operand must be a number").with_backtrace(vec![
@@ -375,7 +713,7 @@ mod tests {
                 line: 13,
             },
         ];
-        let output = format_backtrace(&frames);
+        let output = format_backtrace(&frames, None);
         assert!(output.starts_with("stack backtrace:\n"));
         assert!(output.contains("0: inner()"));
         assert!(output.contains("[line 6]"));
@@ -385,6 +723,51 @@ mod tests {
 
     #[test]
     fn format_backtrace_empty_returns_empty_string() {
-        assert_eq!(format_backtrace(&[]), "");
+        assert_eq!(format_backtrace(&[], None), "");
+    }
+
+    fn deep_frames(count: usize) -> Vec<StackFrame> {
+        (0..count)
+            .map(|i| StackFrame {
+                function_name: "recurse".to_string(),
+                line: i + 1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn format_backtrace_truncates_to_limit() {
+        let output = format_backtrace(&deep_frames(1000), Some(5));
+        assert_eq!(output.lines().count(), 1 + 5 + 1);
+        assert!(output.contains("4: recurse()"));
+        assert!(!output.contains("5: recurse()"));
+        assert!(output.ends_with("  ... 995 more frames\n"), "got: {output}");
+    }
+
+    #[test]
+    fn format_backtrace_singular_more_frame() {
+        let output = format_backtrace(&deep_frames(3), Some(2));
+        assert!(output.ends_with("  ... 1 more frame\n"), "got: {output}");
+    }
+
+    #[test]
+    fn format_backtrace_within_limit_has_no_summary() {
+        let output = format_backtrace(&deep_frames(3), Some(3));
+        assert!(!output.contains("more frame"));
+    }
+
+    #[rstest]
+    #[case(None, false, Some(DEFAULT_BACKTRACE_LIMIT))]
+    #[case(None, true, None)]
+    #[case(Some("10"), false, Some(10))]
+    #[case(Some("10"), true, Some(10))]
+    #[case(Some("0"), false, None)]
+    #[case(Some("lots"), false, Some(DEFAULT_BACKTRACE_LIMIT))]
+    fn backtrace_limit_resolution(
+        #[case] explicit: Option<&str>,
+        #[case] full: bool,
+        #[case] expected: Option<usize>,
+    ) {
+        assert_eq!(resolve_backtrace_limit(explicit, full), expected);
     }
 }
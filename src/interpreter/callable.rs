@@ -3,8 +3,10 @@ use std::fmt;
 use std::rc::Rc;
 
 use crate::ast::Function;
+use crate::error::RuntimeError;
 use crate::interpreter::environment::Environment;
 use crate::interpreter::value::{LoxInstance, Value};
+use crate::stdlib::format_lox_function;
 
 /// Represents something callable in Lox.
 #[derive(Debug, Clone)]
@@ -17,14 +19,27 @@ impl Callable {
     pub fn name(&self) -> &str {
         match self {
             Self::Native(n) => n.name(),
-            Self::User(u) => &u.declaration.name,
+            Self::User(u) => u.name(),
         }
     }
 
     pub fn arity(&self) -> usize {
         match self {
             Self::Native(n) => n.arity(),
-            Self::User(u) => u.declaration.params.len(),
+            Self::User(u) => u.arity(),
+        }
+    }
+
+    /// Reference identity: the same native, or the same declaration closed
+    /// over the same environment. Each `bind` creates a fresh environment,
+    /// so two lookups of a method are never identical.
+    pub fn is_same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Native(a), Self::Native(b)) => a == b,
+            (Self::User(a), Self::User(b)) => {
+                Rc::ptr_eq(&a.closure, &b.closure) && a.declaration.span == b.declaration.span
+            }
+            _ => false,
         }
     }
 
@@ -49,7 +64,7 @@ impl Callable {
 
 impl fmt::Display for Callable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<fn {}>", self.name())
+        write!(f, "{}", format_lox_function(self.name(), self.arity()))
     }
 }
 
@@ -61,33 +76,118 @@ pub struct LoxFunction {
     pub is_initializer: bool,
 }
 
+impl LoxFunction {
+    pub fn name(&self) -> &str {
+        &self.declaration.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// Parameter names, in declaration order.
+    pub fn params(&self) -> &[String] {
+        &self.declaration.params
+    }
+}
+
 /// Native function types.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NativeFunction {
     Clock,
     ReadLine,
     ToNumber,
+    Eprint,
+    TimeMillis,
+    Strlen,
+    Substr,
+    Upper,
+    Lower,
+    IndexOf,
+    Chr,
+    Ord,
+    Type,
+    Env,
+    Write,
+    Writeln,
 }
 
 impl NativeFunction {
+    /// Every native, in the order they are registered as globals.
+    pub const ALL: [NativeFunction; 16] = [
+        Self::Clock,
+        Self::ReadLine,
+        Self::ToNumber,
+        Self::Eprint,
+        Self::TimeMillis,
+        Self::Strlen,
+        Self::Substr,
+        Self::Upper,
+        Self::Lower,
+        Self::IndexOf,
+        Self::Chr,
+        Self::Ord,
+        Self::Type,
+        Self::Env,
+        Self::Write,
+        Self::Writeln,
+    ];
+
     pub fn name(&self) -> &str {
         match self {
             Self::Clock => "clock",
             Self::ReadLine => "readLine",
             Self::ToNumber => "toNumber",
+            Self::Eprint => "eprint",
+            Self::TimeMillis => "time_millis",
+            Self::Strlen => "strlen",
+            Self::Substr => "substr",
+            Self::Upper => "upper",
+            Self::Lower => "lower",
+            Self::IndexOf => "indexOf",
+            Self::Chr => "chr",
+            Self::Ord => "ord",
+            Self::Type => "type",
+            Self::Env => "env",
+            Self::Write => "write",
+            Self::Writeln => "writeln",
         }
     }
 
+    /// Whether the native observes or affects anything outside the program,
+    /// and so is unavailable to a sandboxed interpreter.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Self::Clock | Self::ReadLine | Self::Eprint | Self::TimeMillis | Self::Env
+        )
+    }
+
     pub fn arity(&self) -> usize {
         match self {
             Self::Clock => 0,
             Self::ReadLine => 0,
             Self::ToNumber => 1,
+            Self::Eprint => 1,
+            Self::TimeMillis => 0,
+            Self::Strlen
+            | Self::Upper
+            | Self::Lower
+            | Self::Chr
+            | Self::Ord
+            | Self::Type
+            | Self::Env
+            | Self::Write
+            | Self::Writeln => 1,
+            Self::IndexOf => 2,
+            Self::Substr => 3,
         }
     }
 
-    pub fn call(&self, _args: &[Value]) -> Value {
-        match self {
+    /// Invoke the native. Errors carry no span; the caller locates them at
+    /// the call site.
+    pub fn call(&self, args: &[Value]) -> Result<Value, RuntimeError> {
+        Ok(match self {
             Self::Clock => {
                 use std::time::{SystemTime, UNIX_EPOCH};
                 let secs = SystemTime::now()
@@ -100,7 +200,7 @@ impl NativeFunction {
                 Some(s) => Value::Str(s),
                 None => Value::Nil,
             },
-            Self::ToNumber => match &_args[0] {
+            Self::ToNumber => match &args[0] {
                 Value::Number(n) => Value::Number(*n),
                 Value::Str(s) => match crate::stdlib::parse_lox_number(s) {
                     Some(n) => Value::Number(n),
@@ -108,6 +208,69 @@ impl NativeFunction {
                 },
                 _ => Value::Nil,
             },
-        }
+            Self::Eprint => {
+                // Goes straight to stderr, bypassing the interpreter's print capture
+                eprintln!("{}", args[0]);
+                Value::Nil
+            }
+            Self::TimeMillis => Value::Number(crate::stdlib::time_millis()),
+            // String natives return nil when given arguments of the wrong type
+            Self::Strlen => match &args[0] {
+                Value::Str(s) => Value::Number(s.chars().count() as f64),
+                _ => Value::Nil,
+            },
+            Self::Substr => match (&args[0], &args[1], &args[2]) {
+                (Value::Str(s), Value::Number(start), Value::Number(end)) => Value::Str(
+                    crate::stdlib::substr_chars(s, *start, *end).map_err(RuntimeError::new)?,
+                ),
+                _ => Value::Nil,
+            },
+            Self::Upper => match &args[0] {
+                Value::Str(s) => Value::Str(s.to_uppercase()),
+                _ => Value::Nil,
+            },
+            Self::Lower => match &args[0] {
+                Value::Str(s) => Value::Str(s.to_lowercase()),
+                _ => Value::Nil,
+            },
+            Self::IndexOf => match (&args[0], &args[1]) {
+                (Value::Str(s), Value::Str(needle)) => {
+                    Value::Number(crate::stdlib::index_of_chars(s, needle))
+                }
+                _ => Value::Nil,
+            },
+            Self::Chr => match &args[0] {
+                Value::Number(n) => Value::Str(crate::stdlib::chr(*n).map_err(RuntimeError::new)?),
+                other => {
+                    return Err(RuntimeError::new(format!(
+                        "chr() argument must be a number, got {other}"
+                    )));
+                }
+            },
+            Self::Ord => match &args[0] {
+                Value::Str(s) => Value::Number(crate::stdlib::ord(s).map_err(RuntimeError::new)?),
+                other => {
+                    return Err(RuntimeError::new(format!(
+                        "ord() argument must be a string, got {other}"
+                    )));
+                }
+            },
+            Self::Type => Value::Str(args[0].type_name().to_string()),
+            Self::Env => match &args[0] {
+                Value::Str(name) => crate::stdlib::env_var(name).map_or(Value::Nil, Value::Str),
+                other => {
+                    return Err(RuntimeError::new(format!(
+                        "env() argument must be a string, got {other}"
+                    )));
+                }
+            },
+            // The interpreter handles these itself, since they need its writer.
+            Self::Write | Self::Writeln => {
+                return Err(RuntimeError::new(format!(
+                    "{}() can only be called from a running program",
+                    self.name()
+                )));
+            }
+        })
     }
 }
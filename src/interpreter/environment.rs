@@ -4,9 +4,14 @@ use std::rc::Rc;
 
 use crate::interpreter::value::Value;
 
+/// One scope's variables. Each name gets a slot in `values` the first time
+/// it is defined; redefining reuses the slot and nothing is ever removed, so
+/// a slot found once stays valid for the life of the environment (see
+/// [`Environment::slot`]).
 #[derive(Debug)]
 pub struct Environment {
-    values: HashMap<String, Value>,
+    slots: HashMap<String, usize>,
+    values: Vec<Value>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -19,25 +24,33 @@ impl Default for Environment {
 impl Environment {
     pub fn new() -> Self {
         Self {
-            values: HashMap::new(),
+            slots: HashMap::new(),
+            values: Vec::new(),
             enclosing: None,
         }
     }
 
     pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
         Self {
-            values: HashMap::new(),
+            slots: HashMap::new(),
+            values: Vec::new(),
             enclosing: Some(enclosing),
         }
     }
 
     pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+        match self.slots.get(&name) {
+            Some(&slot) => self.values[slot] = value,
+            None => {
+                self.slots.insert(name, self.values.len());
+                self.values.push(value);
+            }
+        }
     }
 
     pub fn get(&self, name: &str) -> Option<Value> {
-        if let Some(val) = self.values.get(name) {
-            return Some(val.clone());
+        if let Some(&slot) = self.slots.get(name) {
+            return Some(self.values[slot].clone());
         }
         if let Some(ref enclosing) = self.enclosing {
             return enclosing.borrow().get(name);
@@ -45,9 +58,17 @@ impl Environment {
         None
     }
 
+    /// Names defined directly in this environment (not its enclosing ones),
+    /// sorted so listings are stable.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.slots.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
     pub fn get_at(&self, distance: usize, name: &str) -> Option<Value> {
         if distance == 0 {
-            self.values.get(name).cloned()
+            self.slots.get(name).map(|&slot| self.values[slot].clone())
         } else {
             self.enclosing
                 .as_ref()
@@ -57,9 +78,25 @@ impl Environment {
         }
     }
 
+    /// Slot of `name` in this environment (not its enclosing ones), for
+    /// reading and writing it later without hashing the name again.
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    /// Value in a slot returned by [`Environment::slot`].
+    pub fn get_slot(&self, slot: usize) -> Value {
+        self.values[slot].clone()
+    }
+
+    /// Overwrite a slot returned by [`Environment::slot`].
+    pub fn assign_slot(&mut self, slot: usize, value: Value) {
+        self.values[slot] = value;
+    }
+
     pub fn assign(&mut self, name: &str, value: Value) -> bool {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_string(), value);
+        if let Some(&slot) = self.slots.get(name) {
+            self.values[slot] = value;
             return true;
         }
         if let Some(ref enclosing) = self.enclosing {
@@ -70,7 +107,7 @@ impl Environment {
 
     pub fn assign_at(&mut self, distance: usize, name: &str, value: Value) {
         if distance == 0 {
-            self.values.insert(name.to_string(), value);
+            self.define(name.to_string(), value);
         } else {
             self.enclosing
                 .as_ref()
@@ -122,6 +159,29 @@ mod tests {
         assert!(!env.assign("x", Value::Number(1.0)));
     }
 
+    #[test]
+    fn names_lists_only_this_level() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        outer.borrow_mut().define("hidden".to_string(), Value::Nil);
+        let mut env = Environment::with_enclosing(outer);
+        env.define("b".to_string(), Value::Number(2.0));
+        env.define("a".to_string(), Value::Number(1.0));
+        assert_eq!(env.names(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn slot_survives_redefinition_and_later_definitions() {
+        let mut env = Environment::new();
+        env.define("x".to_string(), Value::Number(1.0));
+        let slot = env.slot("x").expect("x is defined");
+        env.define("y".to_string(), Value::Number(2.0));
+        env.define("x".to_string(), Value::Number(3.0));
+        assert_eq!(env.slot("x"), Some(slot));
+        assert!(matches!(env.get_slot(slot), Value::Number(n) if n == 3.0));
+        env.assign_slot(slot, Value::Number(4.0));
+        assert!(matches!(env.get("x"), Some(Value::Number(n)) if n == 4.0));
+    }
+
     #[test]
     fn get_at_depth() {
         let outer = Rc::new(RefCell::new(Environment::new()));
@@ -3,28 +3,92 @@ pub mod environment;
 pub mod resolver;
 pub mod value;
 
+pub use value::Value;
+
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::rc::Rc;
 
 use crate::ast::*;
-use crate::error::{RuntimeError, StackFrame};
+use crate::error::{
+    CompileError, DEFAULT_MAX_CALL_DEPTH, EvalError, RuntimeError, StackFrame,
+    stack_overflow_message,
+};
 use crate::interpreter::callable::{Callable, LoxFunction, NativeFunction};
 use crate::interpreter::environment::Environment;
-use crate::interpreter::value::{LoxClass, LoxInstance, Value};
+use crate::interpreter::resolver::Resolver;
+use crate::interpreter::value::{LoxClass, LoxInstance};
+use crate::parser::Parser;
+use crate::scanner::token::Token;
+
+/// Native stack size for threads running the tree-walk interpreter. Each Lox
+/// call nests several Rust frames, so the default main-thread stack runs out
+/// well before [`DEFAULT_MAX_CALL_DEPTH`] in debug builds.
+pub const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Run `f` on a thread with [`INTERPRETER_STACK_SIZE`] bytes of stack, so deep
+/// Lox recursion reaches the call depth limit instead of overflowing.
+pub fn with_interpreter_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("spawn interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Receives the values a program outputs, for embedders that want to render
+/// them (numbers, strings, instances, ...) themselves instead of reading
+/// formatted text. See [`Interpreter::set_sink`].
+pub trait OutputSink {
+    /// Called once per `print` statement with all of its values, so
+    /// `print a, b;` is one call with two values and `print a; print b;` is
+    /// two calls with one each.
+    fn print(&mut self, values: &[Value]);
+
+    /// Called by `write(value)` and `writeln(value)`; `newline` is set for
+    /// `writeln`.
+    fn write(&mut self, value: &Value, newline: bool);
+}
 
 pub struct Interpreter {
     globals: Rc<RefCell<Environment>>,
     environment: Rc<RefCell<Environment>>,
     locals: HashMap<ExprId, usize>,
+    /// Slot in `globals` of each global variable read or assignment already
+    /// evaluated once, indexed by expression id. Globals are never removed,
+    /// so a slot stays valid.
+    global_slots: Vec<Option<usize>>,
     output: Vec<String>,
+    /// Whether the last line in `output` was started by `write()` and is
+    /// still waiting for its newline.
+    line_open: bool,
     /// Writer for print output (allows testing without stdout)
     writer: Box<dyn Write>,
+    /// Replaces the writer and `output` for `print` statements when set.
+    sink: Option<Box<dyn OutputSink>>,
     /// Tracks the active call stack for backtrace on runtime errors.
     call_stack: Vec<StackFrame>,
     /// Source code, retained for computing line numbers in backtraces.
     source: String,
+    /// Maximum number of nested user function calls before a stack overflow error.
+    max_call_depth: usize,
+    /// Lowest `ExprId` not yet present in `locals`; later parses start here
+    /// so their resolved locals can't collide with earlier ones.
+    next_expr_id: ExprId,
+    /// `const` globals declared by earlier input, kept for resolving later
+    /// input (for REPL line-by-line resolution).
+    global_consts: HashSet<String>,
+    /// Whether `env()` may read the process environment.
+    allow_env: bool,
+    /// Whether natives with side effects are disabled; see [`Interpreter::sandboxed`].
+    sandboxed: bool,
+    /// Whether reading an undefined global is an error (the default) or `nil`.
+    strict_globals: bool,
 }
 
 impl Default for Interpreter {
@@ -35,53 +99,76 @@ impl Default for Interpreter {
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        for native in [
-            NativeFunction::Clock,
-            NativeFunction::ReadLine,
-            NativeFunction::ToNumber,
-        ] {
-            globals.borrow_mut().define(
-                native.name().to_string(),
-                Value::Function(Callable::Native(native)),
-            );
-        }
+        Self::with_sandbox(false)
+    }
 
+    /// Create an interpreter for untrusted code. Natives that touch the
+    /// outside world (the clock, stdin, stderr, the environment) stay defined
+    /// but raise a runtime error when called, so scripts are deterministic.
+    pub fn sandboxed() -> Self {
+        Self::with_sandbox(true)
+    }
+
+    fn with_sandbox(sandboxed: bool) -> Self {
+        let globals = Self::fresh_globals();
         Self {
             globals: Rc::clone(&globals),
             environment: globals,
             locals: HashMap::new(),
+            global_slots: Vec::new(),
             output: Vec::new(),
+            line_open: false,
             writer: Box::new(std::io::stdout()),
+            sink: None,
             call_stack: Vec::new(),
             source: String::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            next_expr_id: 0,
+            global_consts: HashSet::new(),
+            allow_env: true,
+            sandboxed,
+            strict_globals: true,
         }
     }
 
-    /// Create an interpreter that captures output (for testing).
-    #[cfg(test)]
-    fn new_capturing() -> Self {
+    /// A global scope holding only the natives.
+    fn fresh_globals() -> Rc<RefCell<Environment>> {
         let globals = Rc::new(RefCell::new(Environment::new()));
-        for native in [
-            NativeFunction::Clock,
-            NativeFunction::ReadLine,
-            NativeFunction::ToNumber,
-        ] {
+        for native in NativeFunction::ALL {
             globals.borrow_mut().define(
                 native.name().to_string(),
                 Value::Function(Callable::Native(native)),
             );
         }
+        globals
+    }
 
-        Self {
-            globals: Rc::clone(&globals),
-            environment: globals,
-            locals: HashMap::new(),
-            output: Vec::new(),
-            writer: Box::new(Vec::<u8>::new()),
-            call_stack: Vec::new(),
-            source: String::new(),
-        }
+    /// Forget everything earlier programs left behind: globals (the natives
+    /// are defined again) and which of them are `const`, resolved locals, the
+    /// expression-id counter, the call stack, captured output and the stored
+    /// source. Settings such as the writer, sink, call depth
+    /// limit and sandboxing are kept, so an embedder can run independent
+    /// programs through one interpreter.
+    pub fn reset(&mut self) {
+        let globals = Self::fresh_globals();
+        self.globals = Rc::clone(&globals);
+        self.environment = globals;
+        self.locals.clear();
+        self.global_slots.clear();
+        self.output.clear();
+        self.line_open = false;
+        self.call_stack.clear();
+        self.source.clear();
+        self.next_expr_id = 0;
+        self.global_consts.clear();
+    }
+
+    /// Create an interpreter that captures output (for testing).
+    #[cfg(test)]
+    fn new_capturing() -> Self {
+        let mut interp = Self::new();
+        interp.writer = Box::new(Vec::<u8>::new());
+        interp
     }
 
     /// Set the source code for line-number computation in backtraces.
@@ -89,12 +176,41 @@ impl Interpreter {
         self.source = source.to_string();
     }
 
+    /// Set the maximum call depth (default [`DEFAULT_MAX_CALL_DEPTH`]).
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Choose whether reading an undefined global raises a runtime error
+    /// (`true`, the default) or evaluates to `nil`. Assigning to an undefined
+    /// global is an error either way.
+    pub fn set_strict_globals(&mut self, strict: bool) {
+        self.strict_globals = strict;
+    }
+
+    /// Send the values of `print` statements and `write()`/`writeln()` calls
+    /// to `sink` rather than formatting them to the writer and
+    /// [`Interpreter::output`]. The sink gets values as they are, so a
+    /// class's `str` method is not called.
+    pub fn set_sink(&mut self, sink: impl OutputSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Allow or forbid `env()` (allowed by default). When forbidden, calling
+    /// it raises a runtime error.
+    pub fn set_allow_env(&mut self, allow: bool) {
+        self.allow_env = allow;
+    }
+
     pub fn interpret(
         &mut self,
         program: &Program,
         locals: HashMap<ExprId, usize>,
     ) -> Result<(), RuntimeError> {
         self.locals = locals;
+        // A new program may reuse expression ids from the last one.
+        self.global_slots.clear();
+        self.reserve_expr_ids();
         for decl in &program.declarations {
             self.execute_decl(decl)?;
         }
@@ -113,6 +229,34 @@ impl Interpreter {
     /// Merge additional locals (for REPL line-by-line resolution).
     pub fn merge_locals(&mut self, locals: HashMap<ExprId, usize>) {
         self.locals.extend(locals);
+        self.reserve_expr_ids();
+    }
+
+    /// Resolve `program` as a continuation of this session and merge its
+    /// locals in. `const` globals declared by earlier input stay constant
+    /// (for REPL line-by-line resolution).
+    pub fn resolve_additional(&mut self, program: &Program) -> Result<(), Vec<CompileError>> {
+        let (locals, consts) = Resolver::new()
+            .with_global_consts(self.global_consts.clone())
+            .resolve_with_consts(program)?;
+        self.global_consts = consts;
+        self.merge_locals(locals);
+        Ok(())
+    }
+
+    /// Parse tokens for this session, numbering expressions after every id
+    /// already known to the interpreter (for REPL line-by-line parsing).
+    pub fn parse_additional(&mut self, tokens: Vec<Token>) -> Result<Program, Vec<CompileError>> {
+        let mut parser = Parser::new(tokens).with_first_id(self.next_expr_id);
+        let program = parser.parse();
+        self.next_expr_id = parser.next_expr_id();
+        program
+    }
+
+    fn reserve_expr_ids(&mut self) {
+        if let Some(&max) = self.locals.keys().max() {
+            self.next_expr_id = self.next_expr_id.max(max + 1);
+        }
     }
 
     /// Execute additional declarations without resetting the environment (for REPL).
@@ -123,6 +267,48 @@ impl Interpreter {
         Ok(())
     }
 
+    /// Evaluate a single expression in the current environment (for REPL echo).
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.evaluate_expr(expr)
+    }
+
+    /// Scan, parse, resolve and evaluate `source` as a single expression in
+    /// the current environment, returning its value.
+    ///
+    /// Statements and declarations (including a trailing `;`) are rejected
+    /// with a compile error; use [`Interpreter::interpret`] to run those.
+    pub fn eval_expression(&mut self, source: &str) -> Result<Value, EvalError> {
+        let tokens = crate::scanner::scan(source)?;
+        let mut parser = Parser::new(tokens).with_first_id(self.next_expr_id);
+        let expression = parser.parse_expression()?;
+        self.next_expr_id = parser.next_expr_id();
+        // The resolver works on whole programs, so wrap the expression in one.
+        let program = Program {
+            declarations: vec![Decl::Statement(Stmt::Expression(ExprStmt {
+                expression: expression.clone(),
+                span: expression.span(),
+            }))],
+        };
+        self.resolve_additional(&program)?;
+        Ok(self.evaluate_expr(&expression)?)
+    }
+
+    /// Scan, parse, resolve and run `source` as a whole program.
+    ///
+    /// Like a REPL line, the program runs on top of earlier calls: functions,
+    /// globals and `const`s they defined stay usable. Backtrace line numbers
+    /// are computed against the latest `source`, as in the REPL. Compile and
+    /// runtime errors come back as one [`EvalError`], the same type
+    /// [`crate::vm::interpret_vm`] returns.
+    pub fn run_source(&mut self, source: &str) -> Result<(), EvalError> {
+        let tokens = crate::scanner::scan(source)?;
+        let program = self.parse_additional(tokens)?;
+        self.resolve_additional(&program)?;
+        self.set_source(source);
+        self.interpret_additional(&program)?;
+        Ok(())
+    }
+
     fn execute_decl(&mut self, decl: &Decl) -> Result<(), RuntimeError> {
         match decl {
             Decl::Var(v) => {
@@ -218,14 +404,35 @@ impl Interpreter {
                 self.evaluate_expr(&e.expression)?;
                 Ok(())
             }
+            Stmt::Print(p) if self.sink.is_some() => {
+                let mut values = Vec::with_capacity(p.expressions.len());
+                for expr in &p.expressions {
+                    values.push(self.evaluate_expr(expr)?);
+                }
+                self.sink
+                    .as_mut()
+                    .expect("checked by the match guard")
+                    .print(&values);
+                Ok(())
+            }
             Stmt::Print(p) => {
-                let value = self.evaluate_expr(&p.expression)?;
-                let text = format!("{value}");
-                writeln!(self.writer, "{text}").expect("write should succeed");
-                self.output.push(text);
+                let mut parts = Vec::with_capacity(p.expressions.len());
+                for expr in &p.expressions {
+                    let value = self.evaluate_expr(expr)?;
+                    parts.push(self.stringify(value, p.span)?);
+                }
+                self.write_output(parts.join(" "), true);
                 Ok(())
             }
             Stmt::Return(r) => {
+                // The resolver rejects this, but a program that skipped
+                // resolution could still reach it.
+                if self.call_stack.is_empty() {
+                    return Err(RuntimeError::with_span(
+                        "can't return from top-level code",
+                        r.span,
+                    ));
+                }
                 let value = match &r.value {
                     Some(val) => self.evaluate_expr(val)?,
                     None => Value::Nil,
@@ -297,13 +504,10 @@ impl Interpreter {
                         .borrow_mut()
                         .assign_at(distance, &a.name, value.clone());
                 } else {
-                    let ok = self.globals.borrow_mut().assign(&a.name, value.clone());
-                    if !ok {
-                        return Err(RuntimeError::with_span(
-                            format!("undefined variable '{}'", a.name),
-                            a.span,
-                        ));
-                    }
+                    let slot = self.global_slot(a.id, &a.name).ok_or_else(|| {
+                        RuntimeError::with_span(format!("undefined variable '{}'", a.name), a.span)
+                    })?;
+                    self.globals.borrow_mut().assign_slot(slot, value.clone());
                 }
                 Ok(value)
             }
@@ -320,6 +524,11 @@ impl Interpreter {
                             return Ok(left);
                         }
                     }
+                    LogicalOp::Coalesce => {
+                        if !matches!(left, Value::Nil) {
+                            return Ok(left);
+                        }
+                    }
                 }
                 self.evaluate_expr(&l.right)
             }
@@ -403,12 +612,24 @@ impl Interpreter {
             },
             BinaryOp::Subtract => number_binop(&left, &right, |a, c| a - c, b),
             BinaryOp::Multiply => number_binop(&left, &right, |a, c| a * c, b),
-            BinaryOp::Divide => number_binop(&left, &right, |a, c| a / c, b),
-            BinaryOp::Less => number_cmp(&left, &right, |a, c| a < c, b),
-            BinaryOp::LessEqual => number_cmp(&left, &right, |a, c| a <= c, b),
-            BinaryOp::Greater => number_cmp(&left, &right, |a, c| a > c, b),
-            BinaryOp::GreaterEqual => number_cmp(&left, &right, |a, c| a >= c, b),
+            BinaryOp::Divide => number_div(&left, &right, |a, c| a / c, b),
+            BinaryOp::Modulo => number_div(&left, &right, |a, c| a % c, b),
+            BinaryOp::Power => number_binop(&left, &right, f64::powf, b),
+            BinaryOp::Less => compare(&left, &right, Ordering::is_lt, b),
+            BinaryOp::LessEqual => compare(&left, &right, Ordering::is_le, b),
+            BinaryOp::Greater => compare(&left, &right, Ordering::is_gt, b),
+            BinaryOp::GreaterEqual => compare(&left, &right, Ordering::is_ge, b),
             BinaryOp::Equal => Ok(Value::Bool(left.is_equal(&right))),
+            BinaryOp::Is => match (&left, &right) {
+                (Value::Instance(inst), Value::Class(class)) => {
+                    Ok(Value::Bool(inst.borrow().class.is_subclass_of(class)))
+                }
+                (_, Value::Class(_)) => Ok(Value::Bool(false)),
+                _ => Err(RuntimeError::with_span(
+                    "right operand of 'is' must be a class",
+                    b.span,
+                )),
+            },
             BinaryOp::NotEqual => Ok(Value::Bool(!left.is_equal(&right))),
         }
     }
@@ -457,6 +678,48 @@ impl Interpreter {
         }
     }
 
+    /// Write `text` to the output, ending the line if `newline` is set.
+    /// Text written without a newline is joined onto the same captured
+    /// `output` line as whatever follows it.
+    fn write_output(&mut self, text: String, newline: bool) {
+        if newline {
+            writeln!(self.writer, "{text}").expect("write should succeed");
+        } else {
+            write!(self.writer, "{text}").expect("write should succeed");
+        }
+        // Flush per write so output interleaves correctly with stderr and
+        // prompts even when stdout is piped
+        self.writer.flush().expect("flush should succeed");
+        match self.output.last_mut() {
+            Some(line) if self.line_open => line.push_str(&text),
+            _ => self.output.push(text),
+        }
+        self.line_open = !newline;
+    }
+
+    /// Convert a value to its printed form. Instances whose class defines a
+    /// zero-argument `str` method are printed via that method's result.
+    fn stringify(
+        &mut self,
+        value: Value,
+        span: crate::scanner::token::Span,
+    ) -> Result<String, RuntimeError> {
+        if let Value::Instance(ref instance) = value {
+            let method = instance.borrow().class.find_method("str");
+            if let Some(method) = method.filter(|m| m.arity() == 0) {
+                let bound = method.bind(Rc::clone(instance));
+                return match self.call_function(&bound, Vec::new(), span)? {
+                    Value::Str(s) => Ok(s),
+                    other => Err(RuntimeError::with_span(
+                        format!("'str' method must return a string, got {other}"),
+                        span,
+                    )),
+                };
+            }
+        }
+        Ok(format!("{value}"))
+    }
+
     /// Snapshot the current call stack into a Vec<StackFrame> for backtrace display.
     /// Returns frames in innermost-first order (most recent call at index 0).
     fn snapshot_backtrace(&self) -> Vec<StackFrame> {
@@ -467,11 +730,7 @@ impl Interpreter {
 
     /// Compute the 1-based line number from a byte offset in the stored source.
     fn offset_to_line(&self, offset: usize) -> usize {
-        self.source[..offset.min(self.source.len())]
-            .chars()
-            .filter(|&c| c == '\n')
-            .count()
-            + 1
+        crate::error::offset_to_line(&self.source, offset)
     }
 
     fn call_function(
@@ -481,8 +740,36 @@ impl Interpreter {
         call_site_span: crate::scanner::token::Span,
     ) -> Result<Value, RuntimeError> {
         match func {
-            Callable::Native(native) => Ok(native.call(&args)),
+            Callable::Native(native) if self.sandboxed && native.has_side_effects() => {
+                Err(RuntimeError::with_span(
+                    format!("{}() is not available in sandbox mode", native.name()),
+                    call_site_span,
+                ))
+            }
+            Callable::Native(NativeFunction::Env) if !self.allow_env => {
+                Err(RuntimeError::with_span("env() is disabled", call_site_span))
+            }
+            Callable::Native(native @ (NativeFunction::Write | NativeFunction::Writeln)) => {
+                let value = args.into_iter().next().expect("arity checked");
+                let newline = *native == NativeFunction::Writeln;
+                if let Some(sink) = self.sink.as_mut() {
+                    sink.write(&value, newline);
+                } else {
+                    let text = self.stringify(value, call_site_span)?;
+                    self.write_output(text, newline);
+                }
+                Ok(Value::Nil)
+            }
+            Callable::Native(native) => native
+                .call(&args)
+                .map_err(|err| err.or_span(call_site_span)),
             Callable::User(user_fn) => {
+                if self.call_stack.len() >= self.max_call_depth {
+                    return Err(RuntimeError::with_span(
+                        stack_overflow_message(self.max_call_depth),
+                        call_site_span,
+                    ));
+                }
                 let frame = StackFrame {
                     function_name: user_fn.declaration.name.clone(),
                     line: self.offset_to_line(call_site_span.offset),
@@ -539,7 +826,7 @@ impl Interpreter {
     }
 
     fn look_up_variable(
-        &self,
+        &mut self,
         name: &str,
         id: ExprId,
         span: crate::scanner::token::Span,
@@ -551,10 +838,29 @@ impl Interpreter {
                 .get_at(distance, name)
                 .expect("resolver guarantees variable exists"))
         } else {
-            self.globals.borrow().get(name).ok_or_else(|| {
-                RuntimeError::with_span(format!("undefined variable '{name}'"), span)
-            })
+            match self.global_slot(id, name) {
+                Some(slot) => Ok(self.globals.borrow().get_slot(slot)),
+                None if !self.strict_globals => Ok(Value::Nil),
+                None => Err(RuntimeError::with_span(
+                    format!("undefined variable '{name}'"),
+                    span,
+                )),
+            }
+        }
+    }
+
+    /// Slot of global `name` as referenced by expression `id`, looked up by
+    /// name only the first time that expression runs.
+    fn global_slot(&mut self, id: ExprId, name: &str) -> Option<usize> {
+        if let Some(&Some(slot)) = self.global_slots.get(id) {
+            return Some(slot);
         }
+        let slot = self.globals.borrow().slot(name)?;
+        if self.global_slots.len() <= id {
+            self.global_slots.resize(id + 1, None);
+        }
+        self.global_slots[id] = Some(slot);
+        Some(slot)
     }
 }
 
@@ -570,15 +876,36 @@ fn number_binop(
     }
 }
 
-fn number_cmp(
+/// Like [`number_binop`], but raises "division by zero" for a zero divisor.
+fn number_div(
+    left: &Value,
+    right: &Value,
+    op: fn(f64, f64) -> f64,
+    b: &BinaryExpr,
+) -> Result<Value, RuntimeError> {
+    if let (Value::Number(_), Value::Number(divisor)) = (left, right)
+        && *divisor == 0.0
+    {
+        return Err(RuntimeError::with_span("division by zero", b.span));
+    }
+    number_binop(left, right, op, b)
+}
+
+/// Order two numbers, or two strings lexicographically. Any comparison
+/// involving NaN is false, as with IEEE 754 comparisons.
+fn compare(
     left: &Value,
     right: &Value,
-    op: fn(f64, f64) -> bool,
+    op: fn(Ordering) -> bool,
     b: &BinaryExpr,
 ) -> Result<Value, RuntimeError> {
     match (left, right) {
-        (Value::Number(a), Value::Number(c)) => Ok(Value::Bool(op(*a, *c))),
-        _ => Err(RuntimeError::with_span("operands must be numbers", b.span)),
+        (Value::Number(a), Value::Number(c)) => Ok(Value::Bool(a.partial_cmp(c).is_some_and(op))),
+        (Value::Str(a), Value::Str(c)) => Ok(Value::Bool(op(a.cmp(c)))),
+        _ => Err(RuntimeError::with_span(
+            "operands must be two numbers or two strings",
+            b.span,
+        )),
     }
 }
 
@@ -586,8 +913,10 @@ fn number_cmp(
 mod tests {
     use super::*;
     use crate::interpreter::resolver::Resolver;
+    use crate::interpreter::value::FunctionInfo;
     use crate::parser::Parser;
     use crate::scanner;
+    use crate::test_support::FlushRecorder;
     use rstest::rstest;
 
     fn run(source: &str) -> Vec<String> {
@@ -618,6 +947,7 @@ mod tests {
     #[case("print 10 - 3;", "7")]
     #[case("print 2 * 3;", "6")]
     #[case("print 10 / 4;", "2.5")]
+    #[case("print 7 % 3;", "1")]
     #[case("print -5;", "-5")]
     fn arithmetic(#[case] source: &str, #[case] expected: &str) {
         assert_eq!(run(source), vec![expected]);
@@ -728,6 +1058,31 @@ mod tests {
         assert_eq!(output, vec!["42"]);
     }
 
+    #[rstest]
+    #[case::stored_in_variable(
+        "class C { init(n) { this.n = n; } get() { return this.n; } } var m = C(1).get; print m();",
+        "1"
+    )]
+    #[case::passed_as_argument(
+        "class C { init(n) { this.n = n; } get() { return this.n; } } fun call(f) { return f(); } print call(C(2).get);",
+        "2"
+    )]
+    #[case::outlives_receiver_scope(
+        "class C { init(n) { this.n = n; } get() { return this.n; } } var m; { var c = C(3); m = c.get; } print m();",
+        "3"
+    )]
+    #[case::sees_later_field_writes(
+        "class C { get() { return this.n; } } var c = C(); var m = c.get; c.n = 4; print m();",
+        "4"
+    )]
+    #[case::inherited_method(
+        "class A { get() { return \"a\"; } } class B < A {} var m = B().get; print m();",
+        "a"
+    )]
+    fn method_reference(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
     #[test]
     fn inheritance() {
         let output = run("class Animal {
@@ -773,6 +1128,582 @@ mod tests {
         assert!(err.to_string().contains("expected 1 arguments"));
     }
 
+    #[rstest]
+    #[case("clock(5);", "expected 0 arguments but got 1")]
+    #[case("readLine(1, 2);", "expected 0 arguments but got 2")]
+    #[case("toNumber(\"1\", \"2\");", "expected 1 arguments but got 2")]
+    #[case("substr(\"abc\");", "expected 3 arguments but got 1")]
+    fn native_wrong_arity_error(#[case] source: &str, #[case] expected: &str) {
+        let err = run_err(source);
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[test]
+    fn infinite_recursion_is_stack_overflow_error() {
+        let err = with_interpreter_stack(|| run_err("fun f() { f(); } f();").to_string());
+        assert!(
+            err.contains("stack overflow (max call depth 1000 exceeded)"),
+            "got: {err}"
+        );
+    }
+
+    #[test]
+    fn bounded_deep_recursion_succeeds() {
+        let output = with_interpreter_stack(|| {
+            run(
+                "fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+                print depth(900);",
+            )
+        });
+        assert_eq!(output, vec!["900"]);
+    }
+
+    #[test]
+    fn custom_max_call_depth() {
+        let source = "fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+            print depth(10);
+            print depth(11);";
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::new_capturing();
+        interp.set_max_call_depth(11);
+        let err = interp.interpret(&program, locals).unwrap_err();
+        assert_eq!(interp.output, vec!["10"]);
+        assert!(err.to_string().contains("max call depth 11 exceeded"));
+    }
+
+    #[rstest]
+    #[case::direct_class("class A {} print A() is A;", "true")]
+    #[case::superclass("class A {} class B < A {} print B() is A;", "true")]
+    #[case::grandparent("class A {} class B < A {} class C < B {} print C() is A;", "true")]
+    #[case::subclass_of_instance("class A {} class B < A {} print A() is B;", "false")]
+    #[case::unrelated("class A {} class B {} print A() is B;", "false")]
+    #[case::non_instance("class A {} print 1 is A;", "false")]
+    #[case::class_itself("class A {} print A is A;", "false")]
+    fn is_operator(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[test]
+    fn is_operator_requires_class() {
+        let err = run_err("class A {} print A() is 1;");
+        assert!(
+            err.to_string()
+                .contains("right operand of 'is' must be a class"),
+            "got: {err}"
+        );
+    }
+
+    #[rstest]
+    #[case("print 2 ** 10;", "1024")]
+    #[case("print 2 ** 3 ** 2;", "512")]
+    #[case("print -2 ** 2;", "-4")]
+    #[case("print 2 ** -1;", "0.5")]
+    fn power(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[test]
+    fn triple_quoted_string_prints_verbatim() {
+        let output = run("print \"\"\"first \"line\"\nsecond\\n line\"\"\";");
+        assert_eq!(output, vec!["first \"line\"\nsecond\\n line"]);
+    }
+
+    #[rstest]
+    #[case("print nil ?? 5;", "5")]
+    #[case("print 0 ?? 5;", "0")]
+    #[case("print 0 or 5;", "0")]
+    #[case("print false ?? 5;", "false")]
+    #[case("print false or 5;", "5")]
+    #[case("print nil ?? nil ?? \"last\";", "last")]
+    fn nil_coalescing(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[test]
+    fn nil_coalescing_short_circuits() {
+        let output = run("fun boom() { print \"evaluated\"; return 1; } print 1 ?? boom();");
+        assert_eq!(output, vec!["1"]);
+    }
+
+    #[test]
+    fn do_while_runs_body_at_least_once() {
+        let output = run("var i = 10; do { print i; i = i + 1; } while (i < 3);");
+        assert_eq!(output, vec!["10"]);
+    }
+
+    #[test]
+    fn do_while_loops_until_condition_fails() {
+        let output = run("var i = 0; do { print i; i = i + 1; } while (i < 3);");
+        assert_eq!(output, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn switch_evaluates_subject_once() {
+        let output = run("var n = 0; \
+             fun bump() { n = n + 1; return n; } \
+             switch (bump()) { case 0: print \"zero\"; case 1: print \"one\"; default: print \"other\"; } \
+             print n;");
+        assert_eq!(output, vec!["one", "1"]);
+    }
+
+    #[test]
+    fn print_flushes_each_line() {
+        let tokens = scanner::scan("print 1; print 2, 3;").expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let recorder = FlushRecorder::default();
+        let flushed = Rc::clone(&recorder.flushed);
+        let mut interp = Interpreter::new_capturing();
+        interp.writer = Box::new(recorder);
+        interp
+            .interpret(&program, locals)
+            .expect("interpret should succeed");
+        assert_eq!(*flushed.borrow(), vec!["1\n", "2 3\n"]);
+    }
+
+    #[rstest]
+    #[case::write_joins_one_line("write(\"a\"); write(\"b\");", vec!["ab"])]
+    #[case::print_ends_a_written_line("write(\"a\"); print \"b\";", vec!["ab"])]
+    #[case::writeln_ends_the_line("write(1); writeln(2); write(3);", vec!["12", "3"])]
+    #[case::writeln_alone("writeln(\"x\"); print \"y\";", vec!["x", "y"])]
+    #[case::uses_str_method(
+        "class P { str() { return \"p\"; } } write(P()); writeln(\"!\");",
+        vec!["p!"]
+    )]
+    #[case::returns_nil("print write(\"\") == nil;", vec!["true"])]
+    fn write_and_writeln(#[case] source: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(run(source), expected);
+    }
+
+    #[rstest]
+    #[case::write(NativeFunction::Write, "write")]
+    #[case::writeln(NativeFunction::Writeln, "writeln")]
+    fn writing_natives_called_directly_are_an_error(
+        #[case] native: NativeFunction,
+        #[case] name: &str,
+    ) {
+        let err = native
+            .call(&[Value::Str("x".to_string())])
+            .expect_err("needs an interpreter");
+        assert_eq!(
+            err.to_string(),
+            format!("Error: {name}() can only be called from a running program")
+        );
+    }
+
+    #[test]
+    fn write_goes_through_the_writer() {
+        let tokens = scanner::scan("write(\"a\"); writeln(\"b\");").expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let recorder = FlushRecorder::default();
+        let flushed = Rc::clone(&recorder.flushed);
+        let mut interp = Interpreter::new_capturing();
+        interp.writer = Box::new(recorder);
+        interp
+            .interpret(&program, locals)
+            .expect("interpret should succeed");
+        assert_eq!(*flushed.borrow(), vec!["a", "b\n"]);
+    }
+
+    /// Records one line per sink call, naming each value's type; the log is
+    /// shared so the test can read it after handing the sink over.
+    #[derive(Default)]
+    struct RecordingSink {
+        calls: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl OutputSink for RecordingSink {
+        fn print(&mut self, values: &[Value]) {
+            let kinds: Vec<&str> = values.iter().map(Value::type_name).collect();
+            self.calls
+                .borrow_mut()
+                .push(format!("print {}", kinds.join(" ")));
+        }
+
+        fn write(&mut self, value: &Value, newline: bool) {
+            let call = if newline { "writeln" } else { "write" };
+            self.calls
+                .borrow_mut()
+                .push(format!("{call} {}", value.type_name()));
+        }
+    }
+
+    #[test]
+    fn sink_receives_printed_values() {
+        let sink = RecordingSink::default();
+        let calls = Rc::clone(&sink.calls);
+        let mut interp = Interpreter::new_capturing();
+        interp.set_sink(sink);
+        interp
+            .run_source(
+                "class A { str() { return \"a\"; } } \
+                 print 1, \"two\"; print 1; print \"two\"; print nil, A(); \
+                 write(A()); writeln(5);",
+            )
+            .expect("runs");
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "print number string",
+                "print number",
+                "print string",
+                "print nil instance",
+                "write instance",
+                "writeln number",
+            ]
+        );
+        assert!(interp.output().is_empty(), "nothing reaches the writer");
+    }
+
+    #[test]
+    fn parse_additional_uses_disjoint_expr_ids() {
+        let mut interp = Interpreter::new_capturing();
+        let mut ids = Vec::new();
+        for source in ["a;", "b;", "c;"] {
+            let tokens = scanner::scan(source).expect("scan should succeed");
+            let program = interp.parse_additional(tokens).expect("parse");
+            let Decl::Statement(Stmt::Expression(stmt)) = &program.declarations[0] else {
+                panic!("expected expression statement");
+            };
+            ids.push(stmt.expression.id());
+        }
+        assert_eq!(ids, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn later_parses_do_not_reuse_resolved_ids() {
+        // `x` inside `get` is resolved as a local; if the second program
+        // reused its id, the global `x` there would be looked up as a local.
+        let mut interp = Interpreter::new_capturing();
+        for source in [
+            "fun make() { var x = \"local\"; fun get() { return x; } return get; }",
+            "var x = \"global\"; print x; print make()();",
+        ] {
+            let tokens = scanner::scan(source).expect("scan should succeed");
+            let program = interp.parse_additional(tokens).expect("parse");
+            let locals = Resolver::new().resolve(&program).expect("resolve");
+            interp.merge_locals(locals);
+            interp.interpret_additional(&program).expect("run");
+        }
+        assert_eq!(interp.output, vec!["global", "local"]);
+    }
+
+    #[test]
+    fn eval_expression_returns_value() {
+        let mut interp = Interpreter::new();
+        assert_eq!(
+            interp.eval_expression("1 + 2 * 3").expect("evaluates"),
+            Value::Number(7.0)
+        );
+    }
+
+    #[rstest]
+    #[case::user_function("f", Some(("f", 2, Some(vec!["a", "b"]))))]
+    #[case::bound_method("C().m", Some(("m", 1, Some(vec!["x"]))))]
+    #[case::native("clock", Some(("clock", 0, None)))]
+    #[case::class("C", None)]
+    #[case::number("1", None)]
+    fn function_info_reports_name_and_arity(
+        #[case] expression: &str,
+        #[case] expected: Option<(&str, usize, Option<Vec<&str>>)>,
+    ) {
+        let mut interp = Interpreter::new_capturing();
+        interp
+            .run_source("fun f(a, b) {} class C { m(x) {} }")
+            .expect("runs");
+        let value = interp.eval_expression(expression).expect("evaluates");
+        let expected = expected.map(|(name, arity, params)| FunctionInfo {
+            name: name.to_string(),
+            arity,
+            params: params.map(|p| p.into_iter().map(String::from).collect()),
+        });
+        assert_eq!(value.as_function_info(), expected);
+    }
+
+    #[test]
+    fn eval_expression_sees_globals() {
+        let source = "var greeting = \"hi\"; fun twice(n) { return n * 2; }";
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::new_capturing();
+        interp
+            .interpret(&program, locals)
+            .expect("interpret should succeed");
+        assert_eq!(
+            interp
+                .eval_expression("greeting + \"!\"")
+                .expect("evaluates"),
+            Value::Str("hi!".to_string())
+        );
+        assert_eq!(
+            interp.eval_expression("twice(21)").expect("evaluates"),
+            Value::Number(42.0)
+        );
+    }
+
+    #[rstest]
+    #[case("print 1;", "found a statement starting with 'print'")]
+    #[case("var x = 1;", "found a statement starting with 'var'")]
+    #[case("1 + 2;", "expected end of expression, found ';'")]
+    #[case("1 +", "expected expression")]
+    fn eval_expression_rejects_non_expressions(#[case] source: &str, #[case] expected: &str) {
+        let err = Interpreter::new().eval_expression(source).unwrap_err();
+        assert!(matches!(err, EvalError::Compile(_)), "got: {err:?}");
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[test]
+    fn eval_expression_runtime_error() {
+        let err = Interpreter::new().eval_expression("-\"x\"").unwrap_err();
+        assert!(matches!(err, EvalError::Runtime(_)), "got: {err:?}");
+    }
+
+    #[test]
+    fn run_source_runs_a_program() {
+        let mut interp = Interpreter::new();
+        interp
+            .run_source("fun f(x) { return x * 2; } print f(21);")
+            .expect("runs");
+        assert_eq!(interp.output(), ["42"]);
+    }
+
+    #[rstest]
+    #[case::function("fun f(x) { return x; }", "print f(1);", "1")]
+    #[case::function_shadowing_a_global("var x = 5; fun f(x) { return x; }", "print f(1);", "1")]
+    #[case::closure(
+        "fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; } var c = counter();",
+        "c(); print c();",
+        "2"
+    )]
+    fn run_source_keeps_earlier_definitions(
+        #[case] first: &str,
+        #[case] second: &str,
+        #[case] expected: &str,
+    ) {
+        let mut interp = Interpreter::new_capturing();
+        interp.run_source(first).expect("first program runs");
+        interp.run_source(second).expect("second program runs");
+        assert_eq!(interp.output(), [expected]);
+    }
+
+    #[test]
+    fn run_source_keeps_consts_constant() {
+        let mut interp = Interpreter::new_capturing();
+        interp
+            .run_source("const k = 1;")
+            .expect("first program runs");
+        let err = interp.run_source("k = 2;").unwrap_err();
+        assert!(matches!(err, EvalError::Compile(_)), "{err:?}");
+    }
+
+    #[test]
+    fn reset_forgets_the_previous_program() {
+        let mut interp = Interpreter::new_capturing();
+        interp
+            .run_source("var x = 1; const k = 2; fun clock() { return -1; } print x;")
+            .expect("first program runs");
+        assert_eq!(interp.output(), ["1"]);
+
+        interp.reset();
+        assert!(interp.output().is_empty());
+        interp
+            .run_source("print clock() >= 0;")
+            .expect("clock is the native again");
+        assert_eq!(interp.output(), ["true"]);
+        let err = interp.run_source("print x;").unwrap_err();
+        assert_eq!(err.to_string(), "Error: undefined variable 'x'");
+        // `k` is just undefined now, not a constant the resolver rejects.
+        let err = interp.run_source("k = 3;").unwrap_err();
+        assert_eq!(err.to_string(), "Error: undefined variable 'k'");
+        interp
+            .run_source("var k = 4; k = 5; print k;")
+            .expect("k is no longer const");
+        assert_eq!(interp.output(), ["true", "5"]);
+    }
+
+    #[rstest]
+    #[case::bare("print 1;\nreturn;\nprint 2;", 2)]
+    #[case::with_value("print 1;\nreturn 3;", 2)]
+    #[case::in_block("print 1;\n{ if (true) return; }", 2)]
+    #[case::later_line_of_block("print 1;\n{\n  if (true)\n    return;\n}", 4)]
+    fn top_level_return_is_a_clean_error(#[case] source: &str, #[case] line: usize) {
+        // Skip the resolver, which would reject the program outright.
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let mut interp = Interpreter::new_capturing();
+        let err = interp.interpret(&program, HashMap::new()).unwrap_err();
+        let RuntimeError::Error {
+            span: Some(span), ..
+        } = &err
+        else {
+            panic!("expected a located error, got {err:?}");
+        };
+        assert_eq!(Some(span.offset), source.find("return"));
+        assert_eq!(
+            err.display_with_line(source),
+            format!("Error: line {line}: can't return from top-level code")
+        );
+        assert_eq!(interp.output(), ["1"]);
+
+        let err = interp.interpret_additional(&program).unwrap_err();
+        assert!(!err.is_return());
+    }
+
+    #[rstest]
+    #[case::scan("print @;", 1)]
+    #[case::parse("print 1\nprint 2\nvar;", 2)]
+    #[case::resolve("{ var a = 1; var a = 2; }", 1)]
+    fn run_source_reports_every_compile_error(#[case] source: &str, #[case] count: usize) {
+        let err = Interpreter::new().run_source(source).unwrap_err();
+        let EvalError::Compile(errors) = err else {
+            panic!("expected compile errors, got {err:?}");
+        };
+        assert_eq!(errors.len(), count, "{errors:?}");
+    }
+
+    #[rstest]
+    #[case::outside_class("print super.m;")]
+    #[case::no_superclass("class A { m() { return super.m; } } A().m();")]
+    fn misplaced_super_is_a_compile_error_not_a_panic(#[case] source: &str) {
+        let err = Interpreter::new().run_source(source).unwrap_err();
+        assert!(matches!(err, EvalError::Compile(_)), "{err:?}");
+    }
+
+    #[rstest]
+    #[case("print 1, 2, 3;", "1 2 3")]
+    #[case(r#"print "a", nil, true;"#, "a nil true")]
+    #[case("print 1;", "1")]
+    fn print_multiple_values(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[test]
+    fn print_instance_uses_str_method() {
+        let output = run(r#"
+            class Pet {
+                init(name) { this.name = name; }
+                str() { return "Pet(" + this.name + ")"; }
+            }
+            print Pet("Rex");
+        "#);
+        assert_eq!(output, vec!["Pet(Rex)"]);
+    }
+
+    #[test]
+    fn print_instance_uses_inherited_str_method() {
+        let output = run(r#"
+            class Base { str() { return "base"; } }
+            class Derived < Base {}
+            print Derived();
+        "#);
+        assert_eq!(output, vec!["base"]);
+    }
+
+    #[test]
+    fn print_instance_without_str_method() {
+        assert_eq!(run("class Foo {} print Foo();"), vec!["Foo instance"]);
+    }
+
+    #[test]
+    fn print_instance_ignores_str_with_parameters() {
+        assert_eq!(
+            run(r#"class Foo { str(x) { return "no"; } } print Foo();"#),
+            vec!["Foo instance"]
+        );
+    }
+
+    #[test]
+    fn print_instance_str_must_return_string() {
+        let err = run_err("class Foo { str() { return 42; } } print Foo();");
+        assert!(
+            err.to_string()
+                .contains("'str' method must return a string, got 42"),
+            "got: {err}"
+        );
+    }
+
+    #[rstest]
+    #[case("print 1 / 0;")]
+    #[case("print 0 / 0;")]
+    #[case("print 5 % 0;")]
+    fn division_by_zero(#[case] source: &str) {
+        let err = run_err(source);
+        assert!(err.to_string().contains("division by zero"), "got: {err}");
+    }
+
+    #[test]
+    fn division_by_zero_has_operator_span() {
+        let source = "var a = 1;\nprint a / 0;";
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::new_capturing();
+        let err = interp.interpret(&program, locals).unwrap_err();
+        assert_eq!(
+            err.display_with_line(source),
+            "Error: line 2: division by zero"
+        );
+    }
+
+    #[test]
+    fn errors_after_multibyte_text_report_the_right_line() {
+        let source = "fun f() {\n  print \"héllo wörld\";\n  return -\"ü\";\n}\nprint \"→\";\nf();";
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::new_capturing();
+        interp.set_source(source);
+        let err = interp.interpret(&program, locals).unwrap_err();
+        assert_eq!(
+            err.display_with_line(source),
+            "Error: line 3: operand must be a number"
+        );
+        let lines: Vec<usize> = err.backtrace_frames().iter().map(|f| f.line).collect();
+        assert_eq!(lines, vec![6]);
+    }
+
+    #[rstest]
+    #[case("print \"apple\" < \"banana\";", "true")]
+    #[case("print \"apple\" > \"banana\";", "false")]
+    #[case("print \"b\" >= \"b\";", "true")]
+    #[case("print \"b\" <= \"a\";", "false")]
+    #[case("print \"Z\" < \"a\";", "true")]
+    #[case("print \"app\" < \"apple\";", "true")]
+    #[case("print 1 < 2;", "true")]
+    #[case("print 2 <= 1;", "false")]
+    #[case("print 0/1 >= -0;", "true")]
+    fn comparison(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print 1 < \"2\";")]
+    #[case("print \"a\" >= nil;")]
+    fn comparison_type_error(#[case] source: &str) {
+        let err = run_err(source);
+        assert!(
+            err.to_string()
+                .contains("operands must be two numbers or two strings"),
+            "got: {err}"
+        );
+    }
+
     #[test]
     fn type_error_addition() {
         let err = run_err("print 1 + \"a\";");
@@ -793,4 +1724,283 @@ mod tests {
             vec!["0", "1", "1", "2", "3", "5", "8", "13", "21", "34"]
         );
     }
+
+    #[test]
+    fn eprint_bypasses_captured_output() {
+        assert_eq!(run("eprint(\"oops\"); print \"ok\";"), vec!["ok"]);
+    }
+
+    #[test]
+    fn eprint_returns_nil() {
+        assert_eq!(run("print eprint(1);"), vec!["nil"]);
+    }
+
+    #[test]
+    fn time_millis_is_positive_number() {
+        let output = run("print time_millis(); print time_millis() > 0;");
+        assert!(output[0].parse::<f64>().is_ok());
+        assert_eq!(output[1], "true");
+    }
+
+    #[rstest]
+    #[case(r#"print strlen("hello");"#, "5")]
+    #[case(r#"print strlen("héllo");"#, "5")]
+    #[case(r#"print substr("hello", 1, 3);"#, "el")]
+    #[case(r#"print substr("héllo", 1, 4);"#, "éll")]
+    #[case(r#"print substr("héllo", 3, 100);"#, "lo")]
+    #[case(r#"print upper("héllo");"#, "HÉLLO")]
+    #[case(r#"print lower("HeLLo");"#, "hello")]
+    #[case(r#"print indexOf("héllo", "llo");"#, "2")]
+    #[case(r#"print indexOf("hello", "z");"#, "-1")]
+    #[case("print strlen(42);", "nil")]
+    fn string_natives(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case(r#"print ord("A");"#, "65")]
+    #[case(r#"print ord("é");"#, "233")]
+    #[case("print chr(65);", "A")]
+    #[case(r#"print chr(ord("z"));"#, "z")]
+    fn chr_ord(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case(r#"ord("");"#, "non-empty string")]
+    #[case("ord(65);", "must be a string")]
+    #[case("chr(-1);", "valid codepoint")]
+    #[case("chr(65.5);", "valid codepoint")]
+    #[case(r#"chr("A");"#, "must be a number")]
+    fn chr_ord_errors(#[case] source: &str, #[case] expected: &str) {
+        let err = run_err(source);
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[rstest]
+    #[case(r#"substr("hello", 1.5, 3);"#, "got 1.5")]
+    #[case(r#"substr("hello", -1, 3);"#, "got -1")]
+    #[case(
+        r#"substr("hello", 0, 100000000000000000000);"#,
+        "got 100000000000000000000"
+    )]
+    fn substr_rejects_bad_indices(#[case] source: &str, #[case] detail: &str) {
+        let err = run_err(source).to_string();
+        assert!(
+            err.contains("substr() index must be a non-negative integer"),
+            "got: {err}"
+        );
+        assert!(err.contains(detail), "got: {err}");
+    }
+
+    #[rstest]
+    // Cargo sets CARGO_PKG_NAME for the test process.
+    #[case(r#"print env("CARGO_PKG_NAME");"#, env!("CARGO_PKG_NAME"))]
+    #[case(r#"print env("VIBE_LOX_INTERP_ENV_UNSET");"#, "nil")]
+    fn env_reads_process_environment(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[test]
+    fn env_requires_string_argument() {
+        let err = run_err("env(1);");
+        assert!(err.to_string().contains("env() argument must be a string"));
+    }
+
+    #[test]
+    fn env_can_be_disabled() {
+        let source = r#"print env("HOME");"#;
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::new_capturing();
+        interp.set_allow_env(false);
+        let err = interp.interpret(&program, locals).unwrap_err();
+        assert_eq!(
+            err.display_with_line(source),
+            "Error: line 1: env() is disabled"
+        );
+        assert!(interp.output.is_empty());
+    }
+
+    fn run_lenient(source: &str) -> Result<Vec<String>, RuntimeError> {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::new_capturing();
+        interp.set_strict_globals(false);
+        interp.interpret(&program, locals)?;
+        Ok(interp.output)
+    }
+
+    #[rstest]
+    #[case("print missing;", "nil")]
+    #[case("print missing ?? 42;", "42")]
+    #[case("fun f() { return missing; } print f();", "nil")]
+    fn lenient_globals_read_undefined_as_nil(#[case] source: &str, #[case] expected: &str) {
+        let output = run_lenient(source).expect("interpret should succeed");
+        assert_eq!(output, vec![expected]);
+    }
+
+    #[test]
+    fn lenient_globals_still_reject_undefined_assignment() {
+        let err = run_lenient("missing = 1;").expect_err("assignment should fail");
+        assert!(err.to_string().contains("undefined variable 'missing'"));
+    }
+
+    #[test]
+    fn strict_globals_reject_undefined_read() {
+        let err = run_err("print missing;");
+        assert!(err.to_string().contains("undefined variable 'missing'"));
+    }
+
+    #[rstest]
+    #[case::reassigned_in_loop(
+        "var x = 0; fun get() { return x; } \
+         for (var i = 0; i < 3; i = i + 1) { x = x + 1; print get(); }",
+        vec!["1", "2", "3"]
+    )]
+    #[case::redefined(
+        "var x = 1; fun get() { return x; } print get(); var x = 2; print get();",
+        vec!["1", "2"]
+    )]
+    #[case::assigned_through_function(
+        "var x = 1; fun set(v) { x = v; } print x; set(5); print x; set(6); print x;",
+        vec!["1", "5", "6"]
+    )]
+    fn cached_global_slots_see_later_writes(#[case] source: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(run(source), expected);
+    }
+
+    #[test]
+    fn failed_global_lookup_is_not_cached() {
+        let output = run_lenient("fun get() { return x; } print get(); var x = 3; print get();")
+            .expect("interpret should succeed");
+        assert_eq!(output, vec!["nil", "3"]);
+    }
+
+    #[test]
+    fn cached_global_slots_are_per_program() {
+        // Both programs number their expressions from zero, so the same id
+        // names a different global in each.
+        let mut interp = Interpreter::new_capturing();
+        for source in ["var a = 1; print a;", "var b = 2; print b;"] {
+            let tokens = scanner::scan(source).expect("scan should succeed");
+            let program = Parser::new(tokens).parse().expect("parse should succeed");
+            let locals = Resolver::new()
+                .resolve(&program)
+                .expect("resolve should succeed");
+            interp.interpret(&program, locals).expect("interpret");
+        }
+        assert_eq!(interp.output(), ["1", "2"]);
+    }
+
+    fn run_sandboxed(source: &str) -> (Vec<String>, Result<(), RuntimeError>) {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let locals = Resolver::new()
+            .resolve(&program)
+            .expect("resolve should succeed");
+        let mut interp = Interpreter::sandboxed();
+        interp.writer = Box::new(Vec::<u8>::new());
+        let result = interp.interpret(&program, locals);
+        (interp.output, result)
+    }
+
+    #[rstest]
+    #[case("clock();", "clock")]
+    #[case("readLine();", "readLine")]
+    #[case("eprint(1);", "eprint")]
+    #[case("time_millis();", "time_millis")]
+    #[case(r#"env("HOME");"#, "env")]
+    fn sandbox_rejects_side_effecting_natives(#[case] source: &str, #[case] name: &str) {
+        let (_, result) = run_sandboxed(source);
+        let err = result.expect_err("native should be disabled");
+        assert_eq!(
+            err.to_string(),
+            format!("Error: {name}() is not available in sandbox mode")
+        );
+    }
+
+    #[test]
+    fn sandbox_still_runs_pure_code() {
+        let (output, result) = run_sandboxed(r#"print 1 + 1; print upper("ok"); print clock;"#);
+        result.expect("interpret should succeed");
+        assert_eq!(output, vec!["2", "OK", "<fn clock(0)>"]);
+    }
+
+    #[test]
+    fn native_errors_are_located_at_the_call_site() {
+        let source = "var x = 1;\nprint chr(\"A\");";
+        let err = run_err(source);
+        assert_eq!(
+            err.display_with_line(source),
+            "Error: line 2: chr() argument must be a number, got A"
+        );
+    }
+
+    #[rstest]
+    #[case::increment("for (var i = 0; i < 3; i = i + \"x\") print i;", "i + \"x\"")]
+    #[case::condition("for (var i = 0; i < \"x\"; i = i + 1) print i;", "i < \"x\"")]
+    fn for_clause_errors_point_at_the_clause(#[case] source: &str, #[case] clause: &str) {
+        let err = run_err(source);
+        let RuntimeError::Error {
+            span: Some(span), ..
+        } = &err
+        else {
+            panic!("expected a located error, got {err:?}");
+        };
+        assert_eq!(&source[span.offset..span.offset + span.len], clause);
+    }
+
+    #[rstest]
+    #[case("chr(\"A\")")]
+    #[case("chr(-1)")]
+    #[case("ord(65)")]
+    #[case("ord(\"\")")]
+    #[case("env(1)")]
+    fn native_errors_span_the_whole_call(#[case] call: &str) {
+        let source = format!("var x = 1;\nprint {call};");
+        let err = run_err(&source);
+        let RuntimeError::Error {
+            span: Some(span), ..
+        } = &err
+        else {
+            panic!("expected a located error, got {err:?}");
+        };
+        assert_eq!(&source[span.offset..span.offset + span.len], call);
+    }
+
+    #[rstest]
+    #[case("class Foo {} var a = Foo(); print a == a;", "true")]
+    #[case("class Foo {} print Foo() == Foo();", "false")]
+    #[case("class Foo {} var a = Foo(); var b = a; print a != b;", "false")]
+    #[case("class Foo {} print Foo == Foo;", "true")]
+    #[case("class Foo {} class Bar {} print Foo == Bar;", "false")]
+    #[case("fun f() {} var g = f; print f == g;", "true")]
+    #[case("fun f() {} fun g() {} print f == g;", "false")]
+    #[case("print clock == clock;", "true")]
+    #[case("print clock == readLine;", "false")]
+    #[case("class Foo {} print Foo() == nil;", "false")]
+    fn reference_equality(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print type(1);", "number")]
+    #[case(r#"print type("s");"#, "string")]
+    #[case("print type(true);", "bool")]
+    #[case("print type(nil);", "nil")]
+    #[case("print type(clock);", "function")]
+    #[case("fun f() {} print type(f);", "function")]
+    #[case("class C { m() {} } print type(C().m);", "function")]
+    #[case("class C {} print type(C);", "class")]
+    #[case("class C {} print type(C());", "instance")]
+    fn type_native(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run(source), vec![expected]);
+    }
 }
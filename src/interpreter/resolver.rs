@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::ast::*;
-use crate::error::CompileError;
+use crate::error::{CompileError, CompileWarning};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum FunctionType {
@@ -18,12 +18,23 @@ enum ClassType {
     Subclass,
 }
 
+/// Scope depth of every expression that refers to a local, by id. Absent
+/// ids are globals.
+pub type Locals = HashMap<ExprId, usize>;
+
 pub struct Resolver {
     scopes: Vec<HashMap<String, bool>>,
-    locals: HashMap<ExprId, usize>,
+    /// `const` names declared in each entry of `scopes`.
+    const_scopes: Vec<HashSet<String>>,
+    /// `const` names declared at global scope.
+    global_consts: HashSet<String>,
+    locals: Locals,
     current_function: FunctionType,
     current_class: ClassType,
     errors: Vec<CompileError>,
+    warnings: Vec<CompileWarning>,
+    /// Report unreachable code as an error instead of a warning.
+    deny_unreachable: bool,
 }
 
 impl Default for Resolver {
@@ -36,33 +47,112 @@ impl Resolver {
     pub fn new() -> Self {
         Self {
             scopes: Vec::new(),
+            const_scopes: Vec::new(),
+            global_consts: HashSet::new(),
             locals: HashMap::new(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
             errors: Vec::new(),
+            warnings: Vec::new(),
+            deny_unreachable: false,
         }
     }
 
-    pub fn resolve(
-        mut self,
+    /// Report code after a `return` as an error rather than a warning.
+    pub fn with_deny_unreachable(mut self, deny: bool) -> Self {
+        self.deny_unreachable = deny;
+        self
+    }
+
+    /// Treat `names` as `const` globals declared by earlier input, such as
+    /// previous REPL lines, so assigning to them is still an error.
+    pub fn with_global_consts(mut self, names: HashSet<String>) -> Self {
+        self.global_consts = names;
+        self
+    }
+
+    /// Resolve `program`, discarding any warnings.
+    pub fn resolve(self, program: &Program) -> Result<Locals, Vec<CompileError>> {
+        self.resolve_with_warnings(program)
+            .map(|(locals, _warnings)| locals)
+    }
+
+    /// Like [`Resolver::resolve`], also returning warnings such as code that
+    /// can never run because it follows a `return` in the same block.
+    pub fn resolve_with_warnings(
+        self,
         program: &Program,
-    ) -> Result<HashMap<ExprId, usize>, Vec<CompileError>> {
+    ) -> Result<(Locals, Vec<CompileWarning>), Vec<CompileError>> {
+        self.run(program)
+            .map(|resolver| (resolver.locals, resolver.warnings))
+    }
+
+    /// Like [`Resolver::resolve`], also returning the `const` globals in
+    /// effect afterwards, to pass to [`Resolver::with_global_consts`] when
+    /// resolving the next piece of the same session.
+    pub fn resolve_with_consts(
+        self,
+        program: &Program,
+    ) -> Result<(Locals, HashSet<String>), Vec<CompileError>> {
+        self.run(program)
+            .map(|resolver| (resolver.locals, resolver.global_consts))
+    }
+
+    fn run(mut self, program: &Program) -> Result<Self, Vec<CompileError>> {
         for decl in &program.declarations {
             self.resolve_decl(decl);
         }
         if self.errors.is_empty() {
-            Ok(self.locals)
+            Ok(self)
         } else {
             Err(self.errors)
         }
     }
 
+    /// Like [`Resolver::resolve`], but lists the resolved locals as
+    /// `(expression id, scope depth)` pairs in id order, for inspecting
+    /// resolution in tests and tooling.
+    pub fn resolve_sorted(
+        self,
+        program: &Program,
+    ) -> Result<Vec<(ExprId, usize)>, Vec<CompileError>> {
+        let mut locals: Vec<_> = self.resolve(program)?.into_iter().collect();
+        locals.sort_unstable();
+        Ok(locals)
+    }
+
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.const_scopes.push(HashSet::new());
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop();
+        self.const_scopes.pop();
+    }
+
+    /// Record whether the innermost binding of `name` is a `const`.
+    fn mark_const(&mut self, name: &str, is_const: bool) {
+        let consts = self
+            .const_scopes
+            .last_mut()
+            .unwrap_or(&mut self.global_consts);
+        if is_const {
+            consts.insert(name.to_string());
+        } else {
+            consts.remove(name);
+        }
+    }
+
+    /// Whether `name` refers to a `const`, looking through enclosing scopes
+    /// the same way `resolve_local` does.
+    fn is_const(&self, name: &str) -> bool {
+        for (scope, consts) in self.scopes.iter().zip(&self.const_scopes).rev() {
+            if scope.contains_key(name) {
+                return consts.contains(name);
+            }
+        }
+        self.global_consts.contains(name)
     }
 
     fn declare(&mut self, name: &str, span: crate::scanner::token::Span) {
@@ -102,6 +192,7 @@ impl Resolver {
                     self.resolve_expr(init);
                 }
                 self.define(&v.name);
+                self.mark_const(&v.name, v.is_const);
             }
             Decl::Fun(f) => {
                 self.declare(&f.function.name, f.span);
@@ -123,8 +214,9 @@ impl Resolver {
                             c.span.len,
                         ));
                     }
+                    // Every backend looks the superclass up by name, so it
+                    // gets no `locals` entry (which would need an ExprId).
                     self.current_class = ClassType::Subclass;
-                    self.resolve_local(0, superclass); // ID doesn't matter for superclass lookup
                     self.begin_scope();
                     self.scopes
                         .last_mut()
@@ -165,17 +257,44 @@ impl Resolver {
             self.declare(param, function.span);
             self.define(param);
         }
-        for decl in &function.body {
-            self.resolve_decl(decl);
-        }
+        self.resolve_block(&function.body);
         self.end_scope();
         self.current_function = enclosing;
     }
 
+    /// Resolve the declarations of a block or function body in order. Once
+    /// one of them always returns, the rest can never run; the first of
+    /// those is reported as unreachable.
+    fn resolve_block(&mut self, declarations: &[Decl]) {
+        let mut terminated = false;
+        let mut reported = false;
+        for decl in declarations {
+            if terminated && !reported {
+                self.report_unreachable(decl.span());
+                reported = true;
+            }
+            self.resolve_decl(decl);
+            terminated |= always_returns(decl);
+        }
+    }
+
+    fn report_unreachable(&mut self, span: crate::scanner::token::Span) {
+        let warning = CompileWarning::new("unreachable code", span.offset, span.len);
+        if self.deny_unreachable {
+            self.errors.push(warning.into_error());
+        } else {
+            self.warnings.push(warning);
+        }
+    }
+
     fn resolve_stmt(&mut self, stmt: &Stmt) {
         match stmt {
             Stmt::Expression(e) => self.resolve_expr(&e.expression),
-            Stmt::Print(p) => self.resolve_expr(&p.expression),
+            Stmt::Print(p) => {
+                for expr in &p.expressions {
+                    self.resolve_expr(expr);
+                }
+            }
             Stmt::Return(r) => {
                 if self.current_function == FunctionType::None {
                     self.errors.push(CompileError::resolve(
@@ -197,9 +316,7 @@ impl Resolver {
             }
             Stmt::Block(b) => {
                 self.begin_scope();
-                for decl in &b.declarations {
-                    self.resolve_decl(decl);
-                }
+                self.resolve_block(&b.declarations);
                 self.end_scope();
             }
             Stmt::If(i) => {
@@ -232,6 +349,13 @@ impl Resolver {
             }
             Expr::Assign(a) => {
                 self.resolve_expr(&a.value);
+                if self.is_const(&a.name) {
+                    self.errors.push(CompileError::resolve(
+                        format!("can't assign to constant '{}'", a.name),
+                        a.span.offset,
+                        a.span.len,
+                    ));
+                }
                 self.resolve_local(a.id, &a.name);
             }
             Expr::Binary(b) => {
@@ -295,3 +419,260 @@ impl Resolver {
         }
     }
 }
+
+/// Whether executing `decl` always ends in a `return`: a `return` itself, a
+/// block containing one, or an `if` whose branches both always return.
+fn always_returns(decl: &Decl) -> bool {
+    match decl {
+        Decl::Statement(stmt) => stmt_always_returns(stmt),
+        Decl::Class(_) | Decl::Fun(_) | Decl::Var(_) => false,
+    }
+}
+
+fn stmt_always_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return(_) => true,
+        Stmt::Block(b) => b.declarations.iter().any(always_returns),
+        Stmt::If(i) => {
+            stmt_always_returns(&i.then_branch)
+                && i.else_branch.as_deref().is_some_and(stmt_always_returns)
+        }
+        Stmt::Expression(_) | Stmt::Print(_) | Stmt::While(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner;
+    use rstest::rstest;
+
+    fn resolve_messages(source: &str) -> Vec<String> {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        match Resolver::new().resolve(&program) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    /// Depths of every resolved local, in source order.
+    fn resolved_depths(source: &str) -> Vec<usize> {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        Resolver::new()
+            .resolve_sorted(&program)
+            .expect("resolve should succeed")
+            .into_iter()
+            .map(|(_, depth)| depth)
+            .collect()
+    }
+
+    #[rstest]
+    #[case::this_in_method("class A { m() { return this; } }", vec![1])]
+    #[case::this_in_block("class A { m() { { return this; } } }", vec![2])]
+    #[case::this_in_closure(
+        "class A { m() { fun f() { return this; } return f; } }",
+        vec![2, 0]
+    )]
+    #[case::super_in_method("class A {} class B < A { m() { return super.m; } }", vec![2])]
+    #[case::super_in_closure(
+        "class A {} class B < A { m() { fun f() { return super.m; } return f; } }",
+        vec![3, 0]
+    )]
+    #[case::globals_are_not_recorded("var a = 1; print a;", vec![])]
+    fn resolves_this_and_super_depths(#[case] source: &str, #[case] expected: Vec<usize>) {
+        assert_eq!(resolved_depths(source), expected);
+    }
+
+    #[rstest]
+    #[case::global("const PI = 3.14; PI = 3;")]
+    #[case::local("{ const PI = 3.14; PI = 3; }")]
+    #[case::from_closure("{ const PI = 3.14; fun f() { PI = 3; } }")]
+    #[case::global_from_function("const PI = 3.14; fun f() { PI = 3; }")]
+    fn assigning_const_is_an_error(#[case] source: &str) {
+        let messages = resolve_messages(source);
+        assert_eq!(messages.len(), 1, "{messages:?}");
+        assert!(
+            messages[0].contains("can't assign to constant 'PI'"),
+            "{messages:?}"
+        );
+    }
+
+    #[rstest]
+    #[case::shadowed_in_block("const x = 1; { var x = 2; x = 3; }")]
+    #[case::shadowed_by_parameter("const x = 1; fun f(x) { x = 2; }")]
+    #[case::global_redeclared_as_var("const x = 1; var x = 2; x = 3;")]
+    #[case::read_only("const x = 1; print x + 1;")]
+    fn const_without_reassignment_is_allowed(#[case] source: &str) {
+        assert_eq!(resolve_messages(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn global_consts_carry_over_between_resolutions() {
+        let parse = |source: &str| {
+            let tokens = scanner::scan(source).expect("scan should succeed");
+            Parser::new(tokens).parse().expect("parse should succeed")
+        };
+        let (_, consts) = Resolver::new()
+            .resolve_with_consts(&parse("const PI = 3; var e = 2;"))
+            .expect("resolve should succeed");
+        assert_eq!(consts, HashSet::from(["PI".to_string()]));
+
+        let errors = Resolver::new()
+            .with_global_consts(consts.clone())
+            .resolve(&parse("PI = 4;"))
+            .expect_err("PI is still a constant");
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("can't assign to constant 'PI'")
+        );
+
+        let (_, consts) = Resolver::new()
+            .with_global_consts(consts)
+            .resolve_with_consts(&parse("var PI = 4; PI = 5;"))
+            .expect("redeclaring as var lifts the restriction");
+        assert!(consts.is_empty());
+    }
+
+    #[rstest]
+    #[case::block("{ var x = 1; var x = 2; }")]
+    #[case::function_body("fun f() { var x = 1; var x = 2; }")]
+    #[case::parameters("fun f(x, x) {}")]
+    #[case::parameter_and_body_local("fun f(x) { var x = 2; }")]
+    #[case::function_declaration("{ var x = 1; fun x() {} }")]
+    fn redeclaring_a_local_is_an_error(#[case] source: &str) {
+        let messages = resolve_messages(source);
+        assert_eq!(messages.len(), 1, "{messages:?}");
+        assert!(
+            messages[0].contains("variable 'x' already declared in this scope"),
+            "{messages:?}"
+        );
+    }
+
+    #[rstest]
+    #[case::global("var x = 1; var x = 2;")]
+    #[case::global_function_and_var("fun x() {} var x = 2;")]
+    #[case::sibling_blocks("{ var x = 1; } { var x = 2; }")]
+    #[case::shadowing_in_nested_block("{ var x = 1; { var x = 2; } }")]
+    fn redeclaration_outside_one_local_scope_is_allowed(#[case] source: &str) {
+        assert_eq!(resolve_messages(source), Vec::<String>::new());
+    }
+
+    /// The single resolve error for `source`: its message and the text it
+    /// points at.
+    fn resolve_error(source: &str) -> (String, String) {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let errors = Resolver::new()
+            .resolve(&program)
+            .expect_err("resolve should fail");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        let span = errors[0].span();
+        (
+            errors[0].to_string(),
+            source[span.offset()..span.offset() + span.len()].to_string(),
+        )
+    }
+
+    #[rstest]
+    #[case::top_level("print super.m;", "can't use 'super' outside of a class")]
+    #[case::in_function("fun f() { return super.m; }", "can't use 'super' outside of a class")]
+    #[case::class_without_superclass(
+        "class A { m() { return super.m; } }",
+        "can't use 'super' in a class with no superclass"
+    )]
+    #[case::nested_class_without_superclass(
+        "class A {} class B < A { m() { class C { n() { return super.m; } } } }",
+        "can't use 'super' in a class with no superclass"
+    )]
+    fn misplaced_super_is_an_error(#[case] source: &str, #[case] message: &str) {
+        let (error, text) = resolve_error(source);
+        assert!(error.contains(message), "{error}");
+        assert_eq!(text, "super.m");
+    }
+
+    #[rstest]
+    #[case::top_level("print this;")]
+    #[case::plain_function("fun f() { return this; }")]
+    #[case::function_after_a_class("class A { m() { return 1; } } fun g() { print this; }")]
+    fn this_outside_a_class_is_an_error(#[case] source: &str) {
+        let (error, text) = resolve_error(source);
+        assert!(
+            error.contains("can't use 'this' outside of a class"),
+            "{error}"
+        );
+        assert!(!error.contains("undefined variable"), "{error}");
+        assert_eq!(text, "this");
+    }
+
+    #[rstest]
+    #[case::value("class A { init() { return 1; } }", "return 1;")]
+    #[case::nested_block("class A { init(x) { if (x) { return this; } } }", "return this;")]
+    fn returning_a_value_from_an_initializer_is_an_error(#[case] source: &str, #[case] ret: &str) {
+        let (error, text) = resolve_error(source);
+        assert!(
+            error.contains("can't return a value from an initializer"),
+            "{error}"
+        );
+        assert_eq!(text, ret);
+    }
+
+    #[rstest]
+    #[case::bare_return("class A { init() { return; } }")]
+    #[case::nested_function("class A { init() { fun f() { return 1; } } }")]
+    #[case::other_method("class A { make() { return 1; } }")]
+    #[case::function_named_init("fun init() { return 1; }")]
+    fn other_returns_near_an_initializer_are_allowed(#[case] source: &str) {
+        assert_eq!(resolve_messages(source), Vec::<String>::new());
+    }
+
+    fn resolve_warnings(source: &str, deny: bool) -> Result<Vec<String>, Vec<String>> {
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        Resolver::new()
+            .with_deny_unreachable(deny)
+            .resolve_with_warnings(&program)
+            .map(|(_, warnings)| {
+                warnings
+                    .iter()
+                    .map(|w| {
+                        let span = w.span();
+                        source[span.offset()..span.offset() + span.len()].to_string()
+                    })
+                    .collect()
+            })
+            .map_err(|errors| errors.iter().map(ToString::to_string).collect())
+    }
+
+    #[rstest]
+    #[case::after_return("fun f() { return 1; print 2; }", "print 2;")]
+    #[case::only_the_first("fun f() { return; print 1; print 2; }", "print 1;")]
+    #[case::declaration("fun f() { return; var x = 1; }", "var x = 1;")]
+    #[case::in_nested_block("fun f() { if (true) { return; print 1; } }", "print 1;")]
+    #[case::after_returning_block("fun f() { { return; } print 1; }", "print 1;")]
+    #[case::after_if_else("fun f(a) { if (a) return 1; else return 2; print 3; }", "print 3;")]
+    #[case::in_method("class A { m() { return this; this.x = 1; } }", "this.x = 1")]
+    fn code_after_return_is_unreachable(#[case] source: &str, #[case] dead: &str) {
+        assert_eq!(resolve_warnings(source, false), Ok(vec![dead.to_string()]));
+    }
+
+    #[rstest]
+    #[case::return_last("fun f() { print 1; return 2; }")]
+    #[case::if_without_else("fun f(a) { if (a) return 1; print 2; }")]
+    #[case::one_branch_returns("fun f(a) { if (a) return 1; else print 0; print 2; }")]
+    #[case::loop_body("fun f() { while (true) { return 1; } print 2; }")]
+    #[case::nested_function("fun f() { fun g() { return 1; } return g; }")]
+    #[case::top_level("print 1; print 2;")]
+    fn reachable_code_is_not_flagged(#[case] source: &str) {
+        assert_eq!(resolve_warnings(source, false), Ok(vec![]));
+    }
+
+    #[test]
+    fn deny_unreachable_makes_it_an_error() {
+        let errors = resolve_warnings("fun f() { return; print 1; }", true).unwrap_err();
+        assert_eq!(errors, vec!["resolution error: unreachable code"]);
+    }
+}
@@ -4,6 +4,7 @@ use std::fmt;
 use std::rc::Rc;
 
 use crate::interpreter::callable::Callable;
+use crate::stdlib::{format_lox_class, format_lox_number};
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -25,32 +26,76 @@ impl Value {
         }
     }
 
+    /// Name of the value's type, as returned by the `type()` native.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::Str(_) => "string",
+            Self::Bool(_) => "bool",
+            Self::Nil => "nil",
+            Self::Function(_) => "function",
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
+        }
+    }
+
     pub fn is_equal(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Nil, Self::Nil) => true,
             (Self::Bool(a), Self::Bool(b)) => a == b,
             (Self::Number(a), Self::Number(b)) => a == b,
             (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Function(a), Self::Function(b)) => a.is_same(b),
+            (Self::Class(a), Self::Class(b)) => Rc::ptr_eq(a, b),
+            (Self::Instance(a), Self::Instance(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
+
+    /// Name and arity of a function value (user-defined, native, or a bound
+    /// method), so a host can check a callback before calling it. `None`
+    /// for anything else, including classes.
+    pub fn as_function_info(&self) -> Option<FunctionInfo> {
+        let Self::Function(callable) = self else {
+            return None;
+        };
+        let params = match callable {
+            Callable::User(function) => Some(function.params().to_vec()),
+            Callable::Native(_) => None,
+        };
+        Some(FunctionInfo {
+            name: callable.name().to_string(),
+            arity: callable.arity(),
+            params,
+        })
+    }
+}
+
+/// What [`Value::as_function_info`] reports about a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub arity: usize,
+    /// Parameter names of a user-defined function; `None` for natives.
+    pub params: Option<Vec<String>>,
+}
+
+/// Lox `==` semantics: values by content, objects by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_equal(other)
+    }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{n}")
-                }
-            }
+            Self::Number(n) => write!(f, "{}", format_lox_number(*n)),
             Self::Str(s) => write!(f, "{s}"),
             Self::Bool(b) => write!(f, "{b}"),
             Self::Nil => write!(f, "nil"),
             Self::Function(func) => write!(f, "{func}"),
-            Self::Class(class) => write!(f, "{class}"),
+            Self::Class(class) => write!(f, "{}", format_lox_class(&class.name)),
             Self::Instance(inst) => write!(f, "{}", inst.borrow()),
         }
     }
@@ -64,6 +109,18 @@ pub struct LoxClass {
 }
 
 impl LoxClass {
+    /// Whether this class is `other` or inherits from it.
+    pub fn is_subclass_of(&self, other: &LoxClass) -> bool {
+        let mut class = Some(self);
+        while let Some(c) = class {
+            if std::ptr::eq(c, other) {
+                return true;
+            }
+            class = c.superclass.as_deref();
+        }
+        false
+    }
+
     pub fn find_method(&self, name: &str) -> Option<Callable> {
         self.methods
             .get(name)
@@ -1,3 +1,4 @@
+pub mod analysis;
 pub mod ast;
 pub mod codegen;
 pub mod error;
@@ -6,6 +7,8 @@ pub mod parser;
 pub mod repl;
 pub mod scanner;
 pub mod stdlib;
+#[cfg(test)]
+mod test_support;
 pub mod vm;
 
 // Re-export error types for convenience
@@ -1,17 +1,35 @@
 use std::path::PathBuf;
+use std::sync::LazyLock;
 
 use anyhow::{Context, Result, bail};
 use clap::{CommandFactory, Parser};
 
-use vibe_lox::ast::printer;
-use vibe_lox::interpreter::Interpreter;
+use vibe_lox::analysis;
+use vibe_lox::ast::{self, printer};
+use vibe_lox::codegen::CompileOptions;
 use vibe_lox::interpreter::resolver::Resolver;
+use vibe_lox::interpreter::{Interpreter, with_interpreter_stack};
 use vibe_lox::parser::Parser as LoxParser;
 use vibe_lox::scanner;
 use vibe_lox::vm::chunk;
 
+/// `--version` text: the crate version plus the bytecode this build reads
+/// and writes, for diagnosing `.blox` compatibility problems.
+static VERSION: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "{} (blox format {}, {} opcodes)",
+        env!("CARGO_PKG_VERSION"),
+        chunk::BLOX_VERSION,
+        <chunk::OpCode as strum::EnumCount>::COUNT
+    )
+});
+
 #[derive(Parser, Debug)]
-#[command(name = "vibe-lox", about = "A Lox language interpreter and compiler")]
+#[command(
+    name = "vibe-lox",
+    about = "A Lox language interpreter and compiler",
+    version = VERSION.as_str()
+)]
 struct Cli {
     /// Lox source file to run (omit for REPL)
     file: Option<PathBuf>,
@@ -24,6 +42,17 @@ struct Cli {
     #[arg(long)]
     dump_ast: bool,
 
+    /// Remove `(group ...)` nodes from the --dump-ast output
+    #[arg(long)]
+    strip_groupings: bool,
+
+    /// Print the global names the program reads but never declares, one per line, and exit
+    #[arg(
+        long,
+        conflicts_with_all = ["dump_tokens", "dump_ast", "disassemble", "compile_bytecode", "stats"]
+    )]
+    emit_deps: bool,
+
     /// AST output format
     #[arg(long, default_value = "sexp", value_parser = ["sexp", "json"])]
     ast_format: String,
@@ -37,10 +66,34 @@ struct Cli {
     compile_llvm: bool,
 
     /// Compile to a native executable
-    #[arg(long, conflicts_with_all = ["compile_llvm", "compile_bytecode", "disassemble", "dump_tokens", "dump_ast"])]
+    #[arg(long, visible_alias = "compile-exe", conflicts_with_all = ["compile_llvm", "compile_bytecode", "disassemble", "dump_tokens", "dump_ast"])]
     compile: bool,
 
-    /// Output file path (overrides default for --compile-bytecode / --compile-llvm / --compile)
+    /// Compile to a native object file (.o) without linking
+    #[arg(long, conflicts_with_all = ["compile", "compile_llvm", "compile_bytecode", "disassemble", "dump_tokens", "dump_ast"])]
+    compile_obj: bool,
+
+    /// LLVM optimization level for --compile-llvm / --compile / --compile-obj (default 0)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=3))]
+    opt_level: Option<u8>,
+
+    /// Emit DWARF line tables for --compile-llvm / --compile / --compile-obj
+    #[arg(long)]
+    debug_info: bool,
+
+    /// Interleave `; line N: <source>` comments into --compile-llvm output
+    #[arg(long)]
+    annotate: bool,
+
+    /// Target triple for --compile / --compile-obj (defaults to the host)
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Compile with LLVM and run the program in-process via JIT
+    #[arg(long, conflicts_with_all = ["compile_llvm", "compile_bytecode", "compile", "compile_obj", "disassemble", "dump_tokens", "dump_ast"])]
+    jit: bool,
+
+    /// Output file path (overrides default for --compile-bytecode / --compile-llvm / --compile / --compile-obj)
     #[arg(short = 'o', long = "output")]
     output: Option<PathBuf>,
 
@@ -51,6 +104,18 @@ struct Cli {
     /// Disassemble bytecode (from source or saved file) and print
     #[arg(long)]
     disassemble: bool,
+
+    /// Print a size summary of the bytecode (from source or saved file)
+    #[arg(long, conflicts_with_all = ["disassemble", "compile_bytecode", "dump_tokens", "dump_ast"])]
+    stats: bool,
+
+    /// Run the peephole optimizer when compiling source for --compile-bytecode / --disassemble / --stats
+    #[arg(long)]
+    optimize_bytecode: bool,
+
+    /// Treat unreachable code (after a `return`) as an error instead of a warning
+    #[arg(long)]
+    deny_unreachable: bool,
 }
 
 fn read_source(cli: &Cli) -> Result<String> {
@@ -68,19 +133,47 @@ fn get_filename(cli: &Cli) -> String {
         .unwrap_or_else(|| "<input>".to_string())
 }
 
-fn compile_source(source: &str) -> Result<chunk::Chunk> {
-    vibe_lox::vm::compile_to_chunk(source).map_err(|e| anyhow::anyhow!("{e}"))
+/// Print disassembly, colorized when stdout is a terminal.
+fn print_disassembly(text: String) {
+    if chunk::color_enabled() {
+        print!("{}", chunk::colorize_disassembly(&text));
+    } else {
+        print!("{text}");
+    }
 }
 
-fn run_source(source: &str, filename: &str) -> Result<()> {
+fn llvm_compile_options(cli: &Cli, filename: &str) -> CompileOptions {
+    CompileOptions {
+        opt_level: cli.opt_level.unwrap_or(0),
+        debug_info: cli.debug_info.then(|| filename.to_string()),
+        annotate: cli.annotate,
+        target: cli.target.clone(),
+    }
+}
+
+fn compile_source(cli: &Cli, source: &str) -> Result<chunk::Chunk> {
+    let mut compiled =
+        vibe_lox::vm::compile_to_chunk(source).map_err(|e| anyhow::anyhow!("{e}"))?;
+    if cli.optimize_bytecode {
+        compiled.optimize();
+    }
+    Ok(compiled)
+}
+
+fn run_source(source: &str, filename: &str, deny_unreachable: bool) -> Result<()> {
     let tokens =
         scanner::scan(source).map_err(|errors| report_compile_errors(errors, filename, source))?;
     let program = LoxParser::new(tokens)
         .parse()
         .map_err(|errors| report_compile_errors(errors, filename, source))?;
-    let locals = Resolver::new()
-        .resolve(&program)
+    let (locals, warnings) = Resolver::new()
+        .with_deny_unreachable(deny_unreachable)
+        .resolve_with_warnings(&program)
         .map_err(|errors| report_compile_errors(errors, filename, source))?;
+    for warning in warnings {
+        let warning_with_src = warning.with_source_code(filename, source);
+        eprintln!("{:?}", miette::Report::new(warning_with_src));
+    }
     let mut interpreter = Interpreter::new();
     interpreter.set_source(source);
     interpreter
@@ -89,28 +182,15 @@ fn run_source(source: &str, filename: &str) -> Result<()> {
     Ok(())
 }
 
-/// Magic number at the start of every `.blox` file: ASCII "blox"
-const BLOX_MAGIC: &[u8; 4] = b"blox";
-
 fn save_chunk(compiled: &chunk::Chunk, path: &PathBuf) -> Result<()> {
-    let payload = rmp_serde::to_vec(compiled).context("serialize bytecode to MessagePack")?;
-    let mut bytes = Vec::with_capacity(BLOX_MAGIC.len() + payload.len());
-    bytes.extend_from_slice(BLOX_MAGIC);
-    bytes.extend_from_slice(&payload);
+    let bytes = compiled.to_blox()?;
     std::fs::write(path, bytes).with_context(|| format!("write bytecode to '{}'", path.display()))
 }
 
 fn load_chunk(path: &PathBuf) -> Result<chunk::Chunk> {
     let bytes =
         std::fs::read(path).with_context(|| format!("read bytecode from '{}'", path.display()))?;
-    if bytes.len() < BLOX_MAGIC.len() || &bytes[..BLOX_MAGIC.len()] != BLOX_MAGIC {
-        bail!(
-            "'{}' is not a valid .blox file (missing magic header)",
-            path.display()
-        );
-    }
-    rmp_serde::from_slice(&bytes[BLOX_MAGIC.len()..])
-        .context("deserialize bytecode from MessagePack")
+    chunk::Chunk::from_blox(&bytes).with_context(|| format!("load '{}'", path.display()))
 }
 
 fn is_bytecode_file(path: &PathBuf) -> Result<bool> {
@@ -119,7 +199,7 @@ fn is_bytecode_file(path: &PathBuf) -> Result<bool> {
     let mut header = [0u8; 4];
     use std::io::Read;
     match file.take(4).read(&mut header) {
-        Ok(4) => Ok(&header == BLOX_MAGIC),
+        Ok(4) => Ok(&header == chunk::BLOX_MAGIC),
         _ => Ok(false),
     }
 }
@@ -141,11 +221,6 @@ fn report_runtime_error(
     error: &vibe_lox::error::RuntimeError,
     source: Option<&str>,
 ) -> anyhow::Error {
-    // Don't report Return as an error
-    if error.is_return() {
-        return anyhow::anyhow!("unexpected return at top level");
-    }
-
     match source {
         Some(src) => {
             eprintln!("{}", error.display_with_line(src));
@@ -156,7 +231,10 @@ fn report_runtime_error(
     }
 
     if vibe_lox::error::backtrace_enabled() {
-        let bt = vibe_lox::error::format_backtrace(error.backtrace_frames());
+        let bt = vibe_lox::error::format_backtrace(
+            error.backtrace_frames(),
+            vibe_lox::error::backtrace_limit(),
+        );
         if !bt.is_empty() {
             eprint!("{bt}");
         }
@@ -168,6 +246,17 @@ fn report_runtime_error(
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Draw diagnostic carets with the same tab stops used for reported columns.
+    let tab_width = vibe_lox::error::tab_width();
+    miette::set_hook(Box::new(move |_| {
+        Box::new(
+            miette::MietteHandlerOpts::new()
+                .tab_width(tab_width)
+                .build(),
+        )
+    }))
+    .context("failed to install diagnostic handler")?;
+
     // Validate that the provided file exists before doing anything else
     if let Some(ref path) = cli.file
         && !path.exists()
@@ -178,8 +267,57 @@ fn main() -> Result<()> {
         bail!("file not found: '{}'", path.display());
     }
 
-    if cli.output.is_some() && !cli.compile_bytecode && !cli.compile_llvm && !cli.compile {
-        bail!("--output/-o can only be used with --compile-bytecode, --compile-llvm, or --compile");
+    if cli.output.is_some()
+        && !cli.compile_bytecode
+        && !cli.compile_llvm
+        && !cli.compile
+        && !cli.compile_obj
+    {
+        bail!(
+            "--output/-o can only be used with --compile-bytecode, --compile-llvm, --compile, or --compile-obj"
+        );
+    }
+
+    if cli.target.is_some() && !cli.compile && !cli.compile_obj {
+        bail!("--target can only be used with --compile or --compile-obj");
+    }
+
+    if cli.opt_level.is_some() && !cli.compile_llvm && !cli.compile && !cli.compile_obj {
+        bail!("--opt-level can only be used with --compile-llvm, --compile, or --compile-obj");
+    }
+
+    if cli.debug_info && !cli.compile_llvm && !cli.compile && !cli.compile_obj {
+        bail!("--debug-info can only be used with --compile-llvm, --compile, or --compile-obj");
+    }
+
+    if cli.annotate && !cli.compile_llvm {
+        bail!("--annotate can only be used with --compile-llvm");
+    }
+
+    if cli.optimize_bytecode && !cli.compile_bytecode && !cli.disassemble && !cli.stats {
+        bail!(
+            "--optimize-bytecode can only be used with --compile-bytecode, --disassemble, or --stats"
+        );
+    }
+
+    if cli.deny_unreachable
+        && (cli.file.is_none()
+            || cli.dump_tokens
+            || cli.dump_ast
+            || cli.emit_deps
+            || cli.compile_bytecode
+            || cli.compile_llvm
+            || cli.compile
+            || cli.compile_obj
+            || cli.jit
+            || cli.disassemble
+            || cli.stats)
+    {
+        bail!("--deny-unreachable can only be used when interpreting a .lox file");
+    }
+
+    if cli.strip_groupings && !cli.dump_ast {
+        bail!("--strip-groupings can only be used with --dump-ast");
     }
 
     if cli.dump_tokens {
@@ -198,9 +336,12 @@ fn main() -> Result<()> {
         let filename = get_filename(&cli);
         let tokens =
             scanner::scan(&source).map_err(|e| report_compile_errors(e, &filename, &source))?;
-        let program = LoxParser::new(tokens)
+        let mut program = LoxParser::new(tokens)
             .parse()
             .map_err(|e| report_compile_errors(e, &filename, &source))?;
+        if cli.strip_groupings {
+            ast::strip_groupings(&mut program);
+        }
         if cli.ast_format.as_str() == "json" {
             print!("{}", printer::to_json(&program))
         } else {
@@ -209,37 +350,61 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    // TODO: disassemble doesn't really make sense for source files, only for compiled code
-    // what's the use case for disassembly of source code ... looking at what would be generated
-    // for a source file?
+    if cli.emit_deps {
+        let source = read_source(&cli)?;
+        let filename = get_filename(&cli);
+        let tokens =
+            scanner::scan(&source).map_err(|e| report_compile_errors(e, &filename, &source))?;
+        let program = LoxParser::new(tokens)
+            .parse()
+            .map_err(|e| report_compile_errors(e, &filename, &source))?;
+        let locals = Resolver::new()
+            .resolve(&program)
+            .map_err(|e| report_compile_errors(e, &filename, &source))?;
+        for name in analysis::free_globals(&program, &locals) {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+
     if cli.disassemble {
         // autodetect whether input is bytecode or source
         if let Some(ref path) = cli.file
             && is_bytecode_file(path)?
         {
             let compiled = load_chunk(path)?;
-            print!(
-                "{}",
+            print_disassembly(
                 chunk::disassemble(&compiled, &path.display().to_string())
-                    .context("while disassembling bytecode")?
+                    .context("while disassembling bytecode")?,
             );
         } else {
             let source = read_source(&cli)?;
-            let compiled = compile_source(&source)?;
+            let compiled = compile_source(&cli, &source)?;
             let name = cli
                 .file
                 .as_ref()
                 .map(|p| p.display().to_string())
                 .unwrap_or_else(|| "<script>".to_string());
-            print!(
-                "{}",
-                chunk::disassemble(&compiled, &name).context("while disassembling bytecode")?
+            // Source is compiled only to show what the VM would run.
+            let banner = format!("disassembly of compiled {name} (not executed)");
+            print_disassembly(
+                chunk::disassemble_with_banner(&compiled, &name, &banner)
+                    .context("while disassembling bytecode")?,
             );
         }
 
         return Ok(());
     }
 
+    if cli.stats {
+        let compiled = match cli.file {
+            Some(ref path) if is_bytecode_file(path)? => load_chunk(path)?,
+            _ => compile_source(&cli, &read_source(&cli)?)?,
+        };
+        print!("{}", chunk::ChunkStats::collect(&compiled));
+        return Ok(());
+    }
+
     // Save bytecode to file (derived from input path: .lox -> .blox)
     if cli.compile_bytecode {
         let input_path = cli
@@ -251,7 +416,7 @@ fn main() -> Result<()> {
             .clone()
             .unwrap_or_else(|| input_path.with_extension("blox"));
         let source = read_source(&cli)?;
-        let compiled = compile_source(&source)?;
+        let compiled = compile_source(&cli, &source)?;
         save_chunk(&compiled, &output_path)?;
         if !cli.quiet {
             println!("Wrote bytecode to {}", output_path.display());
@@ -279,16 +444,76 @@ fn main() -> Result<()> {
             .parse()
             .map_err(|e| report_compile_errors(e, &filename, &source))?;
         let context = inkwell::context::Context::create();
-        let module = vibe_lox::codegen::compile_to_module(&context, &program, &source)
-            .context("compile to LLVM module")?;
-        vibe_lox::codegen::native::compile_to_executable(&module, &output_path)
-            .context("compile to native executable")?;
+        let module = vibe_lox::codegen::compile_to_module_with_options(
+            &context,
+            &program,
+            &source,
+            &llvm_compile_options(&cli, &filename),
+        )
+        .context("compile to LLVM module")?;
+        vibe_lox::codegen::native::compile_to_executable(
+            &module,
+            &output_path,
+            cli.target.as_deref(),
+        )
+        .context("compile to native executable")?;
         if !cli.quiet {
             println!("Compiled native executable: {}", output_path.display());
         }
         return Ok(());
     }
 
+    if cli.compile_obj {
+        let input_path = cli
+            .file
+            .as_ref()
+            .context("--compile-obj requires an input file")?;
+        if is_bytecode_file(input_path)? {
+            bail!("cannot compile .blox bytecode to an object file; use a .lox source file");
+        }
+        let output_path = cli
+            .output
+            .clone()
+            .unwrap_or_else(|| input_path.with_extension("o"));
+        let source = read_source(&cli)?;
+        let filename = get_filename(&cli);
+        let tokens =
+            scanner::scan(&source).map_err(|e| report_compile_errors(e, &filename, &source))?;
+        let program = LoxParser::new(tokens)
+            .parse()
+            .map_err(|e| report_compile_errors(e, &filename, &source))?;
+        let context = inkwell::context::Context::create();
+        let module = vibe_lox::codegen::compile_to_module_with_options(
+            &context,
+            &program,
+            &source,
+            &llvm_compile_options(&cli, &filename),
+        )
+        .context("compile to LLVM module")?;
+        vibe_lox::codegen::native::compile_to_object(&module, &output_path, cli.target.as_deref())
+            .context("compile to object file")?;
+        if !cli.quiet {
+            println!("Wrote object file to {}", output_path.display());
+        }
+        return Ok(());
+    }
+
+    if cli.jit {
+        let source = read_source(&cli)?;
+        let filename = get_filename(&cli);
+        let tokens =
+            scanner::scan(&source).map_err(|e| report_compile_errors(e, &filename, &source))?;
+        let program = LoxParser::new(tokens)
+            .parse()
+            .map_err(|e| report_compile_errors(e, &filename, &source))?;
+        let exit_code =
+            vibe_lox::codegen::jit_run(&program, &source).context("run program via LLVM JIT")?;
+        if exit_code != 0 {
+            std::process::exit(exit_code);
+        }
+        return Ok(());
+    }
+
     if cli.compile_llvm {
         let input_path = cli
             .file
@@ -305,7 +530,12 @@ fn main() -> Result<()> {
         let program = LoxParser::new(tokens)
             .parse()
             .map_err(|e| report_compile_errors(e, &filename, &source))?;
-        let ir = vibe_lox::codegen::compile(&program, &source).context("compile to LLVM IR")?;
+        let ir = vibe_lox::codegen::compile_with_options(
+            &program,
+            &source,
+            &llvm_compile_options(&cli, &filename),
+        )
+        .context("compile to LLVM IR")?;
         std::fs::write(&output_path, &ir)
             .with_context(|| format!("write LLVM IR to '{}'", output_path.display()))?;
         if !cli.quiet {
@@ -318,6 +548,9 @@ fn main() -> Result<()> {
         Some(ref path) => {
             // Autodetect: if the file starts with the "blox" magic, run via VM
             if is_bytecode_file(path)? {
+                if cli.deny_unreachable {
+                    bail!("--deny-unreachable can only be used when interpreting a .lox file");
+                }
                 if !cli.quiet {
                     println!("Running VM for {}", path.display());
                 }
@@ -331,12 +564,12 @@ fn main() -> Result<()> {
                 }
                 let source = read_source(&cli)?;
                 let filename = get_filename(&cli);
-                run_source(&source, &filename)?;
+                with_interpreter_stack(|| run_source(&source, &filename, cli.deny_unreachable))?;
             }
             Ok(())
         }
         None => {
-            vibe_lox::repl::run_repl();
+            with_interpreter_stack(vibe_lox::repl::run_repl);
             Ok(())
         }
     }
@@ -1,19 +1,17 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-
 use crate::ast::*;
 use crate::error::CompileError;
 use crate::scanner::token::{Span, Token, TokenKind};
 
-static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
-
-fn next_id() -> ExprId {
-    NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
-}
+/// Hidden local holding a desugared `switch` subject. The `$` keeps it out
+/// of reach of user code, which can't spell it as an identifier.
+const SWITCH_SUBJECT: &str = "$switch";
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
     errors: Vec<CompileError>,
+    /// The `ExprId` given to the next expression created.
+    next_id: ExprId,
 }
 
 impl Parser {
@@ -22,10 +20,29 @@ impl Parser {
             tokens,
             current: 0,
             errors: Vec::new(),
+            next_id: 0,
         }
     }
 
-    pub fn parse(mut self) -> Result<Program, Vec<CompileError>> {
+    /// Number expressions from `first` instead of 0. Programs whose resolved
+    /// locals share one map (as in the REPL) must use disjoint id ranges.
+    pub fn with_first_id(mut self, first: ExprId) -> Self {
+        self.next_id = first;
+        self
+    }
+
+    /// The first `ExprId` not used by anything parsed so far.
+    pub fn next_expr_id(&self) -> ExprId {
+        self.next_id
+    }
+
+    fn next_id(&mut self) -> ExprId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn parse(&mut self) -> Result<Program, Vec<CompileError>> {
         let mut declarations = Vec::new();
         while !self.is_at_end() {
             match self.declaration() {
@@ -39,16 +56,55 @@ impl Parser {
         if self.errors.is_empty() {
             Ok(Program { declarations })
         } else {
-            Err(self.errors)
+            Err(sort_errors(std::mem::take(&mut self.errors)))
         }
     }
 
+    /// Parse the whole token stream as one expression, with no trailing `;`.
+    pub fn parse_expression(&mut self) -> Result<Expr, Vec<CompileError>> {
+        if matches!(
+            self.peek().kind,
+            TokenKind::Var
+                | TokenKind::Const
+                | TokenKind::Fun
+                | TokenKind::Class
+                | TokenKind::Print
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::For
+                | TokenKind::Do
+                | TokenKind::Switch
+                | TokenKind::Return
+                | TokenKind::LeftBrace
+        ) {
+            let token = self.peek();
+            return Err(vec![CompileError::parse(
+                format!(
+                    "expected an expression, found a statement starting with '{}'",
+                    token.lexeme
+                ),
+                token.span.offset,
+                token.span.len,
+            )]);
+        }
+        let expr = self.expression().map_err(|e| vec![e])?;
+        if !self.is_at_end() {
+            let token = self.peek();
+            return Err(vec![CompileError::parse(
+                format!("expected end of expression, found '{}'", token.lexeme),
+                token.span.offset,
+                token.span.len.max(1),
+            )]);
+        }
+        Ok(expr)
+    }
+
     fn declaration(&mut self) -> Result<Decl, CompileError> {
         if self.check(TokenKind::Class) {
             self.class_declaration()
         } else if self.check(TokenKind::Fun) {
             self.fun_declaration()
-        } else if self.check(TokenKind::Var) {
+        } else if self.check(TokenKind::Var) || self.check(TokenKind::Const) {
             self.var_declaration()
         } else {
             self.statement().map(Decl::Statement)
@@ -115,7 +171,8 @@ impl Parser {
                     ));
                 }
                 params.push(self.expect_identifier("parameter name")?);
-                if !self.match_token(TokenKind::Comma) {
+                // A trailing comma is allowed before the closing paren
+                if !self.match_token(TokenKind::Comma) || self.check(TokenKind::RightParen) {
                     break;
                 }
             }
@@ -136,10 +193,13 @@ impl Parser {
 
     fn var_declaration(&mut self) -> Result<Decl, CompileError> {
         let start = self.current_span();
-        self.advance(); // consume 'var'
+        let is_const = self.advance().kind == TokenKind::Const; // consume 'var' or 'const'
         let name = self.expect_identifier("variable name")?;
 
-        let initializer = if self.match_token(TokenKind::Equal) {
+        let initializer = if is_const {
+            self.consume(TokenKind::Equal, "'=' after constant name")?;
+            Some(self.expression()?)
+        } else if self.match_token(TokenKind::Equal) {
             Some(self.expression()?)
         } else {
             None
@@ -150,6 +210,7 @@ impl Parser {
         Ok(Decl::Var(VarDecl {
             name,
             initializer,
+            is_const,
             span,
         }))
     }
@@ -167,6 +228,10 @@ impl Parser {
             self.while_statement()
         } else if self.check(TokenKind::For) {
             self.for_statement()
+        } else if self.check(TokenKind::Do) {
+            self.do_while_statement()
+        } else if self.check(TokenKind::Switch) {
+            self.switch_statement()
         } else {
             self.expression_statement()
         }
@@ -175,10 +240,21 @@ impl Parser {
     fn print_statement(&mut self) -> Result<Stmt, CompileError> {
         let start = self.current_span();
         self.advance(); // consume 'print'
-        let expression = self.expression()?;
+        let mut expressions = vec![self.expression()?];
+        while self.match_token(TokenKind::Comma) {
+            if expressions.len() >= 255 {
+                let span = self.current_span();
+                return Err(CompileError::parse(
+                    "can't print more than 255 values",
+                    span.offset,
+                    span.len,
+                ));
+            }
+            expressions.push(self.expression()?);
+        }
         self.consume(TokenKind::Semicolon, "';' after print value")?;
         let span = self.span_from(start);
-        Ok(Stmt::Print(PrintStmt { expression, span }))
+        Ok(Stmt::Print(PrintStmt { expressions, span }))
     }
 
     fn return_statement(&mut self) -> Result<Stmt, CompileError> {
@@ -255,6 +331,29 @@ impl Parser {
         }))
     }
 
+    /// Desugar `do body while (cond);` into the body followed by
+    /// `while (cond) body`, so the body always runs at least once.
+    fn do_while_statement(&mut self) -> Result<Stmt, CompileError> {
+        let start = self.current_span();
+        self.advance(); // consume 'do'
+        let body = self.statement()?;
+        self.consume(TokenKind::While, "'while' after do body")?;
+        self.consume(TokenKind::LeftParen, "'(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(TokenKind::RightParen, "')' after while condition")?;
+        self.consume(TokenKind::Semicolon, "';' after do-while loop")?;
+        let span = self.span_from(start);
+        let looped = Stmt::While(WhileStmt {
+            condition,
+            body: Box::new(body.clone()),
+            span,
+        });
+        Ok(Stmt::Block(BlockStmt {
+            declarations: vec![Decl::Statement(body), Decl::Statement(looped)],
+            span,
+        }))
+    }
+
     /// Desugar `for` into `while`.
     fn for_statement(&mut self) -> Result<Stmt, CompileError> {
         let start = self.current_span();
@@ -279,7 +378,7 @@ impl Parser {
             self.expression()?
         } else {
             Expr::Literal(LiteralExpr {
-                id: next_id(),
+                id: self.next_id(),
                 value: LiteralValue::Bool(true),
                 span: self.current_span(),
             })
@@ -330,6 +429,100 @@ impl Parser {
         Ok(body)
     }
 
+    /// Desugar `switch (x) { case a: ... default: ... }` into a block that
+    /// evaluates `x` once into a hidden local, followed by an `if`/`else`
+    /// chain comparing it with `==` against each case value in order.
+    /// Cases do not fall through.
+    fn switch_statement(&mut self) -> Result<Stmt, CompileError> {
+        let start = self.current_span();
+        self.advance(); // consume 'switch'
+        self.consume(TokenKind::LeftParen, "'(' after 'switch'")?;
+        let subject = self.expression()?;
+        self.consume(TokenKind::RightParen, "')' after switch value")?;
+        self.consume(TokenKind::LeftBrace, "'{' before switch body")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+        while !self.check(TokenKind::RightBrace) && !self.is_at_end() {
+            let case_start = self.current_span();
+            if default.is_some() {
+                return Err(CompileError::parse(
+                    "'default' must be the last case in a switch",
+                    case_start.offset,
+                    case_start.len,
+                ));
+            }
+            if self.match_token(TokenKind::Case) {
+                let value = self.expression()?;
+                self.consume(TokenKind::Colon, "':' after case value")?;
+                cases.push((value, self.case_body(case_start)?));
+            } else if self.match_token(TokenKind::Default) {
+                self.consume(TokenKind::Colon, "':' after 'default'")?;
+                default = Some(self.case_body(case_start)?);
+            } else {
+                let token = self.peek();
+                return Err(CompileError::parse(
+                    format!("expected 'case' or 'default', found '{}'", token.lexeme),
+                    token.span.offset,
+                    token.span.len.max(1),
+                ));
+            }
+        }
+        self.consume(TokenKind::RightBrace, "'}' after switch body")?;
+
+        let mut chain = default.map(Stmt::Block);
+        for (value, body) in cases.into_iter().rev() {
+            let value_span = value.span();
+            let condition = Expr::Binary(BinaryExpr {
+                id: self.next_id(),
+                left: Box::new(Expr::Variable(VariableExpr {
+                    id: self.next_id(),
+                    name: SWITCH_SUBJECT.to_string(),
+                    span: value_span,
+                })),
+                operator: BinaryOp::Equal,
+                right: Box::new(value),
+                span: value_span,
+            });
+            let span = body.span;
+            chain = Some(Stmt::If(IfStmt {
+                condition,
+                then_branch: Box::new(Stmt::Block(body)),
+                else_branch: chain.map(Box::new),
+                span,
+            }));
+        }
+
+        let subject_span = subject.span();
+        let mut declarations = vec![Decl::Var(VarDecl {
+            name: SWITCH_SUBJECT.to_string(),
+            initializer: Some(subject),
+            is_const: false,
+            span: subject_span,
+        })];
+        declarations.extend(chain.map(Decl::Statement));
+        Ok(Stmt::Block(BlockStmt {
+            declarations,
+            span: self.span_from(start),
+        }))
+    }
+
+    /// Declarations up to the next `case`, `default` or closing `}`.
+    fn case_body(&mut self, start: Span) -> Result<BlockStmt, CompileError> {
+        let mut declarations = Vec::new();
+        while !self.check(TokenKind::Case)
+            && !self.check(TokenKind::Default)
+            && !self.check(TokenKind::RightBrace)
+            && !self.is_at_end()
+        {
+            declarations.push(self.declaration()?);
+        }
+        Ok(BlockStmt {
+            declarations,
+            span: self.span_from(start),
+        })
+    }
+
     fn expression_statement(&mut self) -> Result<Stmt, CompileError> {
         let expression = self.expression()?;
         self.consume(TokenKind::Semicolon, "';' after expression")?;
@@ -342,7 +535,7 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, CompileError> {
-        let expr = self.or()?;
+        let expr = self.coalesce()?;
 
         if self.match_token(TokenKind::Equal) {
             let value = self.assignment()?;
@@ -354,7 +547,7 @@ impl Parser {
             match expr {
                 Expr::Variable(v) => {
                     return Ok(Expr::Assign(AssignExpr {
-                        id: next_id(),
+                        id: self.next_id(),
                         name: v.name,
                         value: Box::new(value),
                         span,
@@ -362,7 +555,7 @@ impl Parser {
                 }
                 Expr::Get(g) => {
                     return Ok(Expr::Set(SetExpr {
-                        id: next_id(),
+                        id: self.next_id(),
                         object: g.object,
                         name: g.name,
                         value: Box::new(value),
@@ -382,6 +575,25 @@ impl Parser {
         Ok(expr)
     }
 
+    fn coalesce(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.or()?;
+        while self.match_token(TokenKind::QuestionQuestion) {
+            let right = self.or()?;
+            let span = Span::new(
+                expr.span().offset,
+                right.span().offset + right.span().len - expr.span().offset,
+            );
+            expr = Expr::Logical(LogicalExpr {
+                id: self.next_id(),
+                left: Box::new(expr),
+                operator: LogicalOp::Coalesce,
+                right: Box::new(right),
+                span,
+            });
+        }
+        Ok(expr)
+    }
+
     fn or(&mut self) -> Result<Expr, CompileError> {
         let mut expr = self.and()?;
         while self.match_token(TokenKind::Or) {
@@ -391,7 +603,7 @@ impl Parser {
                 right.span().offset + right.span().len - expr.span().offset,
             );
             expr = Expr::Logical(LogicalExpr {
-                id: next_id(),
+                id: self.next_id(),
                 left: Box::new(expr),
                 operator: LogicalOp::Or,
                 right: Box::new(right),
@@ -410,7 +622,7 @@ impl Parser {
                 right.span().offset + right.span().len - expr.span().offset,
             );
             expr = Expr::Logical(LogicalExpr {
-                id: next_id(),
+                id: self.next_id(),
                 left: Box::new(expr),
                 operator: LogicalOp::And,
                 right: Box::new(right),
@@ -429,7 +641,7 @@ impl Parser {
                 right.span().offset + right.span().len - expr.span().offset,
             );
             expr = Expr::Binary(BinaryExpr {
-                id: next_id(),
+                id: self.next_id(),
                 left: Box::new(expr),
                 operator: op,
                 right: Box::new(right),
@@ -446,6 +658,7 @@ impl Parser {
             TokenKind::GreaterEqual,
             TokenKind::Less,
             TokenKind::LessEqual,
+            TokenKind::Is,
         ]) {
             let right = self.term()?;
             let span = Span::new(
@@ -453,7 +666,7 @@ impl Parser {
                 right.span().offset + right.span().len - expr.span().offset,
             );
             expr = Expr::Binary(BinaryExpr {
-                id: next_id(),
+                id: self.next_id(),
                 left: Box::new(expr),
                 operator: op,
                 right: Box::new(right),
@@ -472,7 +685,7 @@ impl Parser {
                 right.span().offset + right.span().len - expr.span().offset,
             );
             expr = Expr::Binary(BinaryExpr {
-                id: next_id(),
+                id: self.next_id(),
                 left: Box::new(expr),
                 operator: op,
                 right: Box::new(right),
@@ -484,14 +697,16 @@ impl Parser {
 
     fn factor(&mut self) -> Result<Expr, CompileError> {
         let mut expr = self.unary()?;
-        while let Some(op) = self.match_binary_op(&[TokenKind::Star, TokenKind::Slash]) {
+        while let Some(op) =
+            self.match_binary_op(&[TokenKind::Star, TokenKind::Slash, TokenKind::Percent])
+        {
             let right = self.unary()?;
             let span = Span::new(
                 expr.span().offset,
                 right.span().offset + right.span().len - expr.span().offset,
             );
             expr = Expr::Binary(BinaryExpr {
-                id: next_id(),
+                id: self.next_id(),
                 left: Box::new(expr),
                 operator: op,
                 right: Box::new(right),
@@ -516,13 +731,35 @@ impl Parser {
                 operand.span().offset + operand.span().len - start.offset,
             );
             return Ok(Expr::Unary(UnaryExpr {
-                id: next_id(),
+                id: self.next_id(),
                 operator: op,
                 operand: Box::new(operand),
                 span,
             }));
         }
-        self.call()
+        self.power()
+    }
+
+    /// `**` binds tighter than a unary operator on its left (`-2 ** 2` is
+    /// `-(2 ** 2)`) and is right-associative, since its right operand is
+    /// parsed as a unary that may itself contain `**`.
+    fn power(&mut self) -> Result<Expr, CompileError> {
+        let expr = self.call()?;
+        if !self.match_token(TokenKind::StarStar) {
+            return Ok(expr);
+        }
+        let right = self.unary()?;
+        let span = Span::new(
+            expr.span().offset,
+            right.span().offset + right.span().len - expr.span().offset,
+        );
+        Ok(Expr::Binary(BinaryExpr {
+            id: self.next_id(),
+            left: Box::new(expr),
+            operator: BinaryOp::Power,
+            right: Box::new(right),
+            span,
+        }))
     }
 
     fn call(&mut self) -> Result<Expr, CompileError> {
@@ -538,7 +775,7 @@ impl Parser {
                     self.previous_span().offset + self.previous_span().len - expr.span().offset,
                 );
                 expr = Expr::Get(GetExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     object: Box::new(expr),
                     name,
                     span,
@@ -564,7 +801,8 @@ impl Parser {
                     ));
                 }
                 arguments.push(self.expression()?);
-                if !self.match_token(TokenKind::Comma) {
+                // A trailing comma is allowed before the closing paren
+                if !self.match_token(TokenKind::Comma) || self.check(TokenKind::RightParen) {
                     break;
                 }
             }
@@ -575,7 +813,7 @@ impl Parser {
             self.previous_span().offset + self.previous_span().len - callee.span().offset,
         );
         Ok(Expr::Call(CallExpr {
-            id: next_id(),
+            id: self.next_id(),
             callee: Box::new(callee),
             arguments,
             span,
@@ -592,7 +830,7 @@ impl Parser {
                     .parse()
                     .expect("scanner guarantees valid number");
                 Ok(Expr::Literal(LiteralExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     value: LiteralValue::Number(value),
                     span: token.span,
                 }))
@@ -600,7 +838,7 @@ impl Parser {
             TokenKind::String => {
                 self.advance();
                 Ok(Expr::Literal(LiteralExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     value: LiteralValue::String(token.lexeme),
                     span: token.span,
                 }))
@@ -608,7 +846,7 @@ impl Parser {
             TokenKind::True => {
                 self.advance();
                 Ok(Expr::Literal(LiteralExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     value: LiteralValue::Bool(true),
                     span: token.span,
                 }))
@@ -616,7 +854,7 @@ impl Parser {
             TokenKind::False => {
                 self.advance();
                 Ok(Expr::Literal(LiteralExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     value: LiteralValue::Bool(false),
                     span: token.span,
                 }))
@@ -624,7 +862,7 @@ impl Parser {
             TokenKind::Nil => {
                 self.advance();
                 Ok(Expr::Literal(LiteralExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     value: LiteralValue::Nil,
                     span: token.span,
                 }))
@@ -632,7 +870,7 @@ impl Parser {
             TokenKind::This => {
                 self.advance();
                 Ok(Expr::This(ThisExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     span: token.span,
                 }))
             }
@@ -645,7 +883,7 @@ impl Parser {
                     self.previous_span().offset + self.previous_span().len - token.span.offset,
                 );
                 Ok(Expr::Super(SuperExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     method,
                     span,
                 }))
@@ -653,7 +891,7 @@ impl Parser {
             TokenKind::Identifier => {
                 self.advance();
                 Ok(Expr::Variable(VariableExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     name: token.lexeme,
                     span: token.span,
                 }))
@@ -667,7 +905,7 @@ impl Parser {
                     self.previous_span().offset + self.previous_span().len - token.span.offset,
                 );
                 Ok(Expr::Grouping(GroupingExpr {
-                    id: next_id(),
+                    id: self.next_id(),
                     expression: Box::new(expr),
                     span,
                 }))
@@ -727,9 +965,13 @@ impl Parser {
             let token = self.peek();
             // Point the error at the end of the previous token (where the
             // missing token should have been) rather than at the next token.
+            // At end of input there is nothing to underline, so the span is
+            // empty rather than reaching past the source.
             let (offset, len) = if self.current > 0 {
                 let prev = self.previous_span();
-                (prev.offset + prev.len, 1)
+                let end = prev.offset + prev.len;
+                let at_eof = token.kind == TokenKind::Eof && token.span.offset == end;
+                (end, if at_eof { 0 } else { 1 })
             } else {
                 (token.span.offset, token.span.len.max(1))
             };
@@ -778,9 +1020,12 @@ impl Parser {
                 TokenKind::Class
                 | TokenKind::Fun
                 | TokenKind::Var
+                | TokenKind::Const
                 | TokenKind::For
                 | TokenKind::If
                 | TokenKind::While
+                | TokenKind::Do
+                | TokenKind::Switch
                 | TokenKind::Print
                 | TokenKind::Return => return,
                 _ => {
@@ -797,20 +1042,33 @@ fn token_to_binary_op(kind: TokenKind) -> BinaryOp {
         TokenKind::Minus => BinaryOp::Subtract,
         TokenKind::Star => BinaryOp::Multiply,
         TokenKind::Slash => BinaryOp::Divide,
+        TokenKind::Percent => BinaryOp::Modulo,
         TokenKind::EqualEqual => BinaryOp::Equal,
         TokenKind::BangEqual => BinaryOp::NotEqual,
         TokenKind::Less => BinaryOp::Less,
         TokenKind::LessEqual => BinaryOp::LessEqual,
+        TokenKind::Is => BinaryOp::Is,
         TokenKind::Greater => BinaryOp::Greater,
         TokenKind::GreaterEqual => BinaryOp::GreaterEqual,
         _ => unreachable!("only called with matched operator tokens"),
     }
 }
 
+/// Order errors by source position and drop exact duplicates, so that
+/// output reads top-to-bottom regardless of recovery order. Errors at the
+/// same position are ordered by message, which puts duplicates next to each
+/// other for the dedup.
+fn sort_errors(mut errors: Vec<CompileError>) -> Vec<CompileError> {
+    errors.sort_by_cached_key(|e| (e.span().offset(), e.span().len(), e.to_string()));
+    errors.dedup_by(|a, b| a.span() == b.span() && a.to_string() == b.to_string());
+    errors
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::scanner;
+    use rstest::rstest;
 
     fn parse_ok(source: &str) -> Program {
         let tokens = scanner::scan(source).expect("scan should succeed");
@@ -827,16 +1085,67 @@ mod tests {
         crate::ast::printer::to_sexp(&program).trim().to_string()
     }
 
+    fn statement_expr_ids(program: &Program) -> Vec<ExprId> {
+        program
+            .declarations
+            .iter()
+            .map(|decl| match decl {
+                Decl::Statement(Stmt::Expression(stmt)) => stmt.expression.id(),
+                other => panic!("expected expression statement, got {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_parser_numbers_expressions_from_zero() {
+        let first = parse_ok("a; b;");
+        let second = parse_ok("c; d;");
+        assert_eq!(statement_expr_ids(&first), vec![0, 1]);
+        assert_eq!(statement_expr_ids(&second), vec![0, 1]);
+    }
+
+    #[test]
+    fn with_first_id_continues_numbering() {
+        let tokens = scanner::scan("a; b;").expect("scan should succeed");
+        let mut parser = Parser::new(tokens).with_first_id(10);
+        let program = parser.parse().expect("parse should succeed");
+        assert_eq!(statement_expr_ids(&program), vec![10, 11]);
+        assert_eq!(parser.next_expr_id(), 12);
+    }
+
     #[test]
     fn precedence_add_mul() {
         assert_eq!(parse_sexp("1 + 2 * 3;"), "(+ 1 (* 2 3))");
     }
 
+    #[test]
+    fn precedence_modulo() {
+        assert_eq!(parse_sexp("1 + 7 % 3 * 2;"), "(+ 1 (* (% 7 3) 2))");
+    }
+
     #[test]
     fn precedence_group() {
         assert_eq!(parse_sexp("(1 + 2) * 3;"), "(* (group (+ 1 2)) 3)");
     }
 
+    #[rstest]
+    #[case("x is Foo;", "(is x Foo)")]
+    #[case("x is Foo == true;", "(== (is x Foo) true)")]
+    #[case("a.b is Foo and ok;", "(and (is (. a b) Foo) ok)")]
+    fn is_operator(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse_sexp(source), expected);
+    }
+
+    #[rstest]
+    #[case("2 ** 3 ** 2;", "(** 2 (** 3 2))")]
+    #[case("-2 ** 2;", "(- (** 2 2))")]
+    #[case("2 ** -1;", "(** 2 (- 1))")]
+    #[case("2 * 3 ** 2;", "(* 2 (** 3 2))")]
+    #[case("a.b ** 2;", "(** (. a b) 2)")]
+    fn power_precedence(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse_sexp(source), expected);
+    }
+
     #[test]
     fn unary_negate() {
         assert_eq!(parse_sexp("-1;"), "(- 1)");
@@ -847,6 +1156,11 @@ mod tests {
         assert_eq!(parse_sexp("!true;"), "(! true)");
     }
 
+    #[test]
+    fn print_multiple_values() {
+        assert_eq!(parse_sexp("print 1, 2 + 3, x;"), "(print 1 (+ 2 3) x)");
+    }
+
     #[test]
     fn var_declaration() {
         assert_eq!(parse_sexp("var x = 42;"), "(var x 42)");
@@ -880,6 +1194,108 @@ mod tests {
         assert!(sexp.contains("var i"));
     }
 
+    #[test]
+    fn for_desugaring_keeps_clause_spans() {
+        let source = "for (var i = 0; i < 10; i = i + 1) print i;";
+        let program = parse_ok(source);
+        let text = |span: Span| &source[span.offset..span.offset + span.len];
+        let Decl::Statement(Stmt::Block(outer)) = &program.declarations[0] else {
+            panic!("expected the initializer block");
+        };
+        let Decl::Statement(Stmt::While(w)) = &outer.declarations[1] else {
+            panic!("expected the desugared while");
+        };
+        assert_eq!(text(w.condition.span()), "i < 10");
+        let Stmt::Block(body) = w.body.as_ref() else {
+            panic!("expected the body and increment block");
+        };
+        let Decl::Statement(increment) = &body.declarations[1] else {
+            panic!("expected the increment statement");
+        };
+        assert_eq!(text(increment.span()), "i = i + 1");
+    }
+
+    #[test]
+    fn do_while_desugars_to_body_then_while() {
+        assert_eq!(
+            parse_sexp("do print i; while (i < 3);"),
+            "(block (print i) (while (< i 3) (print i)))"
+        );
+    }
+
+    #[test]
+    fn do_while_requires_semicolon() {
+        let errors = parse_err("do print 1; while (false)");
+        assert_eq!(
+            error_message(&errors[0]),
+            "expected ';' after do-while loop, found ''"
+        );
+    }
+
+    #[test]
+    fn switch_desugars_to_if_chain() {
+        assert_eq!(
+            parse_sexp("switch (x) { case 1: print 1; case 2: print 2; default: print 3; }"),
+            "(block (var $switch x) (if (== $switch 1) (block (print 1)) \
+             (if (== $switch 2) (block (print 2)) (block (print 3)))))"
+        );
+    }
+
+    #[rstest]
+    #[case("switch (x) { }", "(block (var $switch x))")]
+    #[case(
+        "switch (x) { default: print 1; }",
+        "(block (var $switch x) (block (print 1)))"
+    )]
+    #[case(
+        "switch (x) { case 1: case 2: print 2; }",
+        "(block (var $switch x) (if (== $switch 1) (block) (if (== $switch 2) (block (print 2)))))"
+    )]
+    fn switch_edge_cases(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse_sexp(source), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "switch (x) { print 1; }",
+        "expected 'case' or 'default', found 'print'"
+    )]
+    #[case(
+        "switch (x) { default: print 1; case 1: print 2; }",
+        "'default' must be the last case in a switch"
+    )]
+    #[case(
+        "switch (x) { case 1 print 1; }",
+        "expected ':' after case value, found 'print'"
+    )]
+    fn switch_errors(#[case] source: &str, #[case] expected: &str) {
+        let errors = parse_err(source);
+        assert_eq!(error_message(&errors[0]), expected);
+    }
+
+    #[rstest]
+    #[case("a ?? b;", "(?? a b)")]
+    #[case("a ?? b ?? c;", "(?? (?? a b) c)")]
+    #[case("a ?? b or c;", "(?? a (or b c))")]
+    #[case("x = a ?? b;", "(= x (?? a b))")]
+    fn coalesce_precedence(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse_sexp(source), expected);
+    }
+
+    #[test]
+    fn const_decl() {
+        assert_eq!(parse_sexp("const PI = 3.14;"), "(const PI 3.14)");
+    }
+
+    #[test]
+    fn const_requires_initializer() {
+        let errors = parse_err("const PI;");
+        assert_eq!(
+            error_message(&errors[0]),
+            "expected '=' after constant name, found ';'"
+        );
+    }
+
     #[test]
     fn function_decl() {
         assert_eq!(
@@ -888,6 +1304,24 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case("fun f(a, b,) {}", "(fun f (a b))")]
+    #[case("class C { m(a,) {} }", "(class C (fun m (a)))")]
+    #[case("foo(1, 2,);", "(call foo 1 2)")]
+    #[case("foo(1,)(2,);", "(call (call foo 1) 2)")]
+    fn trailing_commas(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse_sexp(source), expected);
+    }
+
+    #[rstest]
+    #[case("foo(,);", "expected expression, found ','")]
+    #[case("foo(1,,);", "expected expression, found ','")]
+    #[case("fun f(,) {}", "expected parameter name")]
+    fn stray_commas_are_errors(#[case] source: &str, #[case] expected: &str) {
+        let errors = parse_err(source);
+        assert_eq!(error_message(&errors[0]), expected);
+    }
+
     #[test]
     fn class_with_methods() {
         let sexp = parse_sexp("class Foo { bar() { return 1; } }");
@@ -901,11 +1335,40 @@ mod tests {
         assert!(sexp.contains("< Bar"));
     }
 
+    #[rstest]
+    #[case("super.m();", "(call (super m))")]
+    #[case("super.m().n;", "(. (call (super m)) n)")]
+    #[case("super.m(1)(2);", "(call (call (super m) 1) 2)")]
+    #[case("super.m().n();", "(call (. (call (super m)) n))")]
+    #[case("super.m.n;", "(. (super m) n)")]
+    fn super_call_chaining(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse_sexp(source), expected);
+    }
+
+    #[test]
+    fn super_chain_span_covers_whole_expression() {
+        let source = "super.m().n;";
+        let program = parse_ok(source);
+        let Decl::Statement(Stmt::Expression(stmt)) = &program.declarations[0] else {
+            panic!("expected expression statement");
+        };
+        let Expr::Get(get) = &stmt.expression else {
+            panic!("expected property access, got {:?}", stmt.expression);
+        };
+        assert_eq!((get.span.offset, get.span.len), (0, "super.m().n".len()));
+        let Expr::Call(call) = get.object.as_ref() else {
+            panic!("expected call, got {:?}", get.object);
+        };
+        assert_eq!((call.span.offset, call.span.len), (0, "super.m()".len()));
+        assert_eq!(call.callee.span().len, "super.m".len());
+    }
+
     fn error_message(error: &CompileError) -> &str {
         match error {
             CompileError::Parse { message, .. } => message,
             CompileError::Scan { message, .. } => message,
             CompileError::Resolve { message, .. } => message,
+            CompileError::Compile { message, .. } => message,
         }
     }
 
@@ -913,10 +1376,55 @@ mod tests {
         match error {
             CompileError::Parse { span, .. }
             | CompileError::Scan { span, .. }
-            | CompileError::Resolve { span, .. } => span.offset().into(),
+            | CompileError::Resolve { span, .. }
+            | CompileError::Compile { span, .. } => span.offset().into(),
         }
     }
 
+    #[test]
+    fn errors_are_reported_in_source_order() {
+        let source = "var a = ;\nvar b = 1;\nvar c = ;\n";
+        let errors = parse_err(source);
+        let offsets: Vec<usize> = errors.iter().map(error_offset).collect();
+        assert_eq!(offsets, vec![8, 29]);
+    }
+
+    #[test]
+    fn sort_errors_orders_by_offset_and_removes_duplicates() {
+        let errors = vec![
+            CompileError::parse("expected expression", 28, 1),
+            CompileError::parse("expected expression", 8, 1),
+            CompileError::parse("expected expression", 28, 1),
+            CompileError::parse("expected ';'", 28, 1),
+        ];
+        let sorted: Vec<(usize, String)> = sort_errors(errors)
+            .iter()
+            .map(|e| (error_offset(e), error_message(e).to_string()))
+            .collect();
+        assert_eq!(
+            sorted,
+            vec![
+                (8, "expected expression".to_string()),
+                (28, "expected ';'".to_string()),
+                (28, "expected expression".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_errors_removes_duplicates_split_by_another_error() {
+        let errors = vec![
+            CompileError::parse("expected expression", 28, 1),
+            CompileError::parse("expected ';'", 28, 1),
+            CompileError::parse("expected expression", 28, 1),
+        ];
+        let messages: Vec<String> = sort_errors(errors)
+            .iter()
+            .map(|e| error_message(e).to_string())
+            .collect();
+        assert_eq!(messages, vec!["expected ';'", "expected expression"]);
+    }
+
     #[test]
     fn error_recovery() {
         let errors = parse_err("var x = ; var y = 1;");
@@ -941,6 +1449,28 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case::print("print 1", "';'")]
+    #[case::var("var a = 1", "';'")]
+    #[case::trailing_whitespace("print 1  \n", "';'")]
+    #[case::grouping("print (1", "')'")]
+    fn missing_token_at_end_of_file_points_at_eof(#[case] source: &str, #[case] expected: &str) {
+        let errors = parse_err(source);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(error_message(&errors[0]).contains(expected));
+        let CompileError::Parse { span, .. } = &errors[0] else {
+            panic!("expected a parse error, got {:?}", errors[0]);
+        };
+        let end = source.trim_end().len();
+        assert_eq!(
+            span.offset(),
+            end,
+            "error should sit right after the last token"
+        );
+        let eof = if end == source.len() { 0 } else { 1 };
+        assert_eq!(span.len(), eof, "a span at end of input should be empty");
+    }
+
     #[test]
     fn block_error_recovery_no_cascade() {
         // A missing ';' inside a block should produce one error, not cascade
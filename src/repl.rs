@@ -1,4 +1,6 @@
-use std::io::{self, Write};
+use std::ffi::OsString;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
 
 use rustyline::completion::{Completer, Pair};
 use rustyline::highlight::Highlighter;
@@ -6,9 +8,11 @@ use rustyline::hint::Hinter;
 use rustyline::validate::Validator;
 use rustyline::{CompletionType, Config, Context, Editor, Helper};
 
+use crate::ast::{Decl, Program, Stmt};
+use crate::error::{CompileError, RuntimeError};
 use crate::interpreter::Interpreter;
-use crate::interpreter::resolver::Resolver;
-use crate::parser::Parser;
+use crate::interpreter::callable::Callable;
+use crate::interpreter::value::Value;
 use crate::scanner;
 
 // Long-form commands offered for tab completion. Short forms (\h, \q, etc.)
@@ -18,6 +22,9 @@ const COMMANDS: &[(&str, &str)] = &[
     ("\\quit", "exit the REPL"),
     ("\\clear", "clear the terminal screen"),
     ("\\version", "show the interpreter version"),
+    ("\\load", "load and run a Lox file"),
+    ("\\reset", "discard all definitions"),
+    ("\\vars", "list defined global variables"),
 ];
 
 struct ReplHelper;
@@ -47,8 +54,31 @@ impl Highlighter for ReplHelper {}
 impl Validator for ReplHelper {}
 impl Helper for ReplHelper {}
 
+/// Environment variable that overrides the REPL history file location.
+const HISTORY_ENV: &str = "LOX_HISTORY";
+
 /// Run the interactive REPL. Environment persists across lines.
+///
+/// When stdin is not a terminal (e.g. piped input) lines are read with plain
+/// `BufRead` and no history is kept.
 pub fn run_repl() {
+    let mut interpreter = Interpreter::new();
+    if !io::stdin().is_terminal() {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => {
+                    eprintln!("read error: {e}");
+                    break;
+                }
+            };
+            if process_line(&mut interpreter, &line) == LineOutcome::Quit {
+                break;
+            }
+        }
+        return;
+    }
+
     let config = Config::builder()
         .completion_type(CompletionType::List)
         .build();
@@ -57,7 +87,11 @@ pub fn run_repl() {
         Editor::with_config(config).expect("rustyline init cannot fail with valid config");
     rl.set_helper(Some(ReplHelper));
 
-    let mut interpreter = Interpreter::new();
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        let _ = rl.load_history(path);
+    }
 
     loop {
         let line = match rl.readline("> ") {
@@ -70,84 +104,193 @@ pub fn run_repl() {
             }
         };
 
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
+        match process_line(&mut interpreter, &line) {
+            LineOutcome::Quit => break,
+            // Only Lox code goes into history, keeping it focused on code.
+            LineOutcome::Code => {
+                let _ = rl.add_history_entry(line.trim());
+            }
+            LineOutcome::Skipped => {}
         }
+    }
 
-        if trimmed.starts_with('\\') {
-            let mut parts = trimmed.split_whitespace();
-            let cmd = parts.next().unwrap_or("");
-            let args: Vec<&str> = parts.collect();
-            if handle_command(cmd, &args) {
-                break;
-            }
-            continue;
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
         }
+        if let Err(e) = rl.save_history(path) {
+            eprintln!("warning: cannot save history to '{}': {e}", path.display());
+        }
+    }
+}
 
-        // Only Lox expressions go into history, keeping it focused on code.
-        let _ = rl.add_history_entry(trimmed);
+/// What a single REPL input line turned out to be.
+#[derive(Debug, PartialEq)]
+enum LineOutcome {
+    /// Blank line or a backslash command that keeps the session going.
+    Skipped,
+    /// Lox code that was run (successfully or not).
+    Code,
+    /// A command asking the REPL to exit.
+    Quit,
+}
 
-        // Auto-wrap bare expressions: if the line doesn't end with ';' or '}',
-        // wrap it as `print <expr>;` so the user sees the result.
-        let source = if is_bare_expression(trimmed) {
-            format!("print {trimmed};")
+/// Dispatch one input line to either the command handler or the Lox pipeline.
+fn process_line(interpreter: &mut Interpreter, line: &str) -> LineOutcome {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return LineOutcome::Skipped;
+    }
+
+    if trimmed.starts_with('\\') {
+        let mut parts = trimmed.split_whitespace();
+        let cmd = parts.next().unwrap_or("");
+        let args: Vec<&str> = parts.collect();
+        return if handle_command(cmd, &args, interpreter) {
+            LineOutcome::Quit
         } else {
-            trimmed.to_string()
+            LineOutcome::Skipped
         };
+    }
 
-        let tokens = match scanner::scan(&source) {
-            Ok(t) => t,
-            Err(errors) => {
-                for error in errors {
-                    let error_with_src = error.with_source_code("<repl>", &source);
-                    eprintln!("{:?}", miette::Report::new(error_with_src));
-                }
-                continue;
-            }
-        };
+    if let Some(value) = eval_line(interpreter, trimmed) {
+        println!("{value}");
+    }
+    LineOutcome::Code
+}
 
-        let program = match Parser::new(tokens).parse() {
-            Ok(p) => p,
-            Err(errors) => {
-                for error in errors {
-                    let error_with_src = error.with_source_code("<repl>", &source);
-                    eprintln!("{:?}", miette::Report::new(error_with_src));
-                }
-                continue;
-            }
-        };
+/// Location of the persistent history file: `$LOX_HISTORY` if set, otherwise
+/// `vibe-lox/history` under the user's data directory.
+fn history_path() -> Option<PathBuf> {
+    resolve_history_path(
+        std::env::var_os(HISTORY_ENV),
+        std::env::var_os("XDG_DATA_HOME"),
+        std::env::var_os("HOME"),
+    )
+}
 
-        let locals = match Resolver::new().resolve(&program) {
-            Ok(l) => l,
-            Err(errors) => {
-                for error in errors {
-                    let error_with_src = error.with_source_code("<repl>", &source);
-                    eprintln!("{:?}", miette::Report::new(error_with_src));
-                }
-                continue;
-            }
-        };
+fn resolve_history_path(
+    explicit: Option<OsString>,
+    xdg_data_home: Option<OsString>,
+    home: Option<OsString>,
+) -> Option<PathBuf> {
+    let non_empty = |v: Option<OsString>| v.filter(|s| !s.is_empty());
+    if let Some(path) = non_empty(explicit) {
+        return Some(PathBuf::from(path));
+    }
+    let data_dir = match non_empty(xdg_data_home) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(non_empty(home)?).join(".local").join("share"),
+    };
+    Some(data_dir.join("vibe-lox").join("history"))
+}
 
-        interpreter.merge_locals(locals);
-        interpreter.set_source(&source);
-        if let Err(e) = interpreter.interpret_additional(&program)
-            && !e.is_return()
-        {
-            eprintln!("{}", e.display_with_line(&source));
-            if crate::error::backtrace_enabled() {
-                let bt = crate::error::format_backtrace(e.backtrace_frames());
-                if !bt.is_empty() {
-                    eprint!("{bt}");
-                }
+/// Run one line typed at the prompt. A line consisting of a single expression
+/// statement is evaluated rather than executed, and its value is returned so
+/// the caller can echo it; anything else runs for effect and returns `None`.
+fn eval_line(interpreter: &mut Interpreter, line: &str) -> Option<Value> {
+    // The trailing ';' is optional after a bare expression at the prompt.
+    let source = if is_bare_expression(line) {
+        format!("{line};")
+    } else {
+        line.to_string()
+    };
+    let program = prepare_source(interpreter, &source, "<repl>")?;
+    if let [Decl::Statement(Stmt::Expression(stmt))] = program.declarations.as_slice() {
+        match interpreter.evaluate(&stmt.expression) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                report_runtime_error(&e, &source);
+                None
             }
         }
+    } else {
+        run_program(interpreter, &program, &source);
+        None
+    }
+}
+
+/// Scan, parse, resolve and run `source` in the REPL's persistent environment,
+/// reporting any errors to stderr. Returns `true` if the source ran cleanly.
+fn eval_source(interpreter: &mut Interpreter, source: &str, filename: &str) -> bool {
+    match prepare_source(interpreter, source, filename) {
+        Some(program) => run_program(interpreter, &program, source),
+        None => false,
+    }
+}
+
+/// Scan, parse and resolve `source`, registering its resolved locals with the
+/// interpreter. Compile errors are reported to stderr and yield `None`.
+fn prepare_source(interpreter: &mut Interpreter, source: &str, filename: &str) -> Option<Program> {
+    let tokens = match scanner::scan(source) {
+        Ok(t) => t,
+        Err(errors) => {
+            report_compile_errors(errors, filename, source);
+            return None;
+        }
+    };
+
+    let program = match interpreter.parse_additional(tokens) {
+        Ok(p) => p,
+        Err(errors) => {
+            report_compile_errors(errors, filename, source);
+            return None;
+        }
+    };
+
+    if let Err(errors) = interpreter.resolve_additional(&program) {
+        report_compile_errors(errors, filename, source);
+        return None;
+    }
+
+    interpreter.set_source(source);
+    Some(program)
+}
+
+fn run_program(interpreter: &mut Interpreter, program: &Program, source: &str) -> bool {
+    match interpreter.interpret_additional(program) {
+        Err(e) => {
+            report_runtime_error(&e, source);
+            false
+        }
+        Ok(()) => true,
+    }
+}
+
+fn report_runtime_error(e: &RuntimeError, source: &str) {
+    eprintln!("{}", e.display_with_line(source));
+    if crate::error::backtrace_enabled() {
+        let bt =
+            crate::error::format_backtrace(e.backtrace_frames(), crate::error::backtrace_limit());
+        if !bt.is_empty() {
+            eprint!("{bt}");
+        }
+    }
+}
+
+fn report_compile_errors(errors: Vec<CompileError>, filename: &str, source: &str) {
+    for error in errors {
+        let error_with_src = error.with_source_code(filename, source);
+        eprintln!("{:?}", miette::Report::new(error_with_src));
+    }
+}
+
+/// Read the file at `path` and run it in the current REPL environment so its
+/// definitions stay available to later lines.
+fn load_file(interpreter: &mut Interpreter, path: &str) -> bool {
+    match std::fs::read_to_string(path) {
+        Ok(source) => eval_source(interpreter, &source, path),
+        Err(e) => {
+            eprintln!("error: cannot read '{path}': {e}");
+            false
+        }
     }
 }
 
 /// Dispatch a backslash command. Returns `true` if the REPL should exit.
-fn handle_command(cmd: &str, args: &[&str]) -> bool {
-    if !args.is_empty() {
+fn handle_command(cmd: &str, args: &[&str], interpreter: &mut Interpreter) -> bool {
+    let takes_args = matches!(cmd, "\\l" | "\\load");
+    if !takes_args && !args.is_empty() {
         eprintln!("warning: '{cmd}' does not accept arguments");
     }
     match cmd {
@@ -157,6 +300,9 @@ fn handle_command(cmd: &str, args: &[&str]) -> bool {
             println!("  \\q, \\quit     Exit the REPL");
             println!("  \\c, \\clear    Clear the terminal screen");
             println!("  \\v, \\version  Show the interpreter version");
+            println!("  \\l, \\load     Load and run a Lox file: \\load <path>");
+            println!("  \\r, \\reset    Discard all definitions and start fresh");
+            println!("      \\vars     List defined global variables and their values");
             false
         }
         "\\q" | "\\quit" => true,
@@ -169,6 +315,24 @@ fn handle_command(cmd: &str, args: &[&str]) -> bool {
             println!("{}", env!("CARGO_PKG_VERSION"));
             false
         }
+        "\\l" | "\\load" => {
+            if args.is_empty() {
+                eprintln!("usage: \\load <path>");
+            } else {
+                load_file(interpreter, &args.join(" "));
+            }
+            false
+        }
+        "\\r" | "\\reset" => {
+            *interpreter = Interpreter::new();
+            false
+        }
+        "\\vars" => {
+            for line in global_vars(interpreter) {
+                println!("{line}");
+            }
+            false
+        }
         other => {
             eprintln!("Unknown command '{other}'. Type \\help for available commands.");
             false
@@ -176,6 +340,20 @@ fn handle_command(cmd: &str, args: &[&str]) -> bool {
     }
 }
 
+/// `name = value` lines for each user-defined global, skipping the natives
+/// every session starts with.
+fn global_vars(interpreter: &Interpreter) -> Vec<String> {
+    let globals = interpreter.environment().borrow();
+    globals
+        .names()
+        .into_iter()
+        .filter_map(|name| match globals.get(&name)? {
+            Value::Function(Callable::Native(_)) => None,
+            value => Some(format!("{name} = {value}")),
+        })
+        .collect()
+}
+
 /// Return the commands from `COMMANDS` whose name starts with `prefix`.
 fn complete_commands(prefix: &str) -> Vec<Pair> {
     COMMANDS
@@ -188,9 +366,9 @@ fn complete_commands(prefix: &str) -> Vec<Pair> {
         .collect()
 }
 
-/// Heuristic: treat the line as a bare expression if it doesn't end with
-/// ';' or '}' and doesn't start with a keyword that begins a declaration
-/// or statement.
+/// Heuristic: treat the line as a bare expression missing its trailing ';'
+/// if it doesn't end with ';' or '}' and doesn't start with a keyword that
+/// begins a declaration or statement.
 fn is_bare_expression(line: &str) -> bool {
     if line.ends_with(';') || line.ends_with('}') {
         return false;
@@ -198,7 +376,18 @@ fn is_bare_expression(line: &str) -> bool {
     let first_word = line.split_whitespace().next().unwrap_or("");
     !matches!(
         first_word,
-        "var" | "fun" | "class" | "if" | "while" | "for" | "print" | "return" | "{"
+        "var"
+            | "const"
+            | "fun"
+            | "class"
+            | "if"
+            | "while"
+            | "for"
+            | "do"
+            | "switch"
+            | "print"
+            | "return"
+            | "{"
     )
 }
 
@@ -219,31 +408,35 @@ mod tests {
 
     #[test]
     fn handle_command_quit_returns_true() {
-        assert!(handle_command("\\quit", &[]));
-        assert!(handle_command("\\q", &[]));
+        assert!(handle_command("\\quit", &[], &mut Interpreter::new()));
+        assert!(handle_command("\\q", &[], &mut Interpreter::new()));
     }
 
     #[test]
     fn handle_command_non_quit_returns_false() {
-        assert!(!handle_command("\\help", &[]));
-        assert!(!handle_command("\\h", &[]));
-        assert!(!handle_command("\\clear", &[]));
-        assert!(!handle_command("\\c", &[]));
-        assert!(!handle_command("\\version", &[]));
-        assert!(!handle_command("\\v", &[]));
-        assert!(!handle_command("\\unknown", &[]));
+        assert!(!handle_command("\\help", &[], &mut Interpreter::new()));
+        assert!(!handle_command("\\h", &[], &mut Interpreter::new()));
+        assert!(!handle_command("\\clear", &[], &mut Interpreter::new()));
+        assert!(!handle_command("\\c", &[], &mut Interpreter::new()));
+        assert!(!handle_command("\\version", &[], &mut Interpreter::new()));
+        assert!(!handle_command("\\v", &[], &mut Interpreter::new()));
+        assert!(!handle_command("\\unknown", &[], &mut Interpreter::new()));
     }
 
     #[test]
     fn handle_command_quit_with_args_still_exits() {
         // Extra args trigger a warning but quit should still return true.
-        assert!(handle_command("\\quit", &["extra"]));
-        assert!(handle_command("\\q", &["extra"]));
+        assert!(handle_command(
+            "\\quit",
+            &["extra"],
+            &mut Interpreter::new()
+        ));
+        assert!(handle_command("\\q", &["extra"], &mut Interpreter::new()));
     }
 
     #[test]
     fn complete_commands_all_on_backslash_only() {
-        assert_eq!(complete_commands("\\").len(), 4);
+        assert_eq!(complete_commands("\\").len(), COMMANDS.len());
     }
 
     #[test]
@@ -264,4 +457,131 @@ mod tests {
     fn complete_commands_empty_for_unknown_prefix() {
         assert!(complete_commands("\\xyz").is_empty());
     }
+
+    #[test]
+    fn load_makes_definitions_available() {
+        let path = std::env::temp_dir().join(format!("repl_load_{}.lox", std::process::id()));
+        std::fs::write(
+            &path,
+            "fun twice(n) { return n * 2; }\nvar loaded = twice(21);\n",
+        )
+        .expect("write temp file");
+        let mut interpreter = Interpreter::new();
+        assert!(!eval_source(&mut interpreter, "loaded;", "<repl>"));
+
+        let arg = path.to_str().expect("temp path is valid UTF-8");
+        assert!(!handle_command("\\load", &[arg], &mut interpreter));
+        assert!(eval_source(&mut interpreter, "loaded;", "<repl>"));
+        assert!(eval_source(&mut interpreter, "twice(loaded);", "<repl>"));
+        std::fs::remove_file(&path).expect("remove temp file");
+    }
+
+    #[test]
+    fn load_missing_file_keeps_session() {
+        let mut interpreter = Interpreter::new();
+        assert!(!load_file(&mut interpreter, "/nonexistent/file.lox"));
+        assert!(!handle_command(
+            "\\load",
+            &["/nonexistent/file.lox"],
+            &mut interpreter
+        ));
+        assert!(!handle_command("\\load", &[], &mut interpreter));
+        assert!(eval_source(&mut interpreter, "var x = 1;", "<repl>"));
+    }
+
+    #[test]
+    fn reset_discards_definitions() {
+        let mut interpreter = Interpreter::new();
+        assert!(eval_source(&mut interpreter, "var x = 1;", "<repl>"));
+        assert!(!handle_command("\\reset", &[], &mut interpreter));
+        assert!(!eval_source(&mut interpreter, "x;", "<repl>"));
+    }
+
+    #[test]
+    fn consts_stay_constant_across_lines() {
+        let mut interpreter = Interpreter::new();
+        assert!(eval_source(&mut interpreter, "const PI = 3;", "<repl>"));
+        assert!(!eval_source(&mut interpreter, "PI = 4;", "<repl>"));
+        assert!(eval_line(&mut interpreter, "PI = 4").is_none());
+        assert_eq!(
+            eval_line(&mut interpreter, "PI").map(|v| v.to_string()),
+            Some("3".to_string())
+        );
+        assert!(!handle_command("\\reset", &[], &mut interpreter));
+        assert!(eval_source(
+            &mut interpreter,
+            "var PI = 1; PI = 4;",
+            "<repl>"
+        ));
+    }
+
+    #[test]
+    fn vars_lists_user_globals() {
+        let mut interpreter = Interpreter::new();
+        assert!(eval_source(
+            &mut interpreter,
+            "var b = \"two\"; var a = 1;",
+            "<repl>"
+        ));
+        assert_eq!(global_vars(&interpreter), vec!["a = 1", "b = two"]);
+        assert!(!handle_command("\\vars", &[], &mut interpreter));
+    }
+
+    #[test]
+    fn eval_line_echoes_expression_value() {
+        let mut interpreter = Interpreter::new();
+        let echo = |i: &mut Interpreter, line| eval_line(i, line).map(|v| v.to_string());
+        assert_eq!(echo(&mut interpreter, "1 + 2"), Some("3".to_string()));
+        assert_eq!(echo(&mut interpreter, "1 + 2;"), Some("3".to_string()));
+        assert_eq!(echo(&mut interpreter, "var x = \"hi\";"), None);
+        assert_eq!(echo(&mut interpreter, "x"), Some("hi".to_string()));
+        assert_eq!(echo(&mut interpreter, "print x;"), None);
+        assert_eq!(echo(&mut interpreter, "fun f() { return 7; }"), None);
+        assert_eq!(echo(&mut interpreter, "f()"), Some("7".to_string()));
+    }
+
+    #[test]
+    fn eval_line_errors_do_not_echo() {
+        let mut interpreter = Interpreter::new();
+        assert!(eval_line(&mut interpreter, "undefined_name").is_none());
+        assert!(eval_line(&mut interpreter, "1 +").is_none());
+    }
+
+    #[test]
+    fn process_line_classifies_input() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(process_line(&mut interpreter, "   "), LineOutcome::Skipped);
+        assert_eq!(
+            process_line(&mut interpreter, "\\help"),
+            LineOutcome::Skipped
+        );
+        assert_eq!(
+            process_line(&mut interpreter, "var x = 1;"),
+            LineOutcome::Code
+        );
+        assert_eq!(process_line(&mut interpreter, "\\q"), LineOutcome::Quit);
+    }
+
+    #[test]
+    fn history_path_prefers_env_override() {
+        let path = resolve_history_path(
+            Some("/tmp/custom_history".into()),
+            Some("/xdg".into()),
+            Some("/home/me".into()),
+        );
+        assert_eq!(path, Some(PathBuf::from("/tmp/custom_history")));
+    }
+
+    #[test]
+    fn history_path_uses_data_dir() {
+        assert_eq!(
+            resolve_history_path(None, Some("/xdg".into()), Some("/home/me".into())),
+            Some(PathBuf::from("/xdg/vibe-lox/history"))
+        );
+        assert_eq!(
+            resolve_history_path(Some("".into()), None, Some("/home/me".into())),
+            Some(PathBuf::from("/home/me/.local/share/vibe-lox/history"))
+        );
+        assert_eq!(resolve_history_path(None, None, None), None);
+    }
 }
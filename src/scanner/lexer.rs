@@ -2,7 +2,7 @@ use winnow::combinator::{alt, opt};
 use winnow::error::ContextError;
 use winnow::prelude::*;
 use winnow::stream::{LocatingSlice, Location};
-use winnow::token::{any, take_till, take_while};
+use winnow::token::{any, take_till, take_until, take_while};
 
 use crate::error::CompileError;
 use crate::scanner::token::{Span, Token, TokenKind, keyword_kind};
@@ -35,6 +35,28 @@ fn whitespace_and_comments<'a>(input: &mut Input<'a>) -> ModalResult<()> {
     Ok(())
 }
 
+/// A `"""`-delimited string taken verbatim: it may span lines, contain lone
+/// `"` or `""`, and has no escape sequences. It ends at the first `"""`.
+fn triple_quoted_string<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
+    let start = input.current_token_start();
+    TRIPLE_QUOTE.void().parse_next(input)?;
+    let text: &str = take_until(0.., TRIPLE_QUOTE).parse_next(input).map_err(
+        |_: winnow::error::ErrMode<ContextError>| winnow::error::ErrMode::Cut(ContextError::new()),
+    )?;
+    TRIPLE_QUOTE.void().parse_next(input)?;
+    let end = input.current_token_start();
+    Ok(Token::new(
+        TokenKind::String,
+        text,
+        Span::new(start, end - start),
+    ))
+}
+
+const TRIPLE_QUOTE: &str = "\"\"\"";
+
+/// A `"`-delimited string on a single line. Reaching a newline or the end of
+/// input before the closing quote is a hard error, reported by `scan_all` as
+/// an unterminated string.
 fn string_literal<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
     let start = input.current_token_start();
     '"'.parse_next(input)?;
@@ -47,6 +69,7 @@ fn string_literal<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
             })?;
         match c {
             '"' => break,
+            '\n' => return Err(winnow::error::ErrMode::Cut(ContextError::new())),
             '\\' => {
                 let esc =
                     any.parse_next(input)
@@ -122,6 +145,8 @@ fn two_char_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
         "==".value((TokenKind::EqualEqual, "==")),
         ">=".value((TokenKind::GreaterEqual, ">=")),
         "<=".value((TokenKind::LessEqual, "<=")),
+        "??".value((TokenKind::QuestionQuestion, "??")),
+        "**".value((TokenKind::StarStar, "**")),
     ))
     .parse_next(input)?;
     Ok(Token::new(kind, lexeme, Span::new(start, 2)))
@@ -130,7 +155,7 @@ fn two_char_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
 fn single_char_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
     let start = input.current_token_start();
     let c = any
-        .verify(|c: &char| "(){}.,;-+/*!=<>".contains(*c))
+        .verify(|c: &char| "(){}.,;:-+/*%!=<>".contains(*c))
         .parse_next(input)?;
     let kind = match c {
         '(' => TokenKind::LeftParen,
@@ -142,8 +167,10 @@ fn single_char_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
         '-' => TokenKind::Minus,
         '+' => TokenKind::Plus,
         ';' => TokenKind::Semicolon,
+        ':' => TokenKind::Colon,
         '/' => TokenKind::Slash,
         '*' => TokenKind::Star,
+        '%' => TokenKind::Percent,
         '!' => TokenKind::Bang,
         '=' => TokenKind::Equal,
         '<' => TokenKind::Less,
@@ -155,6 +182,7 @@ fn single_char_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
 
 fn scan_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
     alt((
+        triple_quoted_string,
         string_literal,
         number_literal,
         identifier_or_keyword,
@@ -166,6 +194,19 @@ fn scan_token<'a>(input: &mut Input<'a>) -> ModalResult<Token> {
 
 /// Scan all tokens from source, returning either a token list or scan errors.
 pub fn scan_all(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
+    let (tokens, errors) = scan_recovering(source);
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Scan all tokens from source, skipping past anything that isn't a token.
+///
+/// Every lexical error is collected rather than stopping at the first, and the
+/// returned tokens are those that scanned cleanly around the errors.
+pub fn scan_recovering(source: &str) -> (Vec<Token>, Vec<CompileError>) {
     let mut input = LocatingSlice::new(source);
     let _ = opt(shebang).parse_next(&mut input);
     let mut tokens = Vec::new();
@@ -178,16 +219,41 @@ pub fn scan_all(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
         if input.is_empty() {
             break;
         }
+        let checkpoint = input.checkpoint();
         match scan_token(&mut input) {
             Ok(token) => tokens.push(token),
             Err(_) => {
+                input.reset(&checkpoint);
                 let offset = input.current_token_start();
-                let c = any::<_, ContextError>.parse_next(&mut input).ok();
-                let ch = c.unwrap_or('?');
+                if input.starts_with(TRIPLE_QUOTE) {
+                    // Nothing after an unclosed `"""` can be scanned as code.
+                    errors.push(CompileError::scan(
+                        "unterminated string literal",
+                        offset,
+                        source.len() - offset,
+                    ));
+                    break;
+                }
+                if input.starts_with('"') {
+                    // Skip the rest of the line so scanning resumes after it.
+                    take_till::<_, _, ContextError>(0.., '\n')
+                        .parse_next(&mut input)
+                        .expect("take_till cannot fail");
+                    let end = input.current_token_start();
+                    errors.push(CompileError::scan(
+                        "unterminated string literal",
+                        offset,
+                        end - offset,
+                    ));
+                    continue;
+                }
+                let ch = any::<_, ContextError>
+                    .parse_next(&mut input)
+                    .expect("input is not empty");
                 errors.push(CompileError::scan(
                     format!("unexpected character '{ch}'"),
                     offset,
-                    1,
+                    ch.len_utf8(),
                 ));
             }
         }
@@ -195,12 +261,7 @@ pub fn scan_all(source: &str) -> Result<Vec<Token>, Vec<CompileError>> {
 
     let eof_offset = source.len();
     tokens.push(Token::new(TokenKind::Eof, "", Span::new(eof_offset, 0)));
-
-    if errors.is_empty() {
-        Ok(tokens)
-    } else {
-        Err(errors)
-    }
+    (tokens, errors)
 }
 
 #[cfg(test)]
@@ -217,7 +278,7 @@ mod tests {
 
     #[test]
     fn single_char_tokens() {
-        let tokens = scan_ok("(){},.-+;/*");
+        let tokens = scan_ok("(){},.-+;/*%");
         assert_eq!(
             kinds(&tokens),
             vec![
@@ -232,6 +293,7 @@ mod tests {
                 TokenKind::Semicolon,
                 TokenKind::Slash,
                 TokenKind::Star,
+                TokenKind::Percent,
                 TokenKind::Eof,
             ]
         );
@@ -239,7 +301,7 @@ mod tests {
 
     #[test]
     fn two_char_tokens() {
-        let tokens = scan_ok("!= == >= <=");
+        let tokens = scan_ok("!= == >= <= ?? **");
         assert_eq!(
             kinds(&tokens),
             vec![
@@ -247,6 +309,8 @@ mod tests {
                 TokenKind::EqualEqual,
                 TokenKind::GreaterEqual,
                 TokenKind::LessEqual,
+                TokenKind::QuestionQuestion,
+                TokenKind::StarStar,
                 TokenKind::Eof,
             ]
         );
@@ -320,22 +384,27 @@ mod tests {
 
     #[test]
     fn all_keywords() {
-        let source =
-            "and class else false fun for if nil or print return super this true var while";
+        let source = "and case class const default do else false fun for if is nil or print return super switch this true var while";
         let tokens = scan_ok(source);
         let expected = vec![
             TokenKind::And,
+            TokenKind::Case,
             TokenKind::Class,
+            TokenKind::Const,
+            TokenKind::Default,
+            TokenKind::Do,
             TokenKind::Else,
             TokenKind::False,
             TokenKind::Fun,
             TokenKind::For,
             TokenKind::If,
+            TokenKind::Is,
             TokenKind::Nil,
             TokenKind::Or,
             TokenKind::Print,
             TokenKind::Return,
             TokenKind::Super,
+            TokenKind::Switch,
             TokenKind::This,
             TokenKind::True,
             TokenKind::Var,
@@ -379,10 +448,106 @@ mod tests {
         assert!(errors[0].to_string().contains('@'));
     }
 
+    fn scan_error_span(source: &str) -> (String, usize, usize) {
+        let errors = scan_all(source).expect_err("scan should fail");
+        assert_eq!(errors.len(), 1, "expected one error, got {errors:?}");
+        let CompileError::Scan { message, span, .. } = &errors[0] else {
+            panic!("expected scan error, got {:?}", errors[0]);
+        };
+        (message.clone(), span.offset(), span.len())
+    }
+
+    #[rstest]
+    #[case::at_eof("var s = \"unterminated", 8, 13)]
+    #[case::at_newline("var s = \"broken\nprint s;", 8, 7)]
+    #[case::escaped_quote_at_eof("\"ends with \\\"", 0, 13)]
+    fn unterminated_string_error(#[case] source: &str, #[case] offset: usize, #[case] len: usize) {
+        let (message, got_offset, got_len) = scan_error_span(source);
+        assert_eq!(message, "unterminated string literal");
+        assert_eq!((got_offset, got_len), (offset, len));
+    }
+
+    #[rstest]
+    #[case::two_lines("\"\"\"line one\nline two\"\"\"", "line one\nline two")]
+    #[case::lone_quote("\"\"\"say \"hi\" now\"\"\"", "say \"hi\" now")]
+    #[case::double_quote("\"\"\"a \"\" b\"\"\"", "a \"\" b")]
+    #[case::no_escapes("\"\"\"tab\\tstays\"\"\"", "tab\\tstays")]
+    #[case::empty("\"\"\"\"\"\"", "")]
+    fn triple_quoted_string(#[case] source: &str, #[case] expected: &str) {
+        let tokens = scan_ok(source);
+        assert_eq!(kinds(&tokens), vec![TokenKind::String, TokenKind::Eof]);
+        assert_eq!(tokens[0].lexeme, expected);
+        assert_eq!(
+            (tokens[0].span.offset, tokens[0].span.len),
+            (0, source.len())
+        );
+    }
+
     #[test]
-    fn unterminated_string_error() {
-        let result = scan_all("\"unterminated");
-        assert!(result.is_err());
+    fn empty_string_is_not_triple_quoted() {
+        let tokens = scan_ok("\"\" + x");
+        assert_eq!(
+            kinds(&tokens),
+            vec![
+                TokenKind::String,
+                TokenKind::Plus,
+                TokenKind::Identifier,
+                TokenKind::Eof
+            ]
+        );
+        assert_eq!(tokens[0].lexeme, "");
+    }
+
+    #[test]
+    fn unterminated_triple_quoted_string_spans_to_eof() {
+        let (message, offset, len) = scan_error_span("print \"\"\"open\nstill open;\n");
+        assert_eq!(message, "unterminated string literal");
+        assert_eq!((offset, len), (6, 20));
+    }
+
+    #[test]
+    fn unexpected_characters_do_not_hide_later_tokens() {
+        let (tokens, errors) = scan_recovering("var x = @;\nprint $ x;");
+        let errors: Vec<(String, usize)> = errors
+            .iter()
+            .map(|e| (e.to_string(), e.span().offset()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("scan error: unexpected character '@'".to_string(), 8),
+                ("scan error: unexpected character '$'".to_string(), 17),
+            ]
+        );
+        assert_eq!(
+            kinds(&tokens),
+            vec![
+                TokenKind::Var,
+                TokenKind::Identifier,
+                TokenKind::Equal,
+                TokenKind::Semicolon,
+                TokenKind::Print,
+                TokenKind::Identifier,
+                TokenKind::Semicolon,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn unexpected_character_span_covers_whole_char() {
+        let (message, offset, len) = scan_error_span("print €;");
+        assert_eq!(message, "unexpected character '€'");
+        assert_eq!((offset, len), (6, '€'.len_utf8()));
+    }
+
+    #[test]
+    fn unterminated_string_resumes_on_next_line() {
+        let errors = scan_all("\"oops\nvar @;").expect_err("scan should fail");
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors.len(), 2, "{messages:?}");
+        assert!(messages[0].contains("unterminated string literal"));
+        assert!(messages[1].contains('@'));
     }
 
     #[test]
@@ -423,4 +588,53 @@ mod tests {
             "print token should start after shebang line"
         );
     }
+
+    #[rstest]
+    #[case::crlf_terminated("// a comment\r\n")]
+    #[case::eof_terminated("// last line")]
+    #[case::crlf_then_eof("// first\r\n// last line")]
+    #[case::empty_comment_at_eof("//")]
+    fn line_comment_only_source_is_just_eof(#[case] source: &str) {
+        let tokens = scan_ok(source);
+        assert_eq!(kinds(&tokens), vec![TokenKind::Eof]);
+        assert_eq!(tokens[0].span, Span::new(source.len(), 0));
+    }
+
+    #[test]
+    fn crlf_comment_does_not_swallow_next_line() {
+        let tokens = scan_ok("// note\r\nprint 1;\r\n");
+        assert_eq!(
+            kinds(&tokens),
+            vec![
+                TokenKind::Print,
+                TokenKind::Number,
+                TokenKind::Semicolon,
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[0].span, Span::new(9, 5));
+    }
+
+    #[test]
+    fn multibyte_string_spans_count_bytes() {
+        let tokens = scan_ok("print \"héllo\"; print 1;");
+        assert_eq!(tokens[1].kind, TokenKind::String);
+        assert_eq!(tokens[1].span, Span::new(6, 8));
+        assert_eq!(tokens[2].span, Span::new(14, 1));
+        assert_eq!(tokens[4].span, Span::new(22, 1));
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::no_trailing_newline("print 1")]
+    #[case::trailing_whitespace("print 1;\n\n  ")]
+    #[case::trailing_comment("print 1; // done")]
+    #[case::trailing_block_comment("print 1; /* done */")]
+    #[case::multibyte_string("print \"é\";")]
+    fn eof_span_is_empty_at_end_of_source(#[case] source: &str) {
+        let tokens = scan_ok(source);
+        let eof = tokens.last().expect("token stream ends with Eof");
+        assert_eq!(eof.kind, TokenKind::Eof);
+        assert_eq!(eof.span, Span::new(source.len(), 0));
+    }
 }
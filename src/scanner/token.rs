@@ -14,6 +14,9 @@ pub enum TokenKind {
     Semicolon,
     Slash,
     Star,
+    StarStar,
+    Percent,
+    Colon,
 
     // One or two character tokens
     Bang,
@@ -24,6 +27,7 @@ pub enum TokenKind {
     GreaterEqual,
     Less,
     LessEqual,
+    QuestionQuestion,
 
     // Literals
     Identifier,
@@ -32,17 +36,23 @@ pub enum TokenKind {
 
     // Keywords
     And,
+    Case,
     Class,
+    Const,
+    Default,
+    Do,
     Else,
     False,
     Fun,
     For,
     If,
+    Is,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
     True,
     Var,
@@ -65,6 +75,9 @@ impl fmt::Display for TokenKind {
             Self::Semicolon => write!(f, ";"),
             Self::Slash => write!(f, "/"),
             Self::Star => write!(f, "*"),
+            Self::StarStar => write!(f, "**"),
+            Self::Percent => write!(f, "%"),
+            Self::Colon => write!(f, ":"),
             Self::Bang => write!(f, "!"),
             Self::BangEqual => write!(f, "!="),
             Self::Equal => write!(f, "="),
@@ -73,21 +86,28 @@ impl fmt::Display for TokenKind {
             Self::GreaterEqual => write!(f, ">="),
             Self::Less => write!(f, "<"),
             Self::LessEqual => write!(f, "<="),
+            Self::QuestionQuestion => write!(f, "??"),
             Self::Identifier => write!(f, "IDENTIFIER"),
             Self::String => write!(f, "STRING"),
             Self::Number => write!(f, "NUMBER"),
             Self::And => write!(f, "and"),
+            Self::Case => write!(f, "case"),
             Self::Class => write!(f, "class"),
+            Self::Const => write!(f, "const"),
+            Self::Default => write!(f, "default"),
+            Self::Do => write!(f, "do"),
             Self::Else => write!(f, "else"),
             Self::False => write!(f, "false"),
             Self::Fun => write!(f, "fun"),
             Self::For => write!(f, "for"),
             Self::If => write!(f, "if"),
+            Self::Is => write!(f, "is"),
             Self::Nil => write!(f, "nil"),
             Self::Or => write!(f, "or"),
             Self::Print => write!(f, "print"),
             Self::Return => write!(f, "return"),
             Self::Super => write!(f, "super"),
+            Self::Switch => write!(f, "switch"),
             Self::This => write!(f, "this"),
             Self::True => write!(f, "true"),
             Self::Var => write!(f, "var"),
@@ -132,26 +152,44 @@ impl Token {
     }
 }
 
+/// The `--dump-tokens` line format, `Kind 'lexeme' @offset+len`, e.g.
+/// `Identifier 'x' @4+1`. The lexeme is escaped like a Rust string
+/// (`\n`, `\'`, `\\`), so every token prints on one line and the quotes
+/// are unambiguous. A string literal's lexeme is its contents, while its
+/// span also covers the quotes.
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?} '{}' @{}", self.kind, self.lexeme, self.span.offset)
+        write!(
+            f,
+            "{:?} '{}' @{}+{}",
+            self.kind,
+            self.lexeme.escape_debug(),
+            self.span.offset,
+            self.span.len
+        )
     }
 }
 
 pub fn keyword_kind(ident: &str) -> Option<TokenKind> {
     match ident {
         "and" => Some(TokenKind::And),
+        "case" => Some(TokenKind::Case),
         "class" => Some(TokenKind::Class),
+        "const" => Some(TokenKind::Const),
+        "default" => Some(TokenKind::Default),
+        "do" => Some(TokenKind::Do),
         "else" => Some(TokenKind::Else),
         "false" => Some(TokenKind::False),
         "fun" => Some(TokenKind::Fun),
         "for" => Some(TokenKind::For),
         "if" => Some(TokenKind::If),
+        "is" => Some(TokenKind::Is),
         "nil" => Some(TokenKind::Nil),
         "or" => Some(TokenKind::Or),
         "print" => Some(TokenKind::Print),
         "return" => Some(TokenKind::Return),
         "super" => Some(TokenKind::Super),
+        "switch" => Some(TokenKind::Switch),
         "this" => Some(TokenKind::This),
         "true" => Some(TokenKind::True),
         "var" => Some(TokenKind::Var),
@@ -159,3 +197,44 @@ pub fn keyword_kind(ident: &str) -> Option<TokenKind> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::scanner::scan;
+
+    fn dump(source: &str) -> String {
+        scan(source)
+            .expect("scan should succeed")
+            .iter()
+            .map(|token| format!("{token}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn dump_format_is_stable() {
+        assert_eq!(
+            dump("var x = \"a b\"; // note\nprint x >= 1.5;"),
+            "\
+Var 'var' @0+3
+Identifier 'x' @4+1
+Equal '=' @6+1
+String 'a b' @8+5
+Semicolon ';' @13+1
+Print 'print' @23+5
+Identifier 'x' @29+1
+GreaterEqual '>=' @31+2
+Number '1.5' @34+3
+Semicolon ';' @37+1
+Eof '' @38+0
+"
+        );
+    }
+
+    #[test]
+    fn lexemes_are_escaped_onto_one_line() {
+        assert_eq!(
+            dump("\"\"\"it's\n\\ \"\"\""),
+            "String 'it\\'s\\n\\\\ ' @0+13\nEof '' @13+0\n"
+        );
+    }
+}
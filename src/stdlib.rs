@@ -18,6 +18,18 @@ pub fn read_line_from<R: std::io::BufRead>(reader: &mut R) -> Option<String> {
     }
 }
 
+/// Whole milliseconds since the Unix epoch, as returned by `time_millis()`.
+///
+/// Lox numbers are `f64`, so the value is exact only up to 2^53 ms (about
+/// 285,000 years after 1970); beyond that it loses integer precision.
+pub fn time_millis() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock should be after unix epoch")
+        .as_millis() as f64
+}
+
 /// Parse a string as a Lox `NUMBER` literal, trimming surrounding whitespace.
 ///
 /// Accepts: `DIGIT+ ("." DIGIT+)?` — no sign, no scientific notation.
@@ -58,12 +70,125 @@ pub fn parse_lox_number(s: &str) -> Option<f64> {
     s.parse::<f64>().ok()
 }
 
+/// Parse a string as a number more forgivingly than [`parse_lox_number`],
+/// trimming surrounding whitespace.
+///
+/// Also accepts a leading `+` or `-`, scientific notation (`1e5`, `2.5E-3`)
+/// and a missing integer part (`.5`). Text like `inf` or `nan`, and values
+/// too large to be finite, are still rejected. Literal validation keeps
+/// using the strict parser.
+pub fn parse_number_lenient(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let numeric = |c: char| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E');
+    if !s.chars().any(|c| c.is_ascii_digit()) || !s.chars().all(numeric) {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Format a number the way Lox's `print` displays it.
+///
+/// Integral values print without a fractional part (`3`, not `3.0`), and
+/// negative zero prints as `0`. Large magnitudes print in full rather than
+/// saturating through an integer cast. Both the interpreter and the VM use
+/// this so their output can't drift apart.
+pub fn format_lox_number(n: f64) -> String {
+    if n == 0.0 {
+        // Covers -0.0, which would otherwise print as "-0"
+        "0".to_string()
+    } else {
+        format!("{n}")
+    }
+}
+
+/// Format a function (user-defined, native, or bound method) the way Lox's
+/// `print` displays it: `<fn name(arity)>`. Shared by the interpreter and
+/// the VM, like [`format_lox_number`].
+pub fn format_lox_function(name: &str, arity: usize) -> String {
+    format!("<fn {name}({arity})>")
+}
+
+/// Format a class the way Lox's `print` displays it: `<class Name>`.
+pub fn format_lox_class(name: &str) -> String {
+    format!("<class {name}>")
+}
+
+/// Convert a number used as an index to `usize`. Fractional, negative,
+/// non-finite and too-large numbers are rejected rather than truncated or
+/// saturated the way `as usize` would.
+pub fn value_to_index(n: f64) -> Result<usize, String> {
+    if n.fract() != 0.0 || n < 0.0 || n >= usize::MAX as f64 {
+        return Err(format!("index must be a non-negative integer, got {n}"));
+    }
+    Ok(n as usize)
+}
+
+/// Characters `start..end` of `s`, as returned by `substr()`.
+///
+/// Indices count characters rather than bytes so multibyte strings can't be
+/// split mid-character, and must be non-negative integers (see
+/// [`value_to_index`]). Bounds past the end of the string, or reversed
+/// bounds, give a shorter or empty result instead of failing.
+pub fn substr_chars(s: &str, start: f64, end: f64) -> Result<String, String> {
+    let index = |n| value_to_index(n).map_err(|err| format!("substr() {err}"));
+    let (start, end) = (index(start)?, index(end)?);
+    Ok(s.chars()
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect())
+}
+
+/// Character index of the first occurrence of `needle` in `s`, or -1 if it
+/// does not occur, as returned by `indexOf()`.
+pub fn index_of_chars(s: &str, needle: &str) -> f64 {
+    match s.find(needle) {
+        Some(byte_idx) => s[..byte_idx].chars().count() as f64,
+        None => -1.0,
+    }
+}
+
+/// Unicode codepoint of the first character of `s`, as returned by `ord()`.
+pub fn ord(s: &str) -> Result<f64, String> {
+    s.chars()
+        .next()
+        .map(|c| u32::from(c) as f64)
+        .ok_or_else(|| "ord() argument must be a non-empty string".to_string())
+}
+
+/// Single-character string for codepoint `n`, as returned by `chr()`.
+///
+/// Fails for non-integers and values that aren't Unicode scalar values
+/// (negative, above `0x10FFFF`, or a surrogate).
+pub fn chr(n: f64) -> Result<String, String> {
+    let invalid = || format!("chr() argument must be a valid codepoint, got {n}");
+    let code = value_to_index(n).map_err(|_| invalid())?;
+    u32::try_from(code)
+        .ok()
+        .and_then(char::from_u32)
+        .map(String::from)
+        .ok_or_else(invalid)
+}
+
+/// Value of the environment variable `name`, as returned by `env()`, or
+/// `None` when it is unset. Values that aren't valid UTF-8 are converted lossily.
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use rstest::rstest;
     use std::io::Cursor;
 
+    #[rstest]
+    #[case::function(format_lox_function("add", 2), "<fn add(2)>")]
+    #[case::no_params(format_lox_function("f", 0), "<fn f(0)>")]
+    #[case::class(format_lox_class("Point"), "<class Point>")]
+    fn callable_display_formats(#[case] formatted: String, #[case] expected: &str) {
+        assert_eq!(formatted, expected);
+    }
+
     #[test]
     fn read_line_returns_string_without_newline() {
         let mut r = Cursor::new(b"hello\nworld\n");
@@ -125,4 +250,140 @@ mod tests {
     fn parse_lox_number_invalid(#[case] input: &str) {
         assert_eq!(parse_lox_number(input), None);
     }
+
+    #[rstest]
+    #[case("-1", -1.0)]
+    #[case("1e5", 1e5)]
+    #[case(".5", 0.5)]
+    #[case("+3.0", 3.0)]
+    #[case("-2.5E-3", -2.5e-3)]
+    #[case("  42  ", 42.0)]
+    fn parse_number_lenient_valid(#[case] input: &str, #[case] expected: f64) {
+        assert_eq!(parse_number_lenient(input), Some(expected));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("inf")]
+    #[case("-infinity")]
+    #[case("nan")]
+    #[case("NaN")]
+    #[case("1 2")]
+    #[case("1e999")]
+    #[case("e5")]
+    #[case("+")]
+    #[case(".")]
+    #[case("1e")]
+    #[case("1.2.3")]
+    #[case("0x10")]
+    fn parse_number_lenient_invalid(#[case] input: &str) {
+        assert_eq!(parse_number_lenient(input), None);
+    }
+
+    #[rstest]
+    #[case(0.0, "0")]
+    #[case(-0.0, "0")]
+    #[case(3.0, "3")]
+    #[case(-7.0, "-7")]
+    #[case(0.5, "0.5")]
+    #[case(1e21, "1000000000000000000000")]
+    #[case(-1e21, "-1000000000000000000000")]
+    #[case(f64::INFINITY, "inf")]
+    #[case(f64::NEG_INFINITY, "-inf")]
+    #[case(f64::NAN, "NaN")]
+    fn format_lox_number_cases(#[case] input: f64, #[case] expected: &str) {
+        assert_eq!(format_lox_number(input), expected);
+    }
+
+    #[rstest]
+    #[case("hello", 1.0, 3.0, "el")]
+    #[case("hello", 0.0, 5.0, "hello")]
+    #[case("héllo", 1.0, 4.0, "éll")]
+    #[case("héllo", 3.0, 99.0, "lo")]
+    #[case("héllo", 4.0, 2.0, "")]
+    fn substr_chars_cases(
+        #[case] s: &str,
+        #[case] start: f64,
+        #[case] end: f64,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(substr_chars(s, start, end).as_deref(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case::negative(-2.0, 2.0, "got -2")]
+    #[case::fractional_start(1.9, 3.0, "got 1.9")]
+    #[case::fractional_end(1.0, 3.2, "got 3.2")]
+    #[case::huge(0.0, 1e20, "got 100000000000000000000")]
+    #[case::infinite(0.0, f64::INFINITY, "got inf")]
+    fn substr_chars_rejects_bad_indices(
+        #[case] start: f64,
+        #[case] end: f64,
+        #[case] detail: &str,
+    ) {
+        let err = substr_chars("hello", start, end).expect_err("bad index");
+        assert!(
+            err.starts_with("substr() index must be a non-negative integer"),
+            "{err}"
+        );
+        assert!(err.ends_with(detail), "{err}");
+    }
+
+    #[rstest]
+    #[case(0.0, 0)]
+    #[case(3.0, 3)]
+    #[case(-0.0, 0)]
+    fn value_to_index_accepts_whole_numbers(#[case] n: f64, #[case] expected: usize) {
+        assert_eq!(value_to_index(n), Ok(expected));
+    }
+
+    #[rstest]
+    #[case(1.5)]
+    #[case(-1.0)]
+    #[case(1e20)]
+    #[case(f64::NAN)]
+    #[case(f64::INFINITY)]
+    fn value_to_index_rejects_other_numbers(#[case] n: f64) {
+        assert!(value_to_index(n).is_err());
+    }
+
+    #[rstest]
+    #[case("hello", "ll", 2.0)]
+    #[case("héllo", "l", 2.0)]
+    #[case("hello", "", 0.0)]
+    #[case("hello", "z", -1.0)]
+    fn index_of_chars_cases(#[case] s: &str, #[case] needle: &str, #[case] expected: f64) {
+        assert_eq!(index_of_chars(s, needle), expected);
+    }
+
+    #[rstest]
+    #[case("A", 65.0)]
+    #[case("abc", 97.0)]
+    #[case("é", 233.0)]
+    fn ord_cases(#[case] s: &str, #[case] expected: f64) {
+        assert_eq!(ord(s), Ok(expected));
+    }
+
+    #[test]
+    fn ord_empty_string_fails() {
+        assert!(ord("").is_err());
+    }
+
+    #[rstest]
+    #[case(65.0, "A")]
+    #[case(233.0, "é")]
+    fn chr_cases(#[case] n: f64, #[case] expected: &str) {
+        assert_eq!(chr(n).as_deref(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case(-1.0)]
+    #[case(65.5)]
+    #[case(0x110000 as f64)]
+    #[case(0xD800 as f64)]
+    #[case(f64::NAN)]
+    #[case(1e20)]
+    fn chr_invalid_codepoint_fails(#[case] n: f64) {
+        assert!(chr(n).is_err());
+    }
 }
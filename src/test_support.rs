@@ -0,0 +1,26 @@
+//! Helpers shared by the unit tests of more than one module.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Writer that records the text pending at each `flush`, so tests can
+/// check output is flushed line by line rather than only at exit.
+#[derive(Default)]
+pub(crate) struct FlushRecorder {
+    pending: Vec<u8>,
+    pub(crate) flushed: Rc<RefCell<Vec<String>>>,
+}
+
+impl Write for FlushRecorder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let text = String::from_utf8(std::mem::take(&mut self.pending)).expect("utf-8");
+        self.flushed.borrow_mut().push(text);
+        Ok(())
+    }
+}
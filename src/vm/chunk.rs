@@ -1,9 +1,32 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::vm::vm::NativeFn;
+
+/// Version of the bytecode encoding: the opcode numbering and the serialized
+/// `Chunk` layout. Bump it whenever either changes in a way older builds
+/// can't read, including every time an opcode is added. Stored in each
+/// `.blox` file right after [`BLOX_MAGIC`].
+pub const BLOX_VERSION: u32 = 3;
+
+/// Magic number at the start of every `.blox` file: ASCII "blox".
+pub const BLOX_MAGIC: &[u8; 4] = b"blox";
+
+/// Length of the `.blox` header: the magic number, the format version and
+/// the native count.
+const BLOX_HEADER_LEN: usize = BLOX_MAGIC.len() + 4 + 2;
+
+/// Number of natives the VM defines ahead of user globals. Global slot
+/// operands count from here, so bytecode is only valid with the same count.
+fn native_count() -> u16 {
+    u16::try_from(NativeFn::ALL.len()).expect("fewer than 65536 natives")
+}
+
 /// A bytecode instruction.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::AsRefStr)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::AsRefStr, strum::EnumCount,
+)]
 #[strum(serialize_all = "snake_case")]
 #[repr(u8)]
 pub enum OpCode {
@@ -44,6 +67,101 @@ pub enum OpCode {
     Class,
     Inherit,
     Method,
+    /// Call in tail position: reuses the caller's frame for plain closures.
+    TailCall,
+    Modulo,
+    /// Pop N values and print them space-separated on one line.
+    PrintN,
+    /// Jump when the top of stack is nil, leaving it in place.
+    JumpIfNil,
+    Power,
+    /// Push a copy of the top of stack: `[a] -> [a, a]`. Lets compound
+    /// operations reuse a value, such as an object that is both read and
+    /// written, without evaluating it twice.
+    Dup,
+    /// Pop a class and a value; push whether the value is an instance of
+    /// that class or one of its subclasses.
+    IsInstance,
+    /// Read a global by its slot in the VM's global table rather than by
+    /// name. Emitted only for globals already defined when the reference is
+    /// compiled; see `Compiler::global_slot`.
+    GetGlobalByIndex,
+    /// `<=`, `>=` and `!=` as single instructions. Unlike negating `Greater`,
+    /// `Less` or `Equal`, a comparison involving NaN is false.
+    LessEqual,
+    GreaterEqual,
+    NotEqual,
+    /// Pop the number of values given by the operand, e.g. the uncaptured
+    /// locals of a block as it ends.
+    PopN,
+    /// Push the number `0` or `1` without a constant-pool entry.
+    Zero,
+    One,
+    /// Like `DefineGlobal`, but the global is a `const`: `SetGlobal` on it
+    /// is a runtime error until a plain `DefineGlobal` redefines it.
+    DefineGlobalConst,
+}
+
+impl OpCode {
+    /// Number of operand bytes that follow the opcode. `Closure` is also
+    /// followed by an `(is_local, index)` pair per upvalue of its function;
+    /// [`Chunk::instruction_len`] accounts for those.
+    pub fn operand_len(self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::DefineGlobalConst
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::Class
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::Method
+            | OpCode::GetSuper
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::TailCall
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::PrintN
+            | OpCode::PopN
+            | OpCode::GetGlobalByIndex
+            | OpCode::Closure => 1,
+            OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfNil
+            | OpCode::Loop
+            | OpCode::Invoke
+            | OpCode::SuperInvoke => 2,
+            OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Pop
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::GreaterEqual
+            | OpCode::NotEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Modulo
+            | OpCode::Power
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Inherit
+            | OpCode::Dup
+            | OpCode::IsInstance
+            | OpCode::Zero
+            | OpCode::One => 0,
+        }
+    }
 }
 
 impl fmt::Display for OpCode {
@@ -56,12 +174,64 @@ impl TryFrom<u8> for OpCode {
     type Error = u8;
 
     fn try_from(byte: u8) -> Result<Self, Self::Error> {
-        if byte <= OpCode::Method as u8 {
-            // Safety: OpCode is repr(u8) and we've verified byte is in range
-            Ok(unsafe { std::mem::transmute::<u8, OpCode>(byte) })
-        } else {
-            Err(byte)
-        }
+        // Spelled out rather than transmuted so a corrupt or truncated chunk
+        // can only ever produce `Err`, never an invalid `OpCode`.
+        let op = match byte {
+            0 => OpCode::Constant,
+            1 => OpCode::Nil,
+            2 => OpCode::True,
+            3 => OpCode::False,
+            4 => OpCode::Pop,
+            5 => OpCode::GetLocal,
+            6 => OpCode::SetLocal,
+            7 => OpCode::GetGlobal,
+            8 => OpCode::SetGlobal,
+            9 => OpCode::DefineGlobal,
+            10 => OpCode::GetUpvalue,
+            11 => OpCode::SetUpvalue,
+            12 => OpCode::GetProperty,
+            13 => OpCode::SetProperty,
+            14 => OpCode::GetSuper,
+            15 => OpCode::Equal,
+            16 => OpCode::Greater,
+            17 => OpCode::Less,
+            18 => OpCode::Add,
+            19 => OpCode::Subtract,
+            20 => OpCode::Multiply,
+            21 => OpCode::Divide,
+            22 => OpCode::Not,
+            23 => OpCode::Negate,
+            24 => OpCode::Print,
+            25 => OpCode::Jump,
+            26 => OpCode::JumpIfFalse,
+            27 => OpCode::Loop,
+            28 => OpCode::Call,
+            29 => OpCode::Invoke,
+            30 => OpCode::SuperInvoke,
+            31 => OpCode::Closure,
+            32 => OpCode::CloseUpvalue,
+            33 => OpCode::Return,
+            34 => OpCode::Class,
+            35 => OpCode::Inherit,
+            36 => OpCode::Method,
+            37 => OpCode::TailCall,
+            38 => OpCode::Modulo,
+            39 => OpCode::PrintN,
+            40 => OpCode::JumpIfNil,
+            41 => OpCode::Power,
+            42 => OpCode::Dup,
+            43 => OpCode::IsInstance,
+            44 => OpCode::GetGlobalByIndex,
+            45 => OpCode::LessEqual,
+            46 => OpCode::GreaterEqual,
+            47 => OpCode::NotEqual,
+            48 => OpCode::PopN,
+            49 => OpCode::Zero,
+            50 => OpCode::One,
+            51 => OpCode::DefineGlobalConst,
+            _ => return Err(byte),
+        };
+        Ok(op)
     }
 }
 
@@ -130,6 +300,47 @@ impl Chunk {
         }
     }
 
+    /// Encode as the contents of a `.blox` file: [`BLOX_MAGIC`], then
+    /// [`BLOX_VERSION`] as a little-endian `u32`, then the number of natives
+    /// as a little-endian `u16`, then the chunk as MessagePack.
+    pub fn to_blox(&self) -> Result<Vec<u8>> {
+        let payload = rmp_serde::to_vec(self).context("serialize bytecode to MessagePack")?;
+        let mut bytes = Vec::with_capacity(BLOX_HEADER_LEN + payload.len());
+        bytes.extend_from_slice(BLOX_MAGIC);
+        bytes.extend_from_slice(&BLOX_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&native_count().to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    /// Decode the contents of a `.blox` file written by [`Chunk::to_blox`].
+    /// A file from a build with a different [`BLOX_VERSION`] or set of
+    /// natives is rejected rather than run with opcodes or global slots that
+    /// may mean something else.
+    pub fn from_blox(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < BLOX_HEADER_LEN || &bytes[..BLOX_MAGIC.len()] != BLOX_MAGIC {
+            bail!("not a valid .blox file (missing magic header)");
+        }
+        let (version, natives) = bytes[BLOX_MAGIC.len()..BLOX_HEADER_LEN].split_at(4);
+        let version = u32::from_le_bytes(version.try_into().expect("version is 4 bytes"));
+        if version != BLOX_VERSION {
+            bail!(
+                "bytecode is blox format {version}, but this build reads format {BLOX_VERSION}; \
+                 recompile it with --compile-bytecode"
+            );
+        }
+        let natives = u16::from_le_bytes(natives.try_into().expect("native count is 2 bytes"));
+        if natives != native_count() {
+            bail!(
+                "bytecode was compiled with {natives} natives, but this build has {}; \
+                 recompile it with --compile-bytecode",
+                native_count()
+            );
+        }
+        rmp_serde::from_slice(&bytes[BLOX_HEADER_LEN..])
+            .context("deserialize bytecode from MessagePack")
+    }
+
     pub fn write_op(&mut self, op: OpCode, line: usize) {
         self.code.push(op as u8);
         self.lines.push(line);
@@ -159,6 +370,151 @@ impl Chunk {
         let lo = self.code[offset + 1] as u16;
         (hi << 8) | lo
     }
+
+    /// Size of the code stream in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Length in bytes of the instruction at `offset`, operands included, or
+    /// `None` if the byte there isn't an opcode.
+    pub fn instruction_len(&self, offset: usize) -> Option<usize> {
+        let op = OpCode::try_from(self.code[offset]).ok()?;
+        let mut len = 1 + op.operand_len();
+        if op == OpCode::Closure
+            && let Some(Constant::Function { upvalue_count, .. }) = self
+                .code
+                .get(offset + 1)
+                .and_then(|&idx| self.constants.get(idx as usize))
+        {
+            len += 2 * upvalue_count;
+        }
+        Some(len)
+    }
+
+    /// Number of instructions in the code stream (not counting nested
+    /// functions). A byte that isn't an opcode counts as one instruction, so
+    /// this never fails on a corrupt chunk.
+    pub fn instruction_count(&self) -> usize {
+        let mut count = 0;
+        let mut offset = 0;
+        while offset < self.code.len() {
+            offset += self.instruction_len(offset).unwrap_or(1);
+            count += 1;
+        }
+        count
+    }
+
+    /// A plain-text dump of the constant pool and every instruction, with
+    /// nested functions after their parent, for comparing compiler output
+    /// in tests.
+    ///
+    /// Unlike [`disassemble`] this has no alignment or commentary to change:
+    /// each instruction is its offset, line, opcode name and raw operand
+    /// bytes (so constant indices appear as numbers). Bytes that don't
+    /// decode are dumped as `invalid N` rather than failing.
+    pub fn to_debug_string(&self) -> String {
+        let mut out = String::new();
+        self.write_debug_string("", &mut out);
+        out
+    }
+
+    fn write_debug_string(&self, indent: &str, out: &mut String) {
+        out.push_str(&format!("{indent}constants:\n"));
+        for (i, constant) in self.constants.iter().enumerate() {
+            let value = match constant {
+                Constant::Number(n) => format!("number {n}"),
+                Constant::String(s) => format!("string {s:?}"),
+                Constant::Function {
+                    name,
+                    arity,
+                    upvalue_count,
+                    ..
+                } => format!("function {name} arity={arity} upvalues={upvalue_count}"),
+            };
+            out.push_str(&format!("{indent}  {i} {value}\n"));
+        }
+        out.push_str(&format!("{indent}code:\n"));
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let line = self.lines.get(offset).copied().unwrap_or(0);
+            let Some(len) = self.instruction_len(offset) else {
+                let byte = self.code[offset];
+                out.push_str(&format!("{indent}  {offset} line {line} invalid {byte}\n"));
+                offset += 1;
+                continue;
+            };
+            let op = OpCode::try_from(self.code[offset]).expect("instruction_len decoded it");
+            out.push_str(&format!("{indent}  {offset} line {line} {}", op.as_ref()));
+            let end = (offset + len).min(self.code.len());
+            for byte in &self.code[offset + 1..end] {
+                out.push_str(&format!(" {byte}"));
+            }
+            out.push('\n');
+            offset += len;
+        }
+        let nested = format!("{indent}  ");
+        for (i, constant) in self.constants.iter().enumerate() {
+            if let Constant::Function { name, chunk, .. } = constant {
+                out.push_str(&format!("{indent}function {i} {name}:\n"));
+                chunk.write_debug_string(&nested, out);
+            }
+        }
+    }
+}
+
+/// Size summary of a chunk and, recursively, every function nested in it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkStats {
+    pub instructions: usize,
+    pub code_bytes: usize,
+    pub number_constants: usize,
+    pub string_constants: usize,
+    /// Function constants, which is also the number of nested functions.
+    pub function_constants: usize,
+}
+
+impl ChunkStats {
+    pub fn collect(chunk: &Chunk) -> Self {
+        let mut stats = Self::default();
+        stats.add(chunk);
+        stats
+    }
+
+    fn add(&mut self, chunk: &Chunk) {
+        self.instructions += chunk.instruction_count();
+        self.code_bytes += chunk.byte_len();
+        for constant in &chunk.constants {
+            match constant {
+                Constant::Number(_) => self.number_constants += 1,
+                Constant::String(_) => self.string_constants += 1,
+                Constant::Function { chunk, .. } => {
+                    self.function_constants += 1;
+                    self.add(chunk);
+                }
+            }
+        }
+    }
+
+    pub fn constants(&self) -> usize {
+        self.number_constants + self.string_constants + self.function_constants
+    }
+}
+
+impl fmt::Display for ChunkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instructions:     {}", self.instructions)?;
+        writeln!(f, "code bytes:       {}", self.code_bytes)?;
+        writeln!(
+            f,
+            "constants:        {} ({} numbers, {} strings, {} functions)",
+            self.constants(),
+            self.number_constants,
+            self.string_constants,
+            self.function_constants
+        )?;
+        writeln!(f, "nested functions: {}", self.function_constants)
+    }
 }
 
 /// Disassemble a chunk into structured, human-readable text with recursive
@@ -168,16 +524,117 @@ impl Chunk {
 pub fn disassemble(chunk: &Chunk, source_name: &str) -> Result<String> {
     let mut out = String::new();
     out.push_str(&format!("Compiled from \"{source_name}\"\n"));
-    disassemble_chunk(chunk, "script", 0, &mut out)?;
+    disassemble_chunk(chunk, "script", 0, 0, &mut out)?;
     Ok(out)
 }
 
+/// Deepest function nesting `disassemble` descends into. A chunk can't
+/// contain itself, but a crafted `.blox` file could nest absurdly deep.
+const MAX_DISASSEMBLY_DEPTH: usize = 256;
+
+/// Like [`disassemble`], with a `; banner` comment line before the output,
+/// e.g. to mark bytecode compiled on the fly rather than loaded from disk.
+pub fn disassemble_with_banner(chunk: &Chunk, source_name: &str, banner: &str) -> Result<String> {
+    Ok(format!("; {banner}\n{}", disassemble(chunk, source_name)?))
+}
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether disassembly printed to stdout should be colorized: only when
+/// stdout is a terminal and `NO_COLOR` is unset or empty.
+pub fn color_enabled() -> bool {
+    use std::io::IsTerminal;
+    resolve_color(
+        std::io::stdout().is_terminal(),
+        std::env::var_os("NO_COLOR").as_deref(),
+    )
+}
+
+fn resolve_color(is_terminal: bool, no_color: Option<&std::ffi::OsStr>) -> bool {
+    is_terminal && no_color.is_none_or(|value| value.is_empty())
+}
+
+/// Add ANSI colors to the output of [`disassemble`]: opcodes, operands,
+/// constant types and values, and `//` comments each get their own color.
+/// Only escape codes are inserted, so column alignment is unchanged.
+pub fn colorize_disassembly(plain: &str) -> String {
+    let mut out = String::with_capacity(plain.len() * 2);
+    for line in plain.lines() {
+        colorize_line(line, &mut out);
+        out.push('\n');
+    }
+    out
+}
+
+fn colorize_line(line: &str, out: &mut String) {
+    let body = line.trim_start();
+    out.push_str(&line[..line.len() - body.len()]);
+    if let Some((offset, rest)) = body.split_once(": ")
+        && !offset.is_empty()
+        && offset.bytes().all(|b| b.is_ascii_digit())
+    {
+        let name_end = rest.find(' ').unwrap_or(rest.len());
+        let (name, rest) = rest.split_at(name_end);
+        let (operands, comment) = match rest.find("// ") {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        out.push_str(&format!("{DIM}{offset}{RESET}: {CYAN}{name}{RESET}"));
+        push_colored(out, operands, YELLOW);
+        push_colored(out, comment, DIM);
+    } else if body.starts_with('#')
+        && let Some((index, rest)) = body.split_once(" = ")
+    {
+        let type_end = rest.find(' ').unwrap_or(rest.len());
+        let (type_name, value) = rest.split_at(type_end);
+        out.push_str(&format!(
+            "{YELLOW}{index}{RESET} = {MAGENTA}{type_name}{RESET}"
+        ));
+        push_colored(out, value, GREEN);
+    } else if body.starts_with("; ") {
+        push_colored(out, body, DIM);
+    } else {
+        push_colored(out, body, BOLD);
+    }
+}
+
+/// Push `text` with its non-blank middle wrapped in `color`, leaving the
+/// surrounding padding uncolored.
+fn push_colored(out: &mut String, text: &str, color: &str) {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        out.push_str(text);
+        return;
+    }
+    let start = text.len() - text.trim_start().len();
+    out.push_str(&text[..start]);
+    out.push_str(&format!("{color}{trimmed}{RESET}"));
+    out.push_str(&text[start + trimmed.len()..]);
+}
+
 /// Recursively disassemble a single chunk (script or function body).
-fn disassemble_chunk(chunk: &Chunk, name: &str, arity: usize, out: &mut String) -> Result<()> {
+fn disassemble_chunk(
+    chunk: &Chunk,
+    name: &str,
+    arity: usize,
+    depth: usize,
+    out: &mut String,
+) -> Result<()> {
+    if depth > MAX_DISASSEMBLY_DEPTH {
+        anyhow::bail!("functions nested more than {MAX_DISASSEMBLY_DEPTH} deep");
+    }
+
     // Function header
     if name == "script" {
         out.push_str("script;\n");
     } else {
+        out.push_str(&format!("== fn {name} ==\n"));
         let params: Vec<String> = (0..arity).map(|i| format!("_{i}")).collect();
         out.push_str(&format!(
             "fun {name}({});  // arity={arity}\n",
@@ -207,7 +664,7 @@ fn disassemble_chunk(chunk: &Chunk, name: &str, arity: usize, out: &mut String)
     }
     out.push('\n');
 
-    // Recursively disassemble nested functions
+    // Recursively disassemble nested functions, indented under their parent
     for constant in &chunk.constants {
         if let Constant::Function {
             name,
@@ -216,7 +673,15 @@ fn disassemble_chunk(chunk: &Chunk, name: &str, arity: usize, out: &mut String)
             ..
         } = constant
         {
-            disassemble_chunk(fn_chunk, name, *arity, out)?;
+            let mut nested = String::new();
+            disassemble_chunk(fn_chunk, name, *arity, depth + 1, &mut nested)?;
+            for line in nested.lines() {
+                if !line.is_empty() {
+                    out.push_str("  ");
+                }
+                out.push_str(line);
+                out.push('\n');
+            }
         }
     }
 
@@ -233,6 +698,7 @@ fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> Re
     match op {
         OpCode::Constant
         | OpCode::DefineGlobal
+        | OpCode::DefineGlobalConst
         | OpCode::GetGlobal
         | OpCode::SetGlobal
         | OpCode::Class
@@ -246,28 +712,28 @@ fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> Re
                 "    {:>3}: {:<18} #{:<5} // {comment}\n",
                 offset, name, idx
             ));
-            Ok(offset + 2)
         }
         OpCode::GetLocal
         | OpCode::SetLocal
         | OpCode::Call
+        | OpCode::TailCall
         | OpCode::GetUpvalue
-        | OpCode::SetUpvalue => {
+        | OpCode::SetUpvalue
+        | OpCode::PrintN
+        | OpCode::PopN
+        | OpCode::GetGlobalByIndex => {
             let slot = chunk.code[offset + 1];
             out.push_str(&format!("    {:>3}: {:<18} {slot}\n", offset, name));
-            Ok(offset + 2)
         }
-        OpCode::Jump | OpCode::JumpIfFalse => {
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNil => {
             let jump = chunk.read_u16(offset + 1);
             let target = offset + 3 + jump as usize;
             out.push_str(&format!("    {:>3}: {:<18} -> {target}\n", offset, name));
-            Ok(offset + 3)
         }
         OpCode::Loop => {
             let jump = chunk.read_u16(offset + 1);
             let target = offset + 3 - jump as usize;
             out.push_str(&format!("    {:>3}: {:<18} -> {target}\n", offset, name));
-            Ok(offset + 3)
         }
         OpCode::Invoke | OpCode::SuperInvoke => {
             let name_idx = chunk.code[offset + 1];
@@ -277,7 +743,6 @@ fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> Re
                 "    {:>3}: {:<18} #{:<5} // ({arg_count} args) {comment}\n",
                 offset, name, name_idx
             ));
-            Ok(offset + 3)
         }
         OpCode::Closure => {
             let idx = chunk.code[offset + 1];
@@ -286,23 +751,25 @@ fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> Re
                 "    {:>3}: {:<18} #{:<5} // {comment}\n",
                 offset, name, idx
             ));
-            let mut off = offset + 2;
             if let Constant::Function { upvalue_count, .. } = &chunk.constants[idx as usize] {
-                for _ in 0..*upvalue_count {
+                for i in 0..*upvalue_count {
+                    let off = offset + 2 + 2 * i;
                     let is_local = chunk.code[off];
                     let index = chunk.code[off + 1];
                     let kind = if is_local == 1 { "local" } else { "upvalue" };
                     out.push_str(&format!("           | {kind} {index}\n"));
-                    off += 2;
                 }
             }
-            Ok(off)
         }
         _ => {
             out.push_str(&format!("    {:>3}: {name}\n", offset));
-            Ok(offset + 1)
         }
     }
+
+    let len = chunk
+        .instruction_len(offset)
+        .expect("opcode already decoded");
+    Ok(offset + len)
 }
 
 #[cfg(test)]
@@ -321,6 +788,112 @@ mod tests {
         assert_eq!(chunk.constants[idx as usize], Constant::Number(1.2));
     }
 
+    #[test]
+    fn debug_string_lists_operands_and_invalid_bytes() {
+        let mut chunk = Chunk::new();
+        let idx = chunk.add_constant(Constant::Number(1.5));
+        chunk.write_op(OpCode::Constant, 1);
+        chunk.write_byte(idx, 1);
+        chunk.write_op(OpCode::JumpIfFalse, 2);
+        chunk.write_u16(258, 2);
+        chunk.write_byte(250, 3);
+        chunk.write_op(OpCode::Return, 3);
+        assert_eq!(
+            chunk.to_debug_string(),
+            "constants:\n  0 number 1.5\ncode:\n  0 line 1 constant 0\n  \
+             2 line 2 jump_if_false 1 2\n  5 line 3 invalid 250\n  6 line 3 return\n"
+        );
+    }
+
+    #[test]
+    fn opcode_bytes_round_trip() {
+        let last = OpCode::DefineGlobalConst as u8;
+        assert_eq!(usize::from(last) + 1, <OpCode as strum::EnumCount>::COUNT);
+        for byte in 0..=last {
+            let op = OpCode::try_from(byte).expect("every byte up to the last opcode decodes");
+            assert_eq!(op as u8, byte);
+        }
+        for byte in last + 1..=u8::MAX {
+            assert_eq!(OpCode::try_from(byte), Err(byte));
+        }
+    }
+
+    #[test]
+    fn blox_version_tracks_the_opcode_set() {
+        // Adding an opcode changes what older builds can decode: bump
+        // BLOX_VERSION and then update this test.
+        assert_eq!((BLOX_VERSION, <OpCode as strum::EnumCount>::COUNT), (3, 52));
+    }
+
+    #[test]
+    fn blox_round_trip() {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Constant::String("hi".to_string()));
+        chunk.write_op(OpCode::Constant, 1);
+        chunk.write_byte(0, 1);
+        chunk.write_op(OpCode::Return, 1);
+        let bytes = chunk.to_blox().expect("encode");
+        assert_eq!(&bytes[..4], BLOX_MAGIC);
+        assert_eq!(bytes[4..8], BLOX_VERSION.to_le_bytes());
+        assert_eq!(bytes[8..10], native_count().to_le_bytes());
+        assert_eq!(Chunk::from_blox(&bytes).expect("decode"), chunk);
+    }
+
+    #[rstest::rstest]
+    #[case::empty(&[], "missing magic header")]
+    #[case::wrong_magic(b"blob\x03\0\0\0\x10\0", "missing magic header")]
+    #[case::truncated_header(b"blox\x03\0\0\0", "missing magic header")]
+    #[case::older_version(b"blox\x01\0\0\0\x10\0\x93", "blox format 1")]
+    #[case::newer_version(b"blox\xff\0\0\0\x10\0\x93", "blox format 255")]
+    #[case::fewer_natives(b"blox\x03\0\0\0\x0e\0\x93", "compiled with 14 natives")]
+    fn blox_header_is_checked(#[case] bytes: &[u8], #[case] expected: &str) {
+        let err = Chunk::from_blox(bytes).expect_err("should be rejected");
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[test]
+    fn instruction_count_respects_operands() {
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::JumpIfFalse, 1);
+        chunk.write_u16(1, 1);
+        chunk.write_op(OpCode::GetLocal, 1);
+        chunk.write_byte(OpCode::Return as u8, 1);
+        chunk.write_op(OpCode::Return, 1);
+        assert_eq!(chunk.byte_len(), 7);
+        assert_eq!(chunk.instruction_count(), 4);
+    }
+
+    #[test]
+    fn instruction_count_tolerates_invalid_bytes() {
+        let mut chunk = Chunk::new();
+        chunk.write_byte(250, 1);
+        chunk.write_op(OpCode::Return, 1);
+        assert_eq!(chunk.instruction_len(0), None);
+        assert_eq!(chunk.instruction_count(), 2);
+    }
+
+    #[test]
+    fn stats_include_nested_functions() {
+        let compiled = crate::vm::compile_to_chunk(
+            "fun add(a, b) { fun twice(n) { return n * 2; } return twice(a) + b; }\n\
+             print add(1, 2);",
+        )
+        .expect("compile should succeed");
+        let stats = ChunkStats::collect(&compiled);
+        assert_eq!(
+            stats,
+            ChunkStats {
+                // script + add + twice
+                instructions: 9 + 9 + 6,
+                code_bytes: 14 + 14 + 8,
+                number_constants: 2,
+                string_constants: 1,
+                function_constants: 2,
+            }
+        );
+    }
+
     #[test]
     fn disassemble_simple() {
         let mut chunk = Chunk::new();
@@ -480,12 +1053,18 @@ mod tests {
         chunk.write_op(OpCode::Equal, 1);
         chunk.write_op(OpCode::Greater, 1);
         chunk.write_op(OpCode::Less, 1);
+        chunk.write_op(OpCode::LessEqual, 1);
+        chunk.write_op(OpCode::GreaterEqual, 1);
+        chunk.write_op(OpCode::NotEqual, 1);
         chunk.write_op(OpCode::Return, 1);
 
         let text = disassemble(&chunk, "test").expect("valid bytecode");
         assert!(text.contains("nil"));
         assert!(text.contains("add"));
         assert!(text.contains("subtract"));
+        assert!(text.contains("less_equal"));
+        assert!(text.contains("greater_equal"));
+        assert!(text.contains("not_equal"));
         assert!(text.contains("return"));
     }
 
@@ -615,6 +1194,10 @@ mod tests {
         assert_eq!(OpCode::try_from(OpCode::True as u8), Ok(OpCode::True));
         assert_eq!(OpCode::try_from(OpCode::Return as u8), Ok(OpCode::Return));
         assert_eq!(OpCode::try_from(OpCode::Method as u8), Ok(OpCode::Method));
+        assert_eq!(
+            OpCode::try_from(OpCode::TailCall as u8),
+            Ok(OpCode::TailCall)
+        );
     }
 
     #[test]
@@ -675,6 +1258,129 @@ mod tests {
         assert!(text.contains("constant"));
     }
 
+    #[test]
+    fn nested_functions_are_indented_under_their_parent() {
+        let mut g_chunk = Chunk::new();
+        g_chunk.write_op(OpCode::Nil, 1);
+        g_chunk.write_op(OpCode::Return, 1);
+
+        let mut f_chunk = Chunk::new();
+        f_chunk.add_constant(Constant::Function {
+            name: "g".to_string(),
+            arity: 0,
+            upvalue_count: 0,
+            chunk: g_chunk,
+        });
+        f_chunk.write_op(OpCode::Return, 1);
+
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Constant::Function {
+            name: "f".to_string(),
+            arity: 0,
+            upvalue_count: 0,
+            chunk: f_chunk,
+        });
+        chunk.write_op(OpCode::Return, 1);
+
+        let text = disassemble(&chunk, "test.lox").expect("valid bytecode");
+        assert!(text.contains("\n  fun f();  // arity=0\n"), "{text}");
+        assert!(text.contains("\n    fun g();  // arity=0\n"), "{text}");
+        assert!(text.contains("\n          0: nil\n"), "{text}");
+    }
+
+    #[test]
+    fn disassembly_includes_every_nested_function() {
+        let chunk =
+            crate::vm::compile_to_chunk("fun f() { fun g() {} g(); }").expect("should compile");
+        let text = disassemble(&chunk, "nested.lox").expect("valid bytecode");
+        let f = text.find("== fn f ==").expect("header for f");
+        let g = text.find("== fn g ==").expect("header for g");
+        assert!(f < g, "g is disassembled inside f:\n{text}");
+    }
+
+    #[test]
+    fn disassembly_rejects_absurd_nesting() {
+        let mut chunk = Chunk::new();
+        for _ in 0..=MAX_DISASSEMBLY_DEPTH {
+            let mut outer = Chunk::new();
+            outer.add_constant(Constant::Function {
+                name: "f".to_string(),
+                arity: 0,
+                upvalue_count: 0,
+                chunk,
+            });
+            chunk = outer;
+        }
+        let err = disassemble(&chunk, "deep.blox").expect_err("should refuse");
+        assert!(err.to_string().contains("nested more than"), "{err}");
+    }
+
+    #[test]
+    fn disassemble_with_banner_prefixes_comment() {
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Return, 1);
+        let text = disassemble_with_banner(&chunk, "x.lox", "not executed").expect("valid");
+        assert!(text.starts_with("; not executed\nCompiled from \"x.lox\"\n"));
+    }
+
+    fn colorful_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        let idx = chunk.add_constant(Constant::String("a // b".to_string()));
+        chunk.write_op(OpCode::Constant, 1);
+        chunk.write_byte(idx, 1);
+        chunk.write_op(OpCode::JumpIfFalse, 1);
+        chunk.write_u16(1, 1);
+        chunk.write_op(OpCode::Pop, 1);
+        chunk.write_op(OpCode::Return, 1);
+        chunk
+    }
+
+    fn strip_ansi(text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('\x1b') {
+            out.push_str(&rest[..start]);
+            let end = rest[start..].find('m').expect("escape ends with m");
+            rest = &rest[start + end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    #[test]
+    fn disassembly_without_color_has_no_escape_codes() {
+        let text = disassemble_with_banner(&colorful_chunk(), "x.lox", "banner").expect("valid");
+        assert!(!text.contains('\x1b'), "{text}");
+    }
+
+    #[test]
+    fn colorized_disassembly_only_adds_escape_codes() {
+        let plain = disassemble_with_banner(&colorful_chunk(), "x.lox", "banner").expect("valid");
+        let colored = colorize_disassembly(&plain);
+        assert!(
+            colored.contains(&format!("{CYAN}constant{RESET}")),
+            "{colored}"
+        );
+        assert!(
+            colored.contains(&format!("{MAGENTA}String{RESET}")),
+            "{colored}"
+        );
+        assert!(
+            colored.contains(&format!("{YELLOW}-> 6{RESET}")),
+            "{colored}"
+        );
+        assert_eq!(strip_ansi(&colored), plain);
+    }
+
+    #[test]
+    fn color_needs_a_terminal_and_no_no_color() {
+        use std::ffi::OsStr;
+        assert!(resolve_color(true, None));
+        assert!(resolve_color(true, Some(OsStr::new(""))));
+        assert!(!resolve_color(true, Some(OsStr::new("1"))));
+        assert!(!resolve_color(false, None));
+    }
+
     #[test]
     fn test_jump_target_format() {
         let mut chunk = Chunk::new();
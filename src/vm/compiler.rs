@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use crate::ast::*;
 use crate::error::CompileError;
+use crate::scanner::token::Span;
 use crate::vm::chunk::{Chunk, Constant, OpCode};
+use crate::vm::vm::NativeFn;
 
 #[derive(Debug, Clone)]
 struct Local {
@@ -61,20 +65,59 @@ impl CompilerState {
 
 pub struct Compiler {
     states: Vec<CompilerState>,
+    /// Byte offset at which each source line starts, for mapping spans to lines.
+    line_starts: Vec<usize>,
+    /// VM global slot of every global defined so far in compilation order:
+    /// the natives, then each top-level declaration. Reads of these names
+    /// compile to `GetGlobalByIndex`; anything else falls back to a lookup
+    /// by name.
+    global_slots: HashMap<String, u8>,
 }
 
 impl Compiler {
+    /// Create a compiler without source text; every instruction is attributed
+    /// to line 1. Use [`Compiler::with_source`] for accurate line numbers.
     pub fn new() -> Self {
         Self {
             states: vec![CompilerState::new(FunctionType::Script)],
+            line_starts: vec![0],
+            global_slots: native_global_slots(),
+        }
+    }
+
+    /// Create a compiler that records real line numbers for `source`.
+    pub fn with_source(source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            states: vec![CompilerState::new(FunctionType::Script)],
+            line_starts,
+            global_slots: native_global_slots(),
         }
     }
 
     pub fn compile(mut self, program: &Program) -> Result<Chunk, CompileError> {
-        for decl in &program.declarations {
+        let (last, rest) = match program.declarations.split_last() {
+            Some((last, rest)) => (Some(last), rest),
+            None => (None, &[][..]),
+        };
+        for decl in rest {
             self.compile_decl(decl)?;
         }
-        self.emit_op(OpCode::Nil);
+        // A trailing expression statement becomes the script's return value
+        // (see `Vm::interpret_returning`) instead of being popped.
+        match last {
+            Some(Decl::Statement(Stmt::Expression(e))) => {
+                self.set_line(e.span);
+                self.compile_expr(&e.expression)?;
+            }
+            Some(decl) => {
+                self.compile_decl(decl)?;
+                self.emit_op(OpCode::Nil);
+            }
+            None => self.emit_op(OpCode::Nil),
+        }
         self.emit_op(OpCode::Return);
         Ok(self.states.pop().expect("should have script state").chunk)
     }
@@ -89,6 +132,14 @@ impl Compiler {
             .expect("compiler state stack non-empty")
     }
 
+    /// Set the line recorded for subsequently emitted bytecode.
+    fn set_line(&mut self, span: Span) {
+        let line = self
+            .line_starts
+            .partition_point(|&start| start <= span.offset);
+        self.current_mut().line = line;
+    }
+
     fn emit_op(&mut self, op: OpCode) {
         let line = self.current().line;
         self.current_mut().chunk.write_op(op, line);
@@ -112,23 +163,35 @@ impl Compiler {
         self.current().chunk.code.len() - 2
     }
 
-    fn patch_jump(&mut self, offset: usize) {
+    /// Point the jump emitted at `offset` to the current end of the chunk.
+    /// `span` is the construct that owns the jump, reported if it's too far.
+    fn patch_jump(&mut self, offset: usize, span: Span) -> Result<(), CompileError> {
         let jump = self.current().chunk.code.len() - offset - 2;
         if jump > u16::MAX as usize {
-            panic!("jump too large");
+            return Err(CompileError::compile(
+                "too much code to jump over",
+                span.offset,
+                span.len,
+            ));
         }
         self.current_mut().chunk.code[offset] = (jump >> 8) as u8;
         self.current_mut().chunk.code[offset + 1] = (jump & 0xff) as u8;
+        Ok(())
     }
 
-    fn emit_loop(&mut self, loop_start: usize) {
+    fn emit_loop(&mut self, loop_start: usize, span: Span) -> Result<(), CompileError> {
         self.emit_op(OpCode::Loop);
         let offset = self.current().chunk.code.len() - loop_start + 2;
         if offset > u16::MAX as usize {
-            panic!("loop body too large");
+            return Err(CompileError::compile(
+                "loop body too large",
+                span.offset,
+                span.len,
+            ));
         }
         let line = self.current().line;
         self.current_mut().chunk.write_u16(offset as u16, line);
+        Ok(())
     }
 
     fn begin_scope(&mut self) {
@@ -138,17 +201,50 @@ impl Compiler {
     fn end_scope(&mut self) {
         self.current_mut().scope_depth -= 1;
         let depth = self.current().scope_depth;
+        // Runs of uncaptured locals are popped together; a captured local
+        // still needs its own `CloseUpvalue`.
+        let mut pending = 0;
         while let Some(local) = self.current().locals.last() {
             if local.depth <= depth {
                 break;
             }
             if local.is_captured {
+                self.emit_pops(pending);
+                pending = 0;
                 self.emit_op(OpCode::CloseUpvalue);
             } else {
-                self.emit_op(OpCode::Pop);
+                pending += 1;
             }
             self.current_mut().locals.pop();
         }
+        self.emit_pops(pending);
+    }
+
+    /// Pop `count` values: a `Pop` for one, `PopN` for more.
+    fn emit_pops(&mut self, count: usize) {
+        let mut remaining = count;
+        while remaining > 1 {
+            let n = u8::try_from(remaining).unwrap_or(u8::MAX);
+            self.emit_op(OpCode::PopN);
+            self.emit_byte(n);
+            remaining -= n as usize;
+        }
+        if remaining == 1 {
+            self.emit_op(OpCode::Pop);
+        }
+    }
+
+    /// Record that top-level code defines global `name`, mirroring the slot
+    /// the VM's `DefineGlobal` will give it. Top-level declarations always
+    /// run in the order they are compiled, so any read compiled after this
+    /// point is guaranteed to find the slot filled.
+    fn define_global_slot(&mut self, name: &str) {
+        let next = self.global_slots.len();
+        if let Ok(slot) = u8::try_from(next)
+            && !self.global_slots.contains_key(name)
+        {
+            self.global_slots.insert(name.to_string(), slot);
+        }
     }
 
     fn add_local(&mut self, name: String) {
@@ -212,7 +308,7 @@ impl Compiler {
     fn compile_decl(&mut self, decl: &Decl) -> Result<(), CompileError> {
         match decl {
             Decl::Var(v) => {
-                self.current_mut().line = line_from_span(v.span);
+                self.set_line(v.span);
                 if let Some(ref init) = v.initializer {
                     self.compile_expr(init)?;
                 } else {
@@ -225,13 +321,23 @@ impl Compiler {
                         .current_mut()
                         .chunk
                         .add_constant(Constant::String(v.name.clone()));
-                    self.emit_op(OpCode::DefineGlobal);
+                    self.emit_op(if v.is_const {
+                        OpCode::DefineGlobalConst
+                    } else {
+                        OpCode::DefineGlobal
+                    });
                     self.emit_byte(idx);
+                    self.define_global_slot(&v.name);
                 }
                 Ok(())
             }
             Decl::Fun(f) => {
-                self.current_mut().line = line_from_span(f.span);
+                self.set_line(f.span);
+                // The body can only run once the function is defined, so
+                // recursive calls may read the global by slot.
+                if self.current().scope_depth == 0 {
+                    self.define_global_slot(&f.function.name);
+                }
                 self.compile_function(&f.function, FunctionType::Function)?;
                 if self.current().scope_depth > 0 {
                     self.add_local(f.function.name.clone());
@@ -297,7 +403,7 @@ impl Compiler {
     }
 
     fn compile_class(&mut self, class: &ClassDecl) -> Result<(), CompileError> {
-        self.current_mut().line = line_from_span(class.span);
+        self.set_line(class.span);
         let name_idx = self
             .current_mut()
             .chunk
@@ -310,6 +416,7 @@ impl Compiler {
         } else {
             self.emit_op(OpCode::DefineGlobal);
             self.emit_byte(name_idx);
+            self.define_global_slot(&class.name);
         }
 
         if let Some(ref superclass) = class.superclass {
@@ -349,19 +456,26 @@ impl Compiler {
     fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
         match stmt {
             Stmt::Expression(e) => {
-                self.current_mut().line = line_from_span(e.span);
+                self.set_line(e.span);
                 self.compile_expr(&e.expression)?;
                 self.emit_op(OpCode::Pop);
                 Ok(())
             }
             Stmt::Print(p) => {
-                self.current_mut().line = line_from_span(p.span);
-                self.compile_expr(&p.expression)?;
-                self.emit_op(OpCode::Print);
+                self.set_line(p.span);
+                for expr in &p.expressions {
+                    self.compile_expr(expr)?;
+                }
+                if p.expressions.len() == 1 {
+                    self.emit_op(OpCode::Print);
+                } else {
+                    self.emit_op(OpCode::PrintN);
+                    self.emit_byte(p.expressions.len() as u8);
+                }
                 Ok(())
             }
             Stmt::Return(r) => {
-                self.current_mut().line = line_from_span(r.span);
+                self.set_line(r.span);
                 if let Some(ref val) = r.value {
                     if self.current().function_type == FunctionType::Initializer {
                         return Err(CompileError::resolve(
@@ -370,7 +484,13 @@ impl Compiler {
                             r.span.len,
                         ));
                     }
-                    self.compile_expr(val)?;
+                    match val {
+                        // A call in tail position can reuse the current frame
+                        Expr::Call(c) if self.current().function_type != FunctionType::Script => {
+                            self.compile_call(c, OpCode::TailCall)?;
+                        }
+                        _ => self.compile_expr(val)?,
+                    }
                 } else if self.current().function_type == FunctionType::Initializer {
                     self.emit_op(OpCode::GetLocal);
                     self.emit_byte(0);
@@ -381,7 +501,7 @@ impl Compiler {
                 Ok(())
             }
             Stmt::Block(b) => {
-                self.current_mut().line = line_from_span(b.span);
+                self.set_line(b.span);
                 self.begin_scope();
                 for decl in &b.declarations {
                     self.compile_decl(decl)?;
@@ -390,29 +510,29 @@ impl Compiler {
                 Ok(())
             }
             Stmt::If(i) => {
-                self.current_mut().line = line_from_span(i.span);
+                self.set_line(i.span);
                 self.compile_expr(&i.condition)?;
                 let then_jump = self.emit_jump(OpCode::JumpIfFalse);
                 self.emit_op(OpCode::Pop);
                 self.compile_stmt(&i.then_branch)?;
                 let else_jump = self.emit_jump(OpCode::Jump);
-                self.patch_jump(then_jump);
+                self.patch_jump(then_jump, i.span)?;
                 self.emit_op(OpCode::Pop);
                 if let Some(ref else_branch) = i.else_branch {
                     self.compile_stmt(else_branch)?;
                 }
-                self.patch_jump(else_jump);
+                self.patch_jump(else_jump, i.span)?;
                 Ok(())
             }
             Stmt::While(w) => {
-                self.current_mut().line = line_from_span(w.span);
+                self.set_line(w.span);
                 let loop_start = self.current().chunk.code.len();
                 self.compile_expr(&w.condition)?;
                 let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
                 self.emit_op(OpCode::Pop);
                 self.compile_stmt(&w.body)?;
-                self.emit_loop(loop_start);
-                self.patch_jump(exit_jump);
+                self.emit_loop(loop_start, w.span)?;
+                self.patch_jump(exit_jump, w.span)?;
                 self.emit_op(OpCode::Pop);
                 Ok(())
             }
@@ -422,8 +542,13 @@ impl Compiler {
     fn compile_expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
         match expr {
             Expr::Literal(l) => {
-                self.current_mut().line = line_from_span(l.span);
+                self.set_line(l.span);
                 match &l.value {
+                    // Bit-compare so `-0` (never a literal today) can't become `0`.
+                    LiteralValue::Number(n) if n.to_bits() == 0f64.to_bits() => {
+                        self.emit_op(OpCode::Zero)
+                    }
+                    LiteralValue::Number(n) if *n == 1.0 => self.emit_op(OpCode::One),
                     LiteralValue::Number(n) => self.emit_constant(Constant::Number(*n)),
                     LiteralValue::String(s) => {
                         self.emit_constant(Constant::String(s.clone()));
@@ -446,35 +571,30 @@ impl Compiler {
             Expr::Binary(b) => {
                 self.compile_expr(&b.left)?;
                 self.compile_expr(&b.right)?;
+                self.set_line(b.span);
                 match b.operator {
                     BinaryOp::Add => self.emit_op(OpCode::Add),
                     BinaryOp::Subtract => self.emit_op(OpCode::Subtract),
                     BinaryOp::Multiply => self.emit_op(OpCode::Multiply),
                     BinaryOp::Divide => self.emit_op(OpCode::Divide),
+                    BinaryOp::Modulo => self.emit_op(OpCode::Modulo),
+                    BinaryOp::Power => self.emit_op(OpCode::Power),
+                    BinaryOp::Is => self.emit_op(OpCode::IsInstance),
                     BinaryOp::Equal => self.emit_op(OpCode::Equal),
-                    BinaryOp::NotEqual => {
-                        self.emit_op(OpCode::Equal);
-                        self.emit_op(OpCode::Not);
-                    }
+                    BinaryOp::NotEqual => self.emit_op(OpCode::NotEqual),
                     BinaryOp::Less => self.emit_op(OpCode::Less),
-                    BinaryOp::LessEqual => {
-                        self.emit_op(OpCode::Greater);
-                        self.emit_op(OpCode::Not);
-                    }
+                    BinaryOp::LessEqual => self.emit_op(OpCode::LessEqual),
                     BinaryOp::Greater => self.emit_op(OpCode::Greater),
-                    BinaryOp::GreaterEqual => {
-                        self.emit_op(OpCode::Less);
-                        self.emit_op(OpCode::Not);
-                    }
+                    BinaryOp::GreaterEqual => self.emit_op(OpCode::GreaterEqual),
                 }
                 Ok(())
             }
             Expr::Variable(v) => {
-                self.current_mut().line = line_from_span(v.span);
+                self.set_line(v.span);
                 self.compile_named_variable(&v.name)
             }
             Expr::Assign(a) => {
-                self.current_mut().line = line_from_span(a.span);
+                self.set_line(a.span);
                 self.compile_expr(&a.value)?;
                 if let Some(slot) = self.resolve_local(&a.name) {
                     self.emit_op(OpCode::SetLocal);
@@ -499,28 +619,28 @@ impl Compiler {
                         let end_jump = self.emit_jump(OpCode::JumpIfFalse);
                         self.emit_op(OpCode::Pop);
                         self.compile_expr(&l.right)?;
-                        self.patch_jump(end_jump);
+                        self.patch_jump(end_jump, l.span)?;
                     }
                     LogicalOp::Or => {
                         let else_jump = self.emit_jump(OpCode::JumpIfFalse);
                         let end_jump = self.emit_jump(OpCode::Jump);
-                        self.patch_jump(else_jump);
+                        self.patch_jump(else_jump, l.span)?;
                         self.emit_op(OpCode::Pop);
                         self.compile_expr(&l.right)?;
-                        self.patch_jump(end_jump);
+                        self.patch_jump(end_jump, l.span)?;
+                    }
+                    LogicalOp::Coalesce => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfNil);
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(else_jump, l.span)?;
+                        self.emit_op(OpCode::Pop);
+                        self.compile_expr(&l.right)?;
+                        self.patch_jump(end_jump, l.span)?;
                     }
                 }
                 Ok(())
             }
-            Expr::Call(c) => {
-                self.compile_expr(&c.callee)?;
-                for arg in &c.arguments {
-                    self.compile_expr(arg)?;
-                }
-                self.emit_op(OpCode::Call);
-                self.emit_byte(c.arguments.len() as u8);
-                Ok(())
-            }
+            Expr::Call(c) => self.compile_call(c, OpCode::Call),
             Expr::Get(g) => {
                 self.compile_expr(&g.object)?;
                 let idx = self
@@ -543,18 +663,24 @@ impl Compiler {
                 Ok(())
             }
             Expr::This(t) => {
-                self.current_mut().line = line_from_span(t.span);
+                self.set_line(t.span);
                 if let Some(slot) = self.resolve_local("this") {
                     self.emit_op(OpCode::GetLocal);
                     self.emit_byte(slot);
                 } else if let Some(idx) = self.resolve_upvalue("this") {
                     self.emit_op(OpCode::GetUpvalue);
                     self.emit_byte(idx);
+                } else {
+                    return Err(CompileError::resolve(
+                        "can't use 'this' outside of a class",
+                        t.span.offset,
+                        t.span.len,
+                    ));
                 }
                 Ok(())
             }
             Expr::Super(s) => {
-                self.current_mut().line = line_from_span(s.span);
+                self.set_line(s.span);
                 let method_idx = self
                     .current_mut()
                     .chunk
@@ -568,6 +694,33 @@ impl Compiler {
         }
     }
 
+    /// Compile a call as either a regular `Call` or a `TailCall`.
+    fn compile_call(&mut self, call: &CallExpr, op: OpCode) -> Result<(), CompileError> {
+        // `obj.name(args)` skips materializing a bound method
+        if let (OpCode::Call, Expr::Get(get)) = (op, call.callee.as_ref()) {
+            self.compile_expr(&get.object)?;
+            for arg in &call.arguments {
+                self.compile_expr(arg)?;
+            }
+            let idx = self
+                .current_mut()
+                .chunk
+                .add_constant(Constant::String(get.name.clone()));
+            self.set_line(call.span);
+            self.emit_op(OpCode::Invoke);
+            self.emit_byte(idx);
+            self.emit_byte(call.arguments.len() as u8);
+            return Ok(());
+        }
+        self.compile_expr(&call.callee)?;
+        for arg in &call.arguments {
+            self.compile_expr(arg)?;
+        }
+        self.emit_op(op);
+        self.emit_byte(call.arguments.len() as u8);
+        Ok(())
+    }
+
     fn compile_named_variable(&mut self, name: &str) -> Result<(), CompileError> {
         if let Some(slot) = self.resolve_local(name) {
             self.emit_op(OpCode::GetLocal);
@@ -575,6 +728,9 @@ impl Compiler {
         } else if let Some(idx) = self.resolve_upvalue(name) {
             self.emit_op(OpCode::GetUpvalue);
             self.emit_byte(idx);
+        } else if let Some(&slot) = self.global_slots.get(name) {
+            self.emit_op(OpCode::GetGlobalByIndex);
+            self.emit_byte(slot);
         } else {
             let idx = self
                 .current_mut()
@@ -587,23 +743,31 @@ impl Compiler {
     }
 }
 
+/// Global slots the VM assigns its natives before running any code.
+fn native_global_slots() -> HashMap<String, u8> {
+    NativeFn::ALL
+        .into_iter()
+        .enumerate()
+        .map(|(slot, native)| {
+            let slot = u8::try_from(slot).expect("fewer than 256 natives");
+            (native.name().to_string(), slot)
+        })
+        .collect()
+}
+
 impl Default for Compiler {
     fn default() -> Self {
         Self::new()
     }
 }
 
-fn line_from_span(span: crate::scanner::token::Span) -> usize {
-    // We don't have line info in spans, so use offset as a proxy
-    span.offset + 1
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::parser::Parser;
     use crate::scanner;
     use crate::vm::chunk::OpCode;
+    use rstest::rstest;
 
     fn compile(source: &str) -> Result<Chunk, CompileError> {
         let tokens = scanner::scan(source).expect("scan should succeed");
@@ -689,13 +853,14 @@ mod tests {
     fn compile_addition() {
         let chunk = compile_expr("1 + 2").expect("compile should succeed");
         assert!(has_opcode(&chunk, OpCode::Add));
-        // Should have at least 2 number constants
+        // `1` is pushed by `One`; only `2` needs a number constant
+        assert!(has_opcode(&chunk, OpCode::One));
         let num_constants = chunk
             .constants
             .iter()
             .filter(|c| matches!(c, Constant::Number(_)))
             .count();
-        assert!(num_constants >= 2);
+        assert_eq!(num_constants, 1);
     }
 
     #[test]
@@ -716,6 +881,30 @@ mod tests {
         assert!(has_opcode(&chunk, OpCode::Divide));
     }
 
+    #[test]
+    fn compile_modulo() {
+        let chunk = compile_expr("10 % 3").expect("compile should succeed");
+        assert!(has_opcode(&chunk, OpCode::Modulo));
+    }
+
+    #[test]
+    fn with_source_records_line_numbers() {
+        let source = "print 1;\n\nprint 2;";
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let chunk = Compiler::with_source(source)
+            .compile(&program)
+            .expect("compile should succeed");
+        let print_lines: Vec<usize> = chunk
+            .code
+            .iter()
+            .zip(&chunk.lines)
+            .filter(|(byte, _)| **byte == OpCode::Print as u8)
+            .map(|(_, line)| *line)
+            .collect();
+        assert_eq!(print_lines, vec![1, 3]);
+    }
+
     #[test]
     fn compile_negation() {
         let chunk = compile_expr("-42").expect("compile should succeed");
@@ -739,9 +928,8 @@ mod tests {
     #[test]
     fn compile_not_equal() {
         let chunk = compile_expr("1 != 2").expect("compile should succeed");
-        // != is compiled as == followed by Not
-        assert!(has_opcode(&chunk, OpCode::Equal));
-        assert!(has_opcode(&chunk, OpCode::Not));
+        assert!(has_opcode(&chunk, OpCode::NotEqual));
+        assert!(!has_opcode(&chunk, OpCode::Not));
     }
 
     #[test]
@@ -753,9 +941,8 @@ mod tests {
     #[test]
     fn compile_less_equal() {
         let chunk = compile_expr("1 <= 2").expect("compile should succeed");
-        // <= is compiled as > followed by Not
-        assert!(has_opcode(&chunk, OpCode::Greater));
-        assert!(has_opcode(&chunk, OpCode::Not));
+        assert!(has_opcode(&chunk, OpCode::LessEqual));
+        assert!(!has_opcode(&chunk, OpCode::Not));
     }
 
     #[test]
@@ -767,9 +954,8 @@ mod tests {
     #[test]
     fn compile_greater_equal() {
         let chunk = compile_expr("1 >= 2").expect("compile should succeed");
-        // >= is compiled as < followed by Not
-        assert!(has_opcode(&chunk, OpCode::Less));
-        assert!(has_opcode(&chunk, OpCode::Not));
+        assert!(has_opcode(&chunk, OpCode::GreaterEqual));
+        assert!(!has_opcode(&chunk, OpCode::Not));
     }
 
     // ========== Variables ==========
@@ -798,8 +984,49 @@ mod tests {
 
     #[test]
     fn compile_get_global() {
-        let chunk = compile("var x = 1; print x;").expect("compile should succeed");
+        let chunk = compile("fun f() { print x; } var x = 1;").expect("compile should succeed");
+        let Constant::Function { chunk: body, .. } = &chunk.constants[0] else {
+            panic!("expected function constant");
+        };
+        // `x` is not defined yet when `f` is compiled, so it is looked up by name
+        assert!(has_opcode(body, OpCode::GetGlobal));
+        assert!(!has_opcode(body, OpCode::GetGlobalByIndex));
+    }
+
+    /// Slot of the first user-defined global; the natives come first.
+    const FIRST_USER_SLOT: u8 = NativeFn::ALL.len() as u8;
+
+    #[rstest]
+    #[case::backward_reference("var x = 1; print x;", FIRST_USER_SLOT)]
+    #[case::native("print clock;", 0)]
+    #[case::second_global("var a = 1; var b = 2; print b;", FIRST_USER_SLOT + 1)]
+    #[case::redefinition_keeps_slot("var a = 1; var b = 2; var a = 3; print a;", FIRST_USER_SLOT)]
+    fn compile_get_global_by_index(#[case] source: &str, #[case] slot: u8) {
+        let chunk = compile(source).expect("compile should succeed");
+        assert!(!has_opcode(&chunk, OpCode::GetGlobal));
+        let at = chunk
+            .code
+            .iter()
+            .position(|&b| b == OpCode::GetGlobalByIndex as u8)
+            .expect("GetGlobalByIndex emitted");
+        assert_eq!(chunk.code[at + 1], slot);
+    }
+
+    #[test]
+    fn compile_recursive_function_reads_itself_by_index() {
+        let chunk = compile("fun f(n) { if (n > 0) f(n - 1); }").expect("compile should succeed");
+        let Constant::Function { chunk: body, .. } = &chunk.constants[0] else {
+            panic!("expected function constant");
+        };
+        assert!(has_opcode(body, OpCode::GetGlobalByIndex));
+        assert!(!has_opcode(body, OpCode::GetGlobal));
+    }
+
+    #[test]
+    fn compile_initializer_reading_its_own_name_uses_name_lookup() {
+        let chunk = compile("var a = a;").expect("compile should succeed");
         assert!(has_opcode(&chunk, OpCode::GetGlobal));
+        assert!(!has_opcode(&chunk, OpCode::GetGlobalByIndex));
     }
 
     #[test]
@@ -857,6 +1084,30 @@ mod tests {
         assert!(has_opcode(&chunk, OpCode::Jump));
     }
 
+    #[test]
+    fn compile_nil_coalescing() {
+        let chunk = compile_expr("nil ?? 1").expect("compile should succeed");
+        assert!(has_opcode(&chunk, OpCode::JumpIfNil));
+        assert!(!has_opcode(&chunk, OpCode::JumpIfFalse));
+    }
+
+    #[test]
+    fn compile_nil_coalescing_jump_layout() {
+        let chunk = compile_expr("nil ?? 1").expect("compile should succeed");
+        let code = &chunk.code;
+        assert_eq!(code[0], OpCode::Nil as u8);
+        assert_eq!(code[1], OpCode::JumpIfNil as u8);
+        // On nil, skip just the `Jump` that would keep the left value
+        assert_eq!(chunk.read_u16(2), 3);
+        assert_eq!(code[4], OpCode::Jump as u8);
+        // ...and land on the `Pop` that discards it before the right side
+        assert_eq!(code[7], OpCode::Pop as u8);
+        assert_eq!(code[8], OpCode::One as u8);
+        // The `Jump` skips the right side: `Pop` plus `One`
+        assert_eq!(chunk.read_u16(5), 2);
+        assert_eq!(code[9], OpCode::Print as u8);
+    }
+
     // ========== Functions ==========
 
     #[test]
@@ -893,6 +1144,36 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn compile_tail_call() {
+        let chunk = compile("fun f(n) { return f(n); }").expect("compile should succeed");
+        assert!(chunk.constants.iter().any(|c| {
+            if let Constant::Function {
+                chunk: func_chunk, ..
+            } = c
+            {
+                has_opcode(func_chunk, OpCode::TailCall) && !has_opcode(func_chunk, OpCode::Call)
+            } else {
+                false
+            }
+        }));
+    }
+
+    #[test]
+    fn compile_non_tail_call_uses_call() {
+        let chunk = compile("fun f(n) { return f(n) + 1; }").expect("compile should succeed");
+        assert!(!chunk.constants.iter().any(|c| {
+            if let Constant::Function {
+                chunk: func_chunk, ..
+            } = c
+            {
+                has_opcode(func_chunk, OpCode::TailCall)
+            } else {
+                false
+            }
+        }));
+    }
+
     #[test]
     fn compile_implicit_return() {
         let chunk = compile("fun f() { 42; }").expect("compile should succeed");
@@ -1015,6 +1296,14 @@ mod tests {
         assert!(has_opcode(&chunk, OpCode::Inherit));
     }
 
+    #[test]
+    fn compile_method_call_uses_invoke() {
+        let chunk =
+            compile("class Foo { bar(x) {} } Foo().bar(1);").expect("compile should succeed");
+        assert!(has_opcode(&chunk, OpCode::Invoke));
+        assert!(!has_opcode(&chunk, OpCode::GetProperty));
+    }
+
     #[test]
     fn compile_get_property() {
         let chunk =
@@ -1098,18 +1387,141 @@ mod tests {
         assert!(has_opcode(&chunk, OpCode::Print));
     }
 
+    #[test]
+    fn compile_print_multiple_values() {
+        let chunk = compile("print 1, 2, 3;").expect("compile should succeed");
+        assert!(has_opcode(&chunk, OpCode::PrintN));
+        assert!(!has_opcode(&chunk, OpCode::Print));
+        let pos = chunk
+            .code
+            .iter()
+            .position(|&b| b == OpCode::PrintN as u8)
+            .expect("PrintN emitted");
+        assert_eq!(chunk.code[pos + 1], 3);
+    }
+
     #[test]
     fn compile_expression_statement() {
-        let chunk = compile("1 + 2;").expect("compile should succeed");
+        let chunk = compile("1 + 2; print 3;").expect("compile should succeed");
         // Expression statements should pop the result
         assert!(has_opcode(&chunk, OpCode::Pop));
     }
 
+    #[test]
+    fn compile_trailing_expression_is_script_result() {
+        let chunk = compile("1 + 2;").expect("compile should succeed");
+        // The final expression's value is returned instead of popped
+        assert!(!has_opcode(&chunk, OpCode::Pop));
+        assert_eq!(
+            &chunk.code[chunk.code.len() - 2..],
+            &[OpCode::Add as u8, OpCode::Return as u8]
+        );
+    }
+
     #[test]
     fn compile_block() {
         let chunk = compile("{ var x = 1; var y = 2; }").expect("compile should succeed");
-        // Should pop locals at end of block
-        assert_eq!(count_opcode(&chunk, OpCode::Pop), 2);
+        // Should pop both locals at once at end of block
+        assert_eq!(count_opcode(&chunk, OpCode::Pop), 0);
+        assert!(has_opcode(&chunk, OpCode::PopN));
+    }
+
+    /// Decoded (opcode, operand) pairs for the pops and upvalue closes.
+    fn scope_exits(chunk: &Chunk) -> Vec<(OpCode, Option<u8>)> {
+        let mut exits = Vec::new();
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let op = OpCode::try_from(chunk.code[offset]).expect("valid opcode");
+            match op {
+                OpCode::Pop | OpCode::CloseUpvalue => exits.push((op, None)),
+                OpCode::PopN => exits.push((op, Some(chunk.code[offset + 1]))),
+                _ => {}
+            }
+            offset += chunk.instruction_len(offset).expect("valid instruction");
+        }
+        exits
+    }
+
+    #[test]
+    fn block_locals_are_popped_together() {
+        let chunk = compile("{ var a = 1; var b = 2; var c = 3; var d = 4; var e = 5; }")
+            .expect("compile should succeed");
+        assert_eq!(scope_exits(&chunk), vec![(OpCode::PopN, Some(5))]);
+    }
+
+    #[test]
+    fn captured_local_splits_the_pops() {
+        let chunk = compile(
+            "{ var a = 1; var b = 2; var c = 3; fun f() { return b; } var d = 4; var e = 5; }",
+        )
+        .expect("compile should succeed");
+        // Popped in reverse: e, d, f, c together; b closed; then a alone.
+        assert_eq!(
+            scope_exits(&chunk),
+            vec![
+                (OpCode::PopN, Some(4)),
+                (OpCode::CloseUpvalue, None),
+                (OpCode::Pop, None),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case::constant("const x = 2;", OpCode::DefineGlobalConst)]
+    #[case::variable("var x = 2;", OpCode::DefineGlobal)]
+    fn global_definition_opcode(#[case] source: &str, #[case] op: OpCode) {
+        let chunk = compile(source).expect("compile should succeed");
+        assert_eq!(chunk.code[2], op as u8);
+    }
+
+    #[test]
+    fn zero_and_one_literals_use_dedicated_opcodes() {
+        let chunk = compile("var i = 0; var j = 1;").expect("compile should succeed");
+        assert_eq!(chunk.code[0], OpCode::Zero as u8);
+        assert!(has_opcode(&chunk, OpCode::One));
+        assert!(
+            !chunk
+                .constants
+                .iter()
+                .any(|c| matches!(c, Constant::Number(_)))
+        );
+    }
+
+    #[rstest]
+    #[case::two("2")]
+    #[case::fraction("0.5")]
+    #[case::one_point_five("1.5")]
+    fn other_number_literals_use_the_constant_pool(#[case] literal: &str) {
+        let chunk = compile_expr(literal).expect("compile should succeed");
+        assert_eq!(chunk.code[0], OpCode::Constant as u8);
+        assert!(!has_opcode(&chunk, OpCode::Zero));
+        assert!(!has_opcode(&chunk, OpCode::One));
+    }
+
+    // ========== Code size ==========
+
+    #[test]
+    fn compile_print_sum_size() {
+        let chunk = compile("print 1 + 2;").expect("compile should succeed");
+        // One, Constant, Add, Print, then the implicit Nil, Return
+        assert_eq!(chunk.instruction_count(), 6);
+        assert_eq!(chunk.byte_len(), 7);
+    }
+
+    #[test]
+    fn instruction_count_includes_closure_upvalue_operands() {
+        let chunk = compile("fun outer() { var a; var b; fun inner() { return a + b; } }")
+            .expect("compile should succeed");
+        let Some(Constant::Function { chunk: outer, .. }) = chunk.constants.first() else {
+            panic!("expected outer function constant");
+        };
+        let closure_at = outer
+            .code
+            .iter()
+            .position(|&byte| byte == OpCode::Closure as u8)
+            .expect("outer creates a closure");
+        // Closure, constant index, then (is_local, index) for `a` and `b`
+        assert_eq!(outer.instruction_len(closure_at), Some(6));
     }
 
     // ========== Error Cases ==========
@@ -1122,6 +1534,37 @@ mod tests {
         assert!(err.to_string().contains("initializer"));
     }
 
+    #[rstest]
+    #[case::top_level("print this;")]
+    #[case::plain_function("fun f() { return this; }")]
+    fn compile_this_outside_class_errors(#[case] source: &str) {
+        let err = compile(source).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("can't use 'this' outside of a class"),
+            "{err}"
+        );
+    }
+
+    /// A block of `nil;` statements big enough that jumping over it needs
+    /// more than a 16-bit offset (each statement is `Nil` + `Pop`).
+    fn oversized_block() -> String {
+        format!("{{ {} }}", "nil;".repeat(u16::MAX as usize / 2 + 1))
+    }
+
+    #[rstest]
+    #[case::loop_body("while (false) ", "loop body too large")]
+    #[case::if_branch("if (true) ", "too much code to jump over")]
+    fn compile_oversized_jump_errors(#[case] prefix: &str, #[case] expected: &str) {
+        let source = format!("{prefix}{}", oversized_block());
+        let err = compile(&source).expect_err("oversized jump should not compile");
+        assert!(
+            err.to_string().contains(expected),
+            "unexpected error: {err}"
+        );
+        assert_eq!(err.span().offset(), 0);
+    }
+
     // ========== Complex Programs ==========
 
     #[test]
@@ -1164,4 +1607,63 @@ mod tests {
             "Expected count() to capture 'i' as upvalue"
         );
     }
+
+    #[rstest]
+    #[case::globals("var a = 1; var b = a + 2; print a, b;")]
+    #[case::closures(
+        "fun outer() { var x = 1; var y = 2; fun inner() { return x + y; } return inner; } print outer()();"
+    )]
+    #[case::classes(
+        "class A { init(n) { this.n = n; } get() { return this.n; } } \
+         class B < A { get() { return super.get() * 2; } } print B(3).get();"
+    )]
+    #[case::control_flow(
+        "for (var i = 0; i < 3; i = i + 1) { if (i != 1) print i; else print nil ?? \"one\"; }"
+    )]
+    fn compilation_is_deterministic(#[case] source: &str) {
+        let first = compile(source).expect("compile should succeed");
+        let second = compile(source).expect("compile should succeed");
+        assert_eq!(first.to_debug_string(), second.to_debug_string());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn debug_string_snapshot() {
+        let source = "var a = 1;\nfun f(x) { return x + a; }\nprint f(2);";
+        let tokens = scanner::scan(source).expect("scan should succeed");
+        let program = Parser::new(tokens).parse().expect("parse should succeed");
+        let chunk = Compiler::with_source(source)
+            .compile(&program)
+            .expect("compile should succeed");
+        assert_eq!(
+            chunk.to_debug_string(),
+            "\
+constants:
+  0 string \"a\"
+  1 function f arity=1 upvalues=0
+  2 string \"f\"
+  3 number 2
+code:
+  0 line 1 one
+  1 line 1 define_global 0
+  3 line 2 closure 1
+  5 line 2 define_global 2
+  7 line 3 get_global_by_index 17
+  9 line 3 constant 3
+  11 line 3 call 1
+  13 line 3 print
+  14 line 3 nil
+  15 line 3 return
+function 1 f:
+  constants:
+  code:
+    0 line 2 get_local 1
+    2 line 2 get_global_by_index 16
+    4 line 2 add
+    5 line 2 return
+    6 line 2 nil
+    7 line 2 return
+"
+        );
+    }
 }
@@ -1,41 +1,34 @@
 pub mod chunk;
 pub mod compiler;
+pub mod optimizer;
 #[allow(clippy::module_inception)]
 pub mod vm;
 
-use crate::error::{CompileError, RuntimeError};
+use crate::error::{CompileError, EvalError};
+use crate::interpreter::resolver::Resolver;
 use crate::parser::Parser;
 use crate::scanner;
 use crate::vm::compiler::Compiler;
 use crate::vm::vm::Vm;
 
 /// Interpret source code via the bytecode VM.
-/// Returns RuntimeError for execution errors.
-/// Compile errors are converted to RuntimeError for simplicity.
-pub fn interpret_vm(source: &str) -> Result<(), RuntimeError> {
-    let tokens = scanner::scan(source).map_err(|errors| {
-        RuntimeError::new(
-            errors
-                .into_iter()
-                .next()
-                .expect("at least one error")
-                .to_string(),
-        )
-    })?;
-    let program = Parser::new(tokens).parse().map_err(|errors| {
-        RuntimeError::new(
-            errors
-                .into_iter()
-                .next()
-                .expect("at least one error")
-                .to_string(),
-        )
-    })?;
-    let chunk = Compiler::new()
+///
+/// Compile errors (all of them, not just the first) and runtime errors come
+/// back as one [`EvalError`], the same type
+/// [`Interpreter::run_source`](crate::interpreter::Interpreter::run_source)
+/// returns, so callers can handle either backend the same way.
+pub fn interpret_vm(source: &str) -> Result<(), EvalError> {
+    let tokens = scanner::scan(source)?;
+    let program = Parser::new(tokens).parse()?;
+    // The compiler resolves its own locals; the resolver pass is run for its
+    // static checks, such as rejecting assignment to a `const`.
+    Resolver::new().resolve(&program)?;
+    let chunk = Compiler::with_source(source)
         .compile(&program)
-        .map_err(|e| RuntimeError::new(e.to_string()))?;
+        .map_err(|e| vec![e])?;
     let mut vm = Vm::new();
-    vm.interpret(chunk)
+    vm.set_source(source);
+    Ok(vm.interpret(chunk)?)
 }
 
 /// Compile source code to bytecode and return the chunk.
@@ -45,5 +38,8 @@ pub fn compile_to_chunk(source: &str) -> Result<chunk::Chunk, CompileError> {
     let program = Parser::new(tokens)
         .parse()
         .map_err(|errors| errors.into_iter().next().expect("at least one error"))?;
-    Compiler::new().compile(&program)
+    Resolver::new()
+        .resolve(&program)
+        .map_err(|errors| errors.into_iter().next().expect("at least one error"))?;
+    Compiler::with_source(source).compile(&program)
 }
@@ -0,0 +1,359 @@
+//! Peephole optimizer for compiled bytecode.
+//!
+//! Works on one chunk at a time (recursing into nested functions) and only
+//! makes local rewrites. Removing bytes shifts every later instruction, so
+//! the pass decodes the chunk, marks what to drop, and then re-encodes each
+//! surviving jump against the new offsets. An instruction that is the target
+//! of a jump is never removed in a way that changes what executes there.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::vm::chunk::{Chunk, Constant, OpCode};
+
+/// One decoded instruction: its opcode and byte range in the original code.
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    op: OpCode,
+    offset: usize,
+    len: usize,
+}
+
+impl Instruction {
+    fn end(&self) -> usize {
+        self.offset + self.len
+    }
+
+    fn is_forward_jump(&self) -> bool {
+        matches!(
+            self.op,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNil
+        )
+    }
+
+    /// Offset the jump or loop lands on, if this is one.
+    fn target(&self, chunk: &Chunk) -> Option<usize> {
+        if self.is_forward_jump() {
+            Some(self.end() + chunk.read_u16(self.offset + 1) as usize)
+        } else if self.op == OpCode::Loop {
+            Some(self.end() - chunk.read_u16(self.offset + 1) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl Chunk {
+    /// Run the peephole optimizer over this chunk and every function nested
+    /// in it. The result executes identically; it just does less work:
+    ///
+    /// - a jump landing on an unconditional `Jump` goes straight to that
+    ///   jump's destination;
+    /// - a push with no side effects followed by `Pop` (an expression
+    ///   statement like `x;`) is removed;
+    /// - unreachable code after `Return`, `Jump` or `Loop` is removed.
+    ///
+    /// A chunk containing bytes that don't decode is left untouched.
+    pub fn optimize(&mut self) {
+        for constant in &mut self.constants {
+            if let Constant::Function { chunk, .. } = constant {
+                chunk.optimize();
+            }
+        }
+        let Some(instructions) = decode(self) else {
+            return;
+        };
+        thread_jumps(self, &instructions);
+        let keep = kept_instructions(self, &instructions);
+        if keep.iter().all(|&kept| kept) {
+            return;
+        }
+        rewrite(self, &instructions, &keep);
+    }
+}
+
+fn decode(chunk: &Chunk) -> Option<Vec<Instruction>> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let op = OpCode::try_from(chunk.code[offset]).ok()?;
+        let len = chunk.instruction_len(offset)?;
+        if offset + len > chunk.code.len() {
+            return None;
+        }
+        instructions.push(Instruction { op, offset, len });
+        offset += len;
+    }
+    Some(instructions)
+}
+
+/// Retarget forward jumps that land on an unconditional `Jump` to wherever
+/// that jump goes. Only jump distances change, so no offsets move.
+fn thread_jumps(chunk: &mut Chunk, instructions: &[Instruction]) {
+    let jumps_at: HashMap<usize, Instruction> = instructions
+        .iter()
+        .filter(|instruction| instruction.op == OpCode::Jump)
+        .map(|instruction| (instruction.offset, *instruction))
+        .collect();
+    for instruction in instructions.iter().filter(|i| i.is_forward_jump()) {
+        let mut target = instruction.target(chunk).expect("forward jump");
+        // Forward jumps only move forward, so this always terminates.
+        while let Some(next) = jumps_at.get(&target) {
+            target = next.target(chunk).expect("forward jump");
+        }
+        if let Ok(distance) = u16::try_from(target - instruction.end()) {
+            write_u16_at(chunk, instruction.offset + 1, distance);
+        }
+    }
+}
+
+/// Decide which instructions survive: those reachable from the start of the
+/// chunk, minus pure push/`Pop` pairs that no jump lands in the middle of.
+fn kept_instructions(chunk: &Chunk, instructions: &[Instruction]) -> Vec<bool> {
+    let index_at: HashMap<usize, usize> = instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| (instruction.offset, i))
+        .collect();
+    let mut keep = vec![false; instructions.len()];
+    let mut targets = HashSet::new();
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        if i >= instructions.len() || keep[i] {
+            continue;
+        }
+        keep[i] = true;
+        let instruction = instructions[i];
+        if let Some(target) = instruction.target(chunk) {
+            targets.insert(target);
+            pending.extend(index_at.get(&target));
+        }
+        if !matches!(instruction.op, OpCode::Return | OpCode::Jump | OpCode::Loop) {
+            pending.push(i + 1);
+        }
+    }
+    for i in 1..instructions.len() {
+        let (push, pop) = (instructions[i - 1], instructions[i]);
+        if keep[i - 1]
+            && keep[i]
+            && is_pure_push(push.op)
+            && pop.op == OpCode::Pop
+            && !targets.contains(&pop.offset)
+        {
+            keep[i - 1] = false;
+            keep[i] = false;
+        }
+    }
+    keep
+}
+
+/// Opcodes that push one value and can neither fail nor have side effects.
+/// `GetGlobal` is excluded because reading an undefined global is an error.
+fn is_pure_push(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Zero
+            | OpCode::One
+            | OpCode::GetLocal
+            | OpCode::GetUpvalue
+            | OpCode::GetGlobalByIndex
+    )
+}
+
+/// Rebuild the code and line table without the dropped instructions,
+/// re-encoding every jump and loop against the new offsets.
+fn rewrite(chunk: &mut Chunk, instructions: &[Instruction], keep: &[bool]) {
+    // new_offsets[i] is where instruction i (or, if dropped, the next kept
+    // instruction) starts in the rewritten code; the extra entry is the end.
+    let mut new_offsets = Vec::with_capacity(instructions.len() + 1);
+    let mut offset = 0;
+    for (instruction, &kept) in instructions.iter().zip(keep) {
+        new_offsets.push(offset);
+        if kept {
+            offset += instruction.len;
+        }
+    }
+    new_offsets.push(offset);
+    let relocate = |old: usize| {
+        let index = instructions.partition_point(|instruction| instruction.offset < old);
+        new_offsets[index]
+    };
+
+    let mut code = Vec::with_capacity(offset);
+    let mut lines = Vec::with_capacity(offset);
+    for (i, instruction) in instructions.iter().enumerate() {
+        if !keep[i] {
+            continue;
+        }
+        let range = instruction.offset..instruction.end();
+        let start = code.len();
+        code.extend_from_slice(&chunk.code[range.clone()]);
+        lines.extend_from_slice(&chunk.lines[range]);
+        if let Some(target) = instruction.target(chunk) {
+            let new_end = new_offsets[i] + instruction.len;
+            let new_target = relocate(target);
+            let distance = if instruction.op == OpCode::Loop {
+                new_end - new_target
+            } else {
+                new_target - new_end
+            };
+            let distance = u16::try_from(distance).expect("removing code only shortens jumps");
+            code[start + 1] = (distance >> 8) as u8;
+            code[start + 2] = (distance & 0xff) as u8;
+        }
+    }
+    chunk.code = code;
+    chunk.lines = lines;
+}
+
+fn write_u16_at(chunk: &mut Chunk, offset: usize, value: u16) {
+    chunk.code[offset] = (value >> 8) as u8;
+    chunk.code[offset + 1] = (value & 0xff) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::compile_to_chunk;
+    use crate::vm::vm::Vm;
+    use rstest::rstest;
+
+    fn compile(source: &str) -> Chunk {
+        compile_to_chunk(source).expect("compile should succeed")
+    }
+
+    fn optimized(source: &str) -> Chunk {
+        let mut chunk = compile(source);
+        chunk.optimize();
+        chunk
+    }
+
+    fn ops(chunk: &Chunk) -> Vec<OpCode> {
+        decode(chunk)
+            .expect("chunk decodes")
+            .iter()
+            .map(|instruction| instruction.op)
+            .collect()
+    }
+
+    fn function_chunk(chunk: &Chunk) -> &Chunk {
+        chunk
+            .constants
+            .iter()
+            .find_map(|constant| match constant {
+                Constant::Function { chunk, .. } => Some(chunk),
+                _ => None,
+            })
+            .expect("function constant")
+    }
+
+    fn run(chunk: Chunk) -> Vec<String> {
+        let mut vm = Vm::new_capturing();
+        vm.interpret(chunk).expect("interpret should succeed");
+        vm.output().to_vec()
+    }
+
+    #[test]
+    fn pure_expression_statements_are_removed() {
+        let chunk = optimized("var x = 1; x; 2; nil; print x;");
+        assert_eq!(
+            ops(&chunk),
+            vec![
+                OpCode::One,
+                OpCode::DefineGlobal,
+                OpCode::GetGlobalByIndex,
+                OpCode::Print,
+                OpCode::Nil,
+                OpCode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn reading_an_undefined_global_is_kept() {
+        let chunk = optimized("missing; print 1;");
+        assert!(ops(&chunk).contains(&OpCode::GetGlobal));
+    }
+
+    #[test]
+    fn implicit_return_after_explicit_return_is_removed() {
+        let chunk = optimized("fun f() { return 1; }");
+        assert_eq!(
+            ops(function_chunk(&chunk)),
+            vec![OpCode::One, OpCode::Return]
+        );
+    }
+
+    #[test]
+    fn code_reached_by_a_jump_is_kept() {
+        // The else branch follows the then branch's `Jump` but is reachable
+        // through `JumpIfFalse`.
+        let chunk = optimized("fun f(a) { if (a) return 1; else return 2; }");
+        assert_eq!(
+            ops(function_chunk(&chunk))
+                .iter()
+                .filter(|&&op| op == OpCode::Return)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn jumps_to_jumps_are_threaded() {
+        let source = "fun f(a, b) { if (a) { if (b) print 1; else print 2; } else print 3; }";
+        let before = compile(source);
+        let after = optimized(source);
+        let body = function_chunk(&after);
+        let instructions = decode(body).expect("chunk decodes");
+        let jump_targets: Vec<OpCode> = instructions
+            .iter()
+            .filter_map(|instruction| instruction.target(body))
+            .filter_map(|target| instructions.iter().find(|i| i.offset == target))
+            .map(|instruction| instruction.op)
+            .collect();
+        assert!(!jump_targets.contains(&OpCode::Jump), "{jump_targets:?}");
+        assert_eq!(function_chunk(&before).code.len(), body.code.len());
+    }
+
+    #[test]
+    fn optimizing_corrupt_code_is_a_no_op() {
+        let mut chunk = Chunk::new();
+        chunk.write_byte(250, 1);
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::Pop, 1);
+        let before = chunk.clone();
+        chunk.optimize();
+        assert_eq!(chunk, before);
+    }
+
+    #[rstest]
+    #[case::expression_statements("var x = 1; x; 2; print x;")]
+    #[case::nested_if_else(
+        "fun f(a, b) { if (a) { if (b) print 1; else print 2; } else print 3; } \
+         f(true, true); f(true, false); f(false, true);"
+    )]
+    #[case::loops_around_removed_code(
+        "var n = 0; while (n < 3) { n; n = n + 1; print n; } \
+         for (var i = 0; i < 2; i = i + 1) { i; print i; }"
+    )]
+    #[case::logical_operators("var a = nil; a and 1; a or 2; print a ?? 3; print (1 and 2) or 3;")]
+    #[case::closures(
+        "fun counter() { var c = 0; fun inc() { c; c = c + 1; return c; return 0; } return inc; } \
+         var i = counter(); i(); print i();"
+    )]
+    #[case::classes(
+        "class A { init(n) { this.n = n; return; } get() { this; return this.n; } } \
+         class B < A { get() { return super.get() + 1; } } print B(1).get();"
+    )]
+    fn optimized_code_runs_identically(#[case] source: &str) {
+        let chunk = compile(source);
+        let mut optimized = chunk.clone();
+        optimized.optimize();
+        assert!(optimized.byte_len() <= chunk.byte_len());
+        assert_eq!(optimized.lines.len(), optimized.code.len());
+        assert_eq!(run(optimized), run(chunk));
+    }
+}
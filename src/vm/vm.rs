@@ -1,14 +1,18 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::rc::Rc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::error::{RuntimeError, StackFrame};
+use crate::error::{DEFAULT_MAX_CALL_DEPTH, RuntimeError, StackFrame, stack_overflow_message};
+use crate::stdlib::{format_lox_class, format_lox_function, format_lox_number};
 use crate::vm::chunk::{Chunk, Constant, OpCode};
 
+/// A runtime value in the VM. Object variants are opaque handles; use
+/// `Display` or [`VmValue::type_name`] to inspect them.
 #[derive(Debug, Clone)]
-enum VmValue {
+pub enum VmValue {
     Number(f64),
     Bool(bool),
     Nil,
@@ -24,26 +28,48 @@ impl VmValue {
     fn is_falsey(&self) -> bool {
         matches!(self, Self::Nil | Self::Bool(false))
     }
+
+    /// Name of the value's type, as returned by the `type()` native.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::Bool(_) => "bool",
+            Self::Nil => "nil",
+            Self::String(_) => "string",
+            Self::Closure(_) | Self::NativeFunction(_) | Self::BoundMethod(_) => "function",
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
+        }
+    }
+}
+
+/// Lox `==` semantics: values by content, objects by identity.
+impl PartialEq for VmValue {
+    fn eq(&self, other: &Self) -> bool {
+        values_equal(self, other)
+    }
 }
 
 impl std::fmt::Display for VmValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{n}")
-                }
-            }
+            Self::Number(n) => write!(f, "{}", format_lox_number(*n)),
             Self::Bool(b) => write!(f, "{b}"),
             Self::Nil => write!(f, "nil"),
             Self::String(s) => write!(f, "{s}"),
-            Self::Closure(c) => write!(f, "<fn {}>", c.function.name),
-            Self::NativeFunction(_) => write!(f, "<native fn>"),
-            Self::Class(c) => write!(f, "{}", c.borrow().name),
+            Self::Closure(c) => write!(
+                f,
+                "{}",
+                format_lox_function(&c.function.name, c.function.arity)
+            ),
+            Self::NativeFunction(n) => write!(f, "{}", format_lox_function(n.name(), n.arity())),
+            Self::Class(c) => write!(f, "{}", format_lox_class(&c.borrow().name)),
             Self::Instance(i) => write!(f, "{} instance", i.borrow().class.borrow().name),
-            Self::BoundMethod(bm) => write!(f, "<fn {}>", bm.method.function.name),
+            Self::BoundMethod(bm) => write!(
+                f,
+                "{}",
+                format_lox_function(&bm.method.function.name, bm.method.function.arity)
+            ),
         }
     }
 }
@@ -58,7 +84,7 @@ struct VmFunction {
 }
 
 #[derive(Debug)]
-struct VmClosure {
+pub struct VmClosure {
     function: Rc<VmFunction>,
     upvalues: Vec<Rc<RefCell<VmUpvalue>>>,
 }
@@ -79,26 +105,210 @@ enum VmUpvalue {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum NativeFn {
+pub enum NativeFn {
     Clock,
     ReadLine,
     ToNumber,
+    Eprint,
+    TimeMillis,
+    Strlen,
+    Substr,
+    Upper,
+    Lower,
+    IndexOf,
+    Chr,
+    Ord,
+    Type,
+    Env,
+    Write,
+    Writeln,
+}
+
+impl NativeFn {
+    /// Every native, registered as a global by [`Vm::new`]. The order fixes
+    /// the natives' global slots, which the compiler relies on and which
+    /// `.blox` files bake in: changing it means bumping
+    /// [`BLOX_VERSION`](crate::vm::chunk::BLOX_VERSION).
+    pub(crate) const ALL: [NativeFn; 16] = [
+        Self::Clock,
+        Self::ReadLine,
+        Self::ToNumber,
+        Self::Eprint,
+        Self::TimeMillis,
+        Self::Strlen,
+        Self::Substr,
+        Self::Upper,
+        Self::Lower,
+        Self::IndexOf,
+        Self::Chr,
+        Self::Ord,
+        Self::Type,
+        Self::Env,
+        Self::Write,
+        Self::Writeln,
+    ];
+
+    /// The global name the native is bound to.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Clock => "clock",
+            Self::ReadLine => "readLine",
+            Self::ToNumber => "toNumber",
+            Self::Eprint => "eprint",
+            Self::TimeMillis => "time_millis",
+            Self::Strlen => "strlen",
+            Self::Substr => "substr",
+            Self::Upper => "upper",
+            Self::Lower => "lower",
+            Self::IndexOf => "indexOf",
+            Self::Chr => "chr",
+            Self::Ord => "ord",
+            Self::Type => "type",
+            Self::Env => "env",
+            Self::Write => "write",
+            Self::Writeln => "writeln",
+        }
+    }
+
+    /// Number of arguments the native accepts; checked before every call.
+    fn arity(self) -> usize {
+        match self {
+            Self::Clock | Self::ReadLine | Self::TimeMillis => 0,
+            Self::ToNumber
+            | Self::Eprint
+            | Self::Strlen
+            | Self::Upper
+            | Self::Lower
+            | Self::Chr
+            | Self::Ord
+            | Self::Type
+            | Self::Env
+            | Self::Write
+            | Self::Writeln => 1,
+            Self::IndexOf => 2,
+            Self::Substr => 3,
+        }
+    }
+
+    /// Whether the native observes or affects anything outside the program,
+    /// and so is unavailable to a sandboxed VM.
+    fn has_side_effects(self) -> bool {
+        matches!(
+            self,
+            Self::Clock | Self::ReadLine | Self::Eprint | Self::TimeMillis | Self::Env
+        )
+    }
+
+    /// Invoke the native on exactly [`NativeFn::arity`] arguments. Errors
+    /// carry no location; the VM adds the line of the call.
+    fn call(self, args: &[VmValue]) -> Result<VmValue, RuntimeError> {
+        Ok(match (self, args) {
+            (Self::Clock, _) => {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("system clock should be after unix epoch")
+                    .as_secs_f64();
+                VmValue::Number(secs)
+            }
+            (Self::ReadLine, _) => {
+                match crate::stdlib::read_line_from(&mut std::io::stdin().lock()) {
+                    None => VmValue::Nil,
+                    Some(s) => VmValue::String(Rc::new(s)),
+                }
+            }
+            (Self::ToNumber, [VmValue::Number(n)]) => VmValue::Number(*n),
+            (Self::ToNumber, [VmValue::String(s)]) => {
+                crate::stdlib::parse_lox_number(s).map_or(VmValue::Nil, VmValue::Number)
+            }
+            (Self::Eprint, [arg]) => {
+                // Bypasses the VM's print capture and goes straight to stderr
+                eprintln!("{arg}");
+                VmValue::Nil
+            }
+            (Self::TimeMillis, _) => VmValue::Number(crate::stdlib::time_millis()),
+            (Self::Strlen, [VmValue::String(s)]) => VmValue::Number(s.chars().count() as f64),
+            (
+                Self::Substr,
+                [
+                    VmValue::String(s),
+                    VmValue::Number(start),
+                    VmValue::Number(end),
+                ],
+            ) => VmValue::String(Rc::new(
+                crate::stdlib::substr_chars(s, *start, *end).map_err(RuntimeError::new)?,
+            )),
+            (Self::Upper, [VmValue::String(s)]) => VmValue::String(Rc::new(s.to_uppercase())),
+            (Self::Lower, [VmValue::String(s)]) => VmValue::String(Rc::new(s.to_lowercase())),
+            (Self::IndexOf, [VmValue::String(s), VmValue::String(needle)]) => {
+                VmValue::Number(crate::stdlib::index_of_chars(s, needle))
+            }
+            (Self::Chr, [VmValue::Number(n)]) => {
+                VmValue::String(Rc::new(crate::stdlib::chr(*n).map_err(RuntimeError::new)?))
+            }
+            (Self::Chr, [other]) => {
+                return Err(RuntimeError::new(format!(
+                    "chr() argument must be a number, got {other}"
+                )));
+            }
+            (Self::Ord, [VmValue::String(s)]) => {
+                VmValue::Number(crate::stdlib::ord(s).map_err(RuntimeError::new)?)
+            }
+            (Self::Ord, [other]) => {
+                return Err(RuntimeError::new(format!(
+                    "ord() argument must be a string, got {other}"
+                )));
+            }
+            (Self::Type, [arg]) => VmValue::String(Rc::new(arg.type_name().to_string())),
+            (Self::Env, [VmValue::String(name)]) => crate::stdlib::env_var(name)
+                .map_or(VmValue::Nil, |value| VmValue::String(Rc::new(value))),
+            (Self::Env, [other]) => {
+                return Err(RuntimeError::new(format!(
+                    "env() argument must be a string, got {other}"
+                )));
+            }
+            // The VM handles these itself, since they need its writer.
+            (Self::Write | Self::Writeln, _) => {
+                return Err(RuntimeError::new(format!(
+                    "{}() can only be called from a running program",
+                    self.name()
+                )));
+            }
+            // toNumber and the string natives return nil for other argument types
+            _ => VmValue::Nil,
+        })
+    }
 }
 
 #[derive(Debug)]
-struct VmClass {
+pub struct VmClass {
     name: String,
     methods: HashMap<String, Rc<VmClosure>>,
+    /// Methods are copied down at `Inherit`; the link is kept for `is`.
+    superclass: Option<Rc<RefCell<VmClass>>>,
+}
+
+impl VmClass {
+    /// Whether this class is `other` or inherits from it.
+    fn is_subclass_of(this: &Rc<RefCell<VmClass>>, other: &Rc<RefCell<VmClass>>) -> bool {
+        let mut class = Some(Rc::clone(this));
+        while let Some(c) = class {
+            if Rc::ptr_eq(&c, other) {
+                return true;
+            }
+            class = c.borrow().superclass.clone();
+        }
+        false
+    }
 }
 
 #[derive(Debug)]
-struct VmInstance {
+pub struct VmInstance {
     class: Rc<RefCell<VmClass>>,
     fields: HashMap<String, VmValue>,
 }
 
 #[derive(Debug)]
-struct VmBoundMethod {
+pub struct VmBoundMethod {
     receiver: VmValue,
     method: Rc<VmClosure>,
 }
@@ -112,39 +322,75 @@ struct CallFrame {
 pub struct Vm {
     stack: Vec<VmValue>,
     frames: Vec<CallFrame>,
-    globals: HashMap<String, VmValue>,
+    /// Global values by slot. Slots are handed out in definition order,
+    /// natives first, matching the slots the compiler assigns for
+    /// `GetGlobalByIndex`.
+    globals: Vec<VmValue>,
+    global_slots: HashMap<String, usize>,
+    /// Slots of globals defined with `const`.
+    const_globals: HashSet<usize>,
     open_upvalues: Vec<Rc<RefCell<VmUpvalue>>>,
     output: Vec<String>,
+    /// Whether the last line in `output` was started by `write()` and is
+    /// still waiting for its newline.
+    line_open: bool,
     writer: Box<dyn Write>,
+    /// Maximum number of nested calls (excluding the script frame).
+    max_call_depth: usize,
+    /// Whether `env()` may read the process environment.
+    allow_env: bool,
+    /// Whether natives with side effects are disabled; see [`Vm::sandboxed`].
+    sandboxed: bool,
+    /// Source code the chunk was compiled from, if known, so runtime errors
+    /// can quote the offending line.
+    source: String,
+    /// Offset of the instruction being executed, for reporting malformed
+    /// bytecode.
+    instruction_start: usize,
 }
 
 impl Vm {
     pub fn new() -> Self {
-        let mut globals = HashMap::new();
-        globals.insert(
-            "clock".to_string(),
-            VmValue::NativeFunction(NativeFn::Clock),
-        );
-        globals.insert(
-            "readLine".to_string(),
-            VmValue::NativeFunction(NativeFn::ReadLine),
-        );
-        globals.insert(
-            "toNumber".to_string(),
-            VmValue::NativeFunction(NativeFn::ToNumber),
-        );
+        Self::with_sandbox(false)
+    }
+
+    /// Create a VM for untrusted code. Natives that touch the outside world
+    /// (the clock, stdin, stderr, the environment) stay defined but raise a
+    /// runtime error when called, so scripts are deterministic.
+    pub fn sandboxed() -> Self {
+        Self::with_sandbox(true)
+    }
+
+    fn with_sandbox(sandboxed: bool) -> Self {
+        let globals = NativeFn::ALL
+            .into_iter()
+            .map(VmValue::NativeFunction)
+            .collect();
+        let global_slots = NativeFn::ALL
+            .into_iter()
+            .enumerate()
+            .map(|(slot, native)| (native.name().to_string(), slot))
+            .collect();
         Self {
             stack: Vec::with_capacity(256),
             frames: Vec::with_capacity(64),
             globals,
+            global_slots,
+            const_globals: HashSet::new(),
             open_upvalues: Vec::new(),
             output: Vec::new(),
+            line_open: false,
             writer: Box::new(std::io::stdout()),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            allow_env: true,
+            sandboxed,
+            source: String::new(),
+            instruction_start: 0,
         }
     }
 
     #[cfg(test)]
-    fn new_capturing() -> Self {
+    pub(crate) fn new_capturing() -> Self {
         let mut vm = Self::new();
         vm.writer = Box::new(Vec::<u8>::new());
         vm
@@ -154,7 +400,48 @@ impl Vm {
         &self.output
     }
 
+    /// Write `text` to the output, ending the line if `newline` is set, and
+    /// flush so it interleaves correctly with stderr and prompts even when
+    /// stdout is piped. Text written without a newline is joined onto the
+    /// same captured `output` line as whatever follows it.
+    fn write_output(&mut self, text: String, newline: bool) {
+        if newline {
+            writeln!(self.writer, "{text}").expect("write should succeed");
+        } else {
+            write!(self.writer, "{text}").expect("write should succeed");
+        }
+        self.writer.flush().expect("flush should succeed");
+        match self.output.last_mut() {
+            Some(line) if self.line_open => line.push_str(&text),
+            _ => self.output.push(text),
+        }
+        self.line_open = !newline;
+    }
+
+    /// Set the source code the chunk was compiled from, so runtime errors
+    /// quote the line they occurred on.
+    pub fn set_source(&mut self, source: &str) {
+        self.source = source.to_string();
+    }
+
+    /// Set the maximum call depth (default [`DEFAULT_MAX_CALL_DEPTH`]).
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Allow or forbid `env()` (allowed by default). When forbidden, calling
+    /// it raises a runtime error.
+    pub fn set_allow_env(&mut self, allow: bool) {
+        self.allow_env = allow;
+    }
+
     pub fn interpret(&mut self, chunk: Chunk) -> Result<(), RuntimeError> {
+        self.interpret_returning(chunk).map(|_| ())
+    }
+
+    /// Run a script chunk and return its final value: the value of a trailing
+    /// top-level expression statement, or `nil` if the script ends otherwise.
+    pub fn interpret_returning(&mut self, chunk: Chunk) -> Result<VmValue, RuntimeError> {
         let function = Rc::new(VmFunction {
             name: "script".to_string(),
             arity: 0,
@@ -205,90 +492,130 @@ impl Vm {
         // The current frame's line gives us the error location
         let current_line = frames.first().map(|f| f.line).unwrap_or(0);
         let msg = message.into();
-        let display_msg = if current_line > 0 {
-            format!("line {current_line}: {msg}")
-        } else {
-            msg
+        let source_line = current_line
+            .checked_sub(1)
+            .and_then(|index| self.source.lines().nth(index));
+        let display_msg = match source_line {
+            Some(text) => format!("line {current_line}: {msg}\n{current_line:>5} | {text}"),
+            None if current_line > 0 => format!("line {current_line}: {msg}"),
+            None => msg,
         };
 
         RuntimeError::new(display_msg).with_backtrace(frames)
     }
 
-    fn run(&mut self) -> Result<(), RuntimeError> {
+    fn run(&mut self) -> Result<VmValue, RuntimeError> {
         loop {
             let frame_idx = self.frames.len() - 1;
             let ip = self.frames[frame_idx].ip;
             let chunk = &self.frames[frame_idx].closure.function.chunk;
 
             if ip >= chunk.code.len() {
-                return Ok(());
+                return Ok(VmValue::Nil);
             }
 
             let op = chunk.code[ip];
             self.frames[frame_idx].ip += 1;
+            self.instruction_start = ip;
 
             match OpCode::try_from(op) {
                 Ok(OpCode::Constant) => {
-                    let idx = self.read_byte();
-                    let constant = self.current_chunk().constants[idx as usize].clone();
+                    let idx = self.read_byte()?;
+                    let constant = self.constant_at(idx)?.clone();
                     self.stack.push(constant_to_value(constant));
                 }
                 Ok(OpCode::Nil) => self.stack.push(VmValue::Nil),
                 Ok(OpCode::True) => self.stack.push(VmValue::Bool(true)),
                 Ok(OpCode::False) => self.stack.push(VmValue::Bool(false)),
+                Ok(OpCode::Zero) => self.stack.push(VmValue::Number(0.0)),
+                Ok(OpCode::One) => self.stack.push(VmValue::Number(1.0)),
                 Ok(OpCode::Pop) => {
-                    self.stack.pop();
+                    self.pop()?;
+                }
+                Ok(OpCode::PopN) => {
+                    let count = self.read_byte()? as usize;
+                    let len = self.stack.len().checked_sub(count);
+                    let len = len.ok_or_else(|| self.stack_underflow())?;
+                    self.stack.truncate(len);
                 }
                 Ok(OpCode::GetLocal) => {
-                    let slot = self.read_byte() as usize;
-                    let offset = self.frames.last().expect("frame").slot_offset;
-                    let value = self.stack[offset + slot].clone();
+                    let slot = self.read_byte()?;
+                    let index = self.local_index(slot)?;
+                    let value = self.stack[index].clone();
                     self.stack.push(value);
                 }
                 Ok(OpCode::SetLocal) => {
-                    let slot = self.read_byte() as usize;
-                    let offset = self.frames.last().expect("frame").slot_offset;
-                    let value = self.stack.last().expect("stack not empty").clone();
-                    self.stack[offset + slot] = value;
+                    let slot = self.read_byte()?;
+                    let index = self.local_index(slot)?;
+                    let value = self.peek(0)?.clone();
+                    self.stack[index] = value;
                 }
                 Ok(OpCode::GetGlobal) => {
-                    let name = self.read_string_constant();
-                    let value = self.globals.get(&name).cloned().ok_or_else(|| {
-                        self.runtime_error(format!("undefined variable '{name}'"))
+                    let name = self.read_string_constant()?;
+                    let value = self
+                        .global_slots
+                        .get(&name)
+                        .and_then(|&slot| self.globals.get(slot))
+                        .cloned()
+                        .ok_or_else(|| {
+                            self.runtime_error(format!("undefined variable '{name}'"))
+                        })?;
+                    self.stack.push(value);
+                }
+                Ok(OpCode::GetGlobalByIndex) => {
+                    let slot = self.read_byte()? as usize;
+                    let value = self.globals.get(slot).cloned().ok_or_else(|| {
+                        self.runtime_error(format!("undefined global slot {slot}"))
                     })?;
                     self.stack.push(value);
                 }
                 Ok(OpCode::SetGlobal) => {
-                    let name = self.read_string_constant();
-                    if !self.globals.contains_key(&name) {
+                    let name = self.read_string_constant()?;
+                    let Some(&slot) = self.global_slots.get(&name) else {
                         return Err(self.runtime_error(format!("undefined variable '{name}'")));
+                    };
+                    if self.const_globals.contains(&slot) {
+                        return Err(
+                            self.runtime_error(format!("can't assign to constant '{name}'"))
+                        );
                     }
-                    let value = self.stack.last().expect("stack not empty").clone();
-                    self.globals.insert(name, value);
+                    let value = self.peek(0)?.clone();
+                    let Some(global) = self.globals.get_mut(slot) else {
+                        return Err(self.runtime_error(format!("undefined global slot {slot}")));
+                    };
+                    *global = value;
                 }
                 Ok(OpCode::DefineGlobal) => {
-                    let name = self.read_string_constant();
-                    let value = self.stack.pop().expect("stack not empty");
-                    self.globals.insert(name, value);
+                    let slot = self.define_global()?;
+                    self.const_globals.remove(&slot);
+                }
+                Ok(OpCode::DefineGlobalConst) => {
+                    let slot = self.define_global()?;
+                    self.const_globals.insert(slot);
                 }
                 Ok(OpCode::GetUpvalue) => {
-                    let slot = self.read_byte() as usize;
-                    let upvalue =
-                        Rc::clone(&self.frames.last().expect("frame").closure.upvalues[slot]);
+                    let slot = self.read_byte()?;
+                    let upvalue = self.upvalue_at(slot)?;
                     let value = match &*upvalue.borrow() {
-                        VmUpvalue::Open(idx) => self.stack[*idx].clone(),
+                        VmUpvalue::Open(idx) => self
+                            .stack
+                            .get(*idx)
+                            .cloned()
+                            .ok_or_else(|| self.stack_underflow())?,
                         VmUpvalue::Closed(v) => v.clone(),
                     };
                     self.stack.push(value);
                 }
                 Ok(OpCode::SetUpvalue) => {
-                    let slot = self.read_byte() as usize;
-                    let value = self.stack.last().expect("stack not empty").clone();
-                    let upvalue =
-                        Rc::clone(&self.frames.last().expect("frame").closure.upvalues[slot]);
+                    let slot = self.read_byte()?;
+                    let value = self.peek(0)?.clone();
+                    let upvalue = self.upvalue_at(slot)?;
                     match &mut *upvalue.borrow_mut() {
                         VmUpvalue::Open(idx) => {
-                            self.stack[*idx] = value;
+                            let Some(local) = self.stack.get_mut(*idx) else {
+                                return Err(self.stack_underflow());
+                            };
+                            *local = value;
                         }
                         VmUpvalue::Closed(v) => {
                             *v = value;
@@ -296,8 +623,8 @@ impl Vm {
                     }
                 }
                 Ok(OpCode::GetProperty) => {
-                    let name = self.read_string_constant();
-                    let instance = self.stack.pop().expect("stack");
+                    let name = self.read_string_constant()?;
+                    let instance = self.pop()?;
                     match instance {
                         VmValue::Instance(inst) => {
                             if let Some(val) = inst.borrow().fields.get(&name).cloned() {
@@ -322,9 +649,9 @@ impl Vm {
                     }
                 }
                 Ok(OpCode::SetProperty) => {
-                    let name = self.read_string_constant();
-                    let value = self.stack.pop().expect("stack");
-                    let instance = self.stack.pop().expect("stack");
+                    let name = self.read_string_constant()?;
+                    let value = self.pop()?;
+                    let instance = self.pop()?;
                     match instance {
                         VmValue::Instance(inst) => {
                             inst.borrow_mut().fields.insert(name, value.clone());
@@ -336,9 +663,9 @@ impl Vm {
                     }
                 }
                 Ok(OpCode::GetSuper) => {
-                    let name = self.read_string_constant();
-                    let superclass = self.stack.pop().expect("stack");
-                    let receiver = self.stack.pop().expect("stack");
+                    let name = self.read_string_constant()?;
+                    let superclass = self.pop()?;
+                    let receiver = self.pop()?;
                     if let VmValue::Class(sc) = superclass {
                         if let Some(method) = sc.borrow().methods.get(&name).cloned() {
                             let bound =
@@ -350,19 +677,30 @@ impl Vm {
                     }
                 }
                 Ok(OpCode::Equal) => {
-                    let b = self.stack.pop().expect("stack");
-                    let a = self.stack.pop().expect("stack");
+                    let b = self.pop()?;
+                    let a = self.pop()?;
                     self.stack.push(VmValue::Bool(values_equal(&a, &b)));
                 }
+                Ok(OpCode::NotEqual) => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.stack.push(VmValue::Bool(!values_equal(&a, &b)));
+                }
                 Ok(OpCode::Greater) => {
-                    self.binary_op(|a, b| VmValue::Bool(a > b))?;
+                    self.compare_op(Ordering::is_gt)?;
+                }
+                Ok(OpCode::GreaterEqual) => {
+                    self.compare_op(Ordering::is_ge)?;
                 }
                 Ok(OpCode::Less) => {
-                    self.binary_op(|a, b| VmValue::Bool(a < b))?;
+                    self.compare_op(Ordering::is_lt)?;
+                }
+                Ok(OpCode::LessEqual) => {
+                    self.compare_op(Ordering::is_le)?;
                 }
                 Ok(OpCode::Add) => {
-                    let b = self.stack.pop().expect("stack");
-                    let a = self.stack.pop().expect("stack");
+                    let b = self.pop()?;
+                    let a = self.pop()?;
                     match (&a, &b) {
                         (VmValue::Number(x), VmValue::Number(y)) => {
                             self.stack.push(VmValue::Number(x + y));
@@ -384,14 +722,40 @@ impl Vm {
                     self.binary_op(|a, b| VmValue::Number(a * b))?;
                 }
                 Ok(OpCode::Divide) => {
+                    self.check_divisor()?;
                     self.binary_op(|a, b| VmValue::Number(a / b))?;
                 }
+                Ok(OpCode::Modulo) => {
+                    self.check_divisor()?;
+                    self.binary_op(|a, b| VmValue::Number(a % b))?;
+                }
+                Ok(OpCode::IsInstance) => {
+                    let class = self.pop()?;
+                    let value = self.pop()?;
+                    let result = match (&value, &class) {
+                        (VmValue::Instance(inst), VmValue::Class(class)) => {
+                            VmClass::is_subclass_of(&inst.borrow().class, class)
+                        }
+                        (_, VmValue::Class(_)) => false,
+                        _ => {
+                            return Err(self.runtime_error("right operand of 'is' must be a class"));
+                        }
+                    };
+                    self.stack.push(VmValue::Bool(result));
+                }
+                Ok(OpCode::Dup) => {
+                    let top = self.peek(0)?.clone();
+                    self.stack.push(top);
+                }
+                Ok(OpCode::Power) => {
+                    self.binary_op(|a, b| VmValue::Number(a.powf(b)))?;
+                }
                 Ok(OpCode::Not) => {
-                    let val = self.stack.pop().expect("stack");
+                    let val = self.pop()?;
                     self.stack.push(VmValue::Bool(val.is_falsey()));
                 }
                 Ok(OpCode::Negate) => {
-                    let val = self.stack.pop().expect("stack");
+                    let val = self.pop()?;
                     match val {
                         VmValue::Number(n) => self.stack.push(VmValue::Number(-n)),
                         _ => {
@@ -400,36 +764,70 @@ impl Vm {
                     }
                 }
                 Ok(OpCode::Print) => {
-                    let val = self.stack.pop().expect("stack");
+                    let val = self.pop()?;
                     let text = format!("{val}");
-                    writeln!(self.writer, "{text}").expect("write should succeed");
-                    self.output.push(text);
+                    self.write_output(text, true);
+                }
+                Ok(OpCode::PrintN) => {
+                    let count = self.read_byte()? as usize;
+                    let start = self.stack.len().checked_sub(count);
+                    let start = start.ok_or_else(|| self.stack_underflow())?;
+                    let values = self.stack.split_off(start);
+                    let text = values
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    self.write_output(text, true);
                 }
                 Ok(OpCode::Jump) => {
-                    let offset = self.read_u16();
+                    let offset = self.read_u16()?;
                     self.frames.last_mut().expect("frame").ip += offset as usize;
                 }
                 Ok(OpCode::JumpIfFalse) => {
-                    let offset = self.read_u16();
-                    if self.stack.last().expect("stack").is_falsey() {
+                    let offset = self.read_u16()?;
+                    if self.peek(0)?.is_falsey() {
+                        self.frames.last_mut().expect("frame").ip += offset as usize;
+                    }
+                }
+                Ok(OpCode::JumpIfNil) => {
+                    let offset = self.read_u16()?;
+                    if matches!(self.peek(0)?, VmValue::Nil) {
                         self.frames.last_mut().expect("frame").ip += offset as usize;
                     }
                 }
                 Ok(OpCode::Loop) => {
-                    let offset = self.read_u16();
-                    self.frames.last_mut().expect("frame").ip -= offset as usize;
+                    let offset = self.read_u16()?;
+                    let ip = self.frames.last().expect("frame").ip;
+                    let Some(target) = ip.checked_sub(offset as usize) else {
+                        return Err(self.runtime_error(format!(
+                            "bytecode loop before the start of the chunk at ip {}",
+                            self.instruction_start
+                        )));
+                    };
+                    self.frames.last_mut().expect("frame").ip = target;
                 }
                 Ok(OpCode::Call) => {
-                    let arg_count = self.read_byte() as usize;
-                    let callee_idx = self.stack.len() - 1 - arg_count;
-                    let callee = self.stack[callee_idx].clone();
+                    let arg_count = self.read_byte()? as usize;
+                    let callee = self.peek(arg_count)?.clone();
                     self.call_value(callee, arg_count)?;
                 }
+                Ok(OpCode::TailCall) => {
+                    let arg_count = self.read_byte()? as usize;
+                    let callee = self.peek(arg_count)?.clone();
+                    // Only plain closures reuse the frame; classes, bound
+                    // methods and natives fall back to a regular call and the
+                    // compiler's trailing Return handles the result.
+                    match callee {
+                        VmValue::Closure(closure) => self.tail_call(closure, arg_count)?,
+                        other => self.call_value(other, arg_count)?,
+                    }
+                }
                 Ok(OpCode::Invoke) => {
-                    let name = self.read_string_constant();
-                    let arg_count = self.read_byte() as usize;
-                    let receiver_idx = self.stack.len() - 1 - arg_count;
-                    let receiver = self.stack[receiver_idx].clone();
+                    let name = self.read_string_constant()?;
+                    let arg_count = self.read_byte()? as usize;
+                    let receiver = self.peek(arg_count)?.clone();
+                    let receiver_idx = self.callee_slot(arg_count)?;
                     if let VmValue::Instance(inst) = &receiver {
                         if let Some(field) = inst.borrow().fields.get(&name).cloned() {
                             self.stack[receiver_idx] = field.clone();
@@ -439,20 +837,20 @@ impl Vm {
                             self.invoke_from_class(&class, &name, arg_count)?;
                         }
                     } else {
-                        return Err(self.runtime_error("only instances have methods"));
+                        return Err(self.runtime_error("only instances have properties"));
                     }
                 }
                 Ok(OpCode::SuperInvoke) => {
-                    let name = self.read_string_constant();
-                    let arg_count = self.read_byte() as usize;
-                    let superclass = self.stack.pop().expect("stack");
-                    if let VmValue::Class(sc) = superclass {
-                        self.invoke_from_class(&sc, &name, arg_count)?;
-                    }
+                    let name = self.read_string_constant()?;
+                    let arg_count = self.read_byte()? as usize;
+                    let VmValue::Class(superclass) = self.pop()? else {
+                        return Err(self.runtime_error("superclass must be a class"));
+                    };
+                    self.invoke_from_class(&superclass, &name, arg_count)?;
                 }
                 Ok(OpCode::Closure) => {
-                    let idx = self.read_byte();
-                    let constant = self.current_chunk().constants[idx as usize].clone();
+                    let idx = self.read_byte()?;
+                    let constant = self.constant_at(idx)?.clone();
                     if let Constant::Function {
                         name,
                         arity,
@@ -468,18 +866,14 @@ impl Vm {
                         });
                         let mut upvalues = Vec::with_capacity(upvalue_count);
                         for _ in 0..upvalue_count {
-                            let is_local = self.read_byte();
-                            let index = self.read_byte() as usize;
+                            let is_local = self.read_byte()?;
+                            let index = self.read_byte()?;
                             if is_local == 1 {
-                                let abs_idx =
-                                    self.frames.last().expect("frame").slot_offset + index;
+                                let abs_idx = self.local_index(index)?;
                                 let upvalue = self.capture_upvalue(abs_idx);
                                 upvalues.push(upvalue);
                             } else {
-                                let upvalue = Rc::clone(
-                                    &self.frames.last().expect("frame").closure.upvalues[index],
-                                );
-                                upvalues.push(upvalue);
+                                upvalues.push(self.upvalue_at(index)?);
                             }
                         }
                         let closure = Rc::new(VmClosure { function, upvalues });
@@ -487,43 +881,45 @@ impl Vm {
                     }
                 }
                 Ok(OpCode::CloseUpvalue) => {
-                    let idx = self.stack.len() - 1;
-                    self.close_upvalues(idx);
+                    self.peek(0)?;
+                    self.close_upvalues(self.stack.len() - 1);
                     self.stack.pop();
                 }
                 Ok(OpCode::Return) => {
-                    let result = self.stack.pop().expect("stack");
+                    let result = self.pop()?;
                     let frame = self.frames.pop().expect("frame");
                     if self.frames.is_empty() {
                         self.stack.pop(); // pop script closure
-                        return Ok(());
+                        return Ok(result);
                     }
                     self.close_upvalues(frame.slot_offset);
                     self.stack.truncate(frame.slot_offset);
                     self.stack.push(result);
                 }
                 Ok(OpCode::Class) => {
-                    let name = self.read_string_constant();
+                    let name = self.read_string_constant()?;
                     let class = Rc::new(RefCell::new(VmClass {
                         name,
                         methods: HashMap::new(),
+                        superclass: None,
                     }));
                     self.stack.push(VmValue::Class(class));
                 }
                 Ok(OpCode::Inherit) => {
-                    let superclass = self.stack[self.stack.len() - 2].clone();
-                    let subclass = self.stack.last().expect("stack").clone();
+                    let superclass = self.peek(1)?.clone();
+                    let subclass = self.peek(0)?.clone();
                     if let (VmValue::Class(sc), VmValue::Class(sub)) = (&superclass, &subclass) {
                         let methods = sc.borrow().methods.clone();
                         sub.borrow_mut().methods.extend(methods);
+                        sub.borrow_mut().superclass = Some(Rc::clone(sc));
                         self.stack.pop(); // pop subclass, leave super as local
                     } else {
                         return Err(self.runtime_error("superclass must be a class"));
                     }
                 }
                 Ok(OpCode::Method) => {
-                    let name = self.read_string_constant();
-                    let method = self.stack.pop().expect("stack");
+                    let name = self.read_string_constant()?;
+                    let method = self.pop()?;
                     if let (VmValue::Closure(closure), Some(VmValue::Class(class))) =
                         (method, self.stack.last())
                     {
@@ -537,26 +933,125 @@ impl Vm {
         }
     }
 
-    fn read_byte(&mut self) -> u8 {
+    /// Pop the top of the stack. Only malformed bytecode can pop more than
+    /// it pushed, so that is reported as an error rather than a panic.
+    fn pop(&mut self) -> Result<VmValue, RuntimeError> {
+        self.stack.pop().ok_or_else(|| self.stack_underflow())
+    }
+
+    /// The value `distance` slots below the top of the stack.
+    fn peek(&self, distance: usize) -> Result<&VmValue, RuntimeError> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .map(|index| &self.stack[index])
+            .ok_or_else(|| self.stack_underflow())
+    }
+
+    /// Stack index of the callee (or receiver) below `arg_count` arguments.
+    fn callee_slot(&self, arg_count: usize) -> Result<usize, RuntimeError> {
+        self.peek(arg_count)?;
+        Ok(self.stack.len() - arg_count - 1)
+    }
+
+    /// Stack index of local `slot` in the current frame.
+    fn local_index(&self, slot: u8) -> Result<usize, RuntimeError> {
+        let index = self.frames.last().expect("frame").slot_offset + usize::from(slot);
+        if index < self.stack.len() {
+            Ok(index)
+        } else {
+            Err(self.runtime_error(format!(
+                "bytecode local slot {slot} out of range at ip {}",
+                self.instruction_start
+            )))
+        }
+    }
+
+    /// Upvalue `slot` of the current frame's closure.
+    fn upvalue_at(&self, slot: u8) -> Result<Rc<RefCell<VmUpvalue>>, RuntimeError> {
+        let closure = &self.frames.last().expect("frame").closure;
+        closure
+            .upvalues
+            .get(usize::from(slot))
+            .map(Rc::clone)
+            .ok_or_else(|| {
+                self.runtime_error(format!(
+                    "bytecode upvalue {slot} out of range at ip {}",
+                    self.instruction_start
+                ))
+            })
+    }
+
+    fn stack_underflow(&self) -> RuntimeError {
+        self.runtime_error(format!(
+            "bytecode stack underflow at ip {}",
+            self.instruction_start
+        ))
+    }
+
+    /// Read the next operand byte. Only malformed bytecode ends in the
+    /// middle of an instruction, so that is an error rather than a panic.
+    fn read_byte(&mut self) -> Result<u8, RuntimeError> {
         let frame = self.frames.last_mut().expect("frame");
-        let byte = frame.closure.function.chunk.code[frame.ip];
+        let byte = frame.closure.function.chunk.code.get(frame.ip).copied();
         frame.ip += 1;
-        byte
+        byte.ok_or_else(|| self.truncated_instruction())
     }
 
-    fn read_u16(&mut self) -> u16 {
-        let frame = self.frames.last_mut().expect("frame");
-        let value = frame.closure.function.chunk.read_u16(frame.ip);
-        frame.ip += 2;
-        value
+    fn read_u16(&mut self) -> Result<u16, RuntimeError> {
+        let hi = self.read_byte()?;
+        let lo = self.read_byte()?;
+        Ok(u16::from(hi) << 8 | u16::from(lo))
     }
 
-    fn read_string_constant(&mut self) -> String {
-        let idx = self.read_byte();
-        let constant = &self.current_chunk().constants[idx as usize];
-        match constant {
-            Constant::String(s) => s.clone(),
-            _ => panic!("expected string constant"),
+    fn truncated_instruction(&self) -> RuntimeError {
+        self.runtime_error(format!(
+            "truncated bytecode instruction at ip {}",
+            self.instruction_start
+        ))
+    }
+
+    /// The constant at `idx` in the current chunk's pool.
+    fn constant_at(&self, idx: u8) -> Result<&Constant, RuntimeError> {
+        self.current_chunk()
+            .constants
+            .get(usize::from(idx))
+            .ok_or_else(|| {
+                self.runtime_error(format!(
+                    "bytecode constant {idx} out of range at ip {}",
+                    self.instruction_start
+                ))
+            })
+    }
+
+    /// Pop a value into the global named by the next operand, giving a new
+    /// name the next free slot. Returns the slot.
+    fn define_global(&mut self) -> Result<usize, RuntimeError> {
+        let name = self.read_string_constant()?;
+        let value = self.pop()?;
+        let slot = match self.global_slots.get(&name) {
+            Some(&slot) => {
+                self.globals[slot] = value;
+                slot
+            }
+            None => {
+                let slot = self.globals.len();
+                self.global_slots.insert(name, slot);
+                self.globals.push(value);
+                slot
+            }
+        };
+        Ok(slot)
+    }
+
+    fn read_string_constant(&mut self) -> Result<String, RuntimeError> {
+        let idx = self.read_byte()?;
+        match self.constant_at(idx)? {
+            Constant::String(s) => Ok(s.clone()),
+            _ => Err(self.runtime_error(format!(
+                "bytecode constant {idx} is not a string at ip {}",
+                self.instruction_start
+            ))),
         }
     }
 
@@ -565,8 +1060,8 @@ impl Vm {
     }
 
     fn binary_op(&mut self, op: fn(f64, f64) -> VmValue) -> Result<(), RuntimeError> {
-        let b = self.stack.pop().expect("stack");
-        let a = self.stack.pop().expect("stack");
+        let b = self.pop()?;
+        let a = self.pop()?;
         match (&a, &b) {
             (VmValue::Number(x), VmValue::Number(y)) => {
                 self.stack.push(op(*x, *y));
@@ -576,6 +1071,40 @@ impl Vm {
         }
     }
 
+    /// Order two numbers, or two strings lexicographically. Any comparison
+    /// involving NaN is false, as with IEEE 754 comparisons.
+    fn compare_op(&mut self, op: fn(Ordering) -> bool) -> Result<(), RuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let result = match (&a, &b) {
+            (VmValue::Number(x), VmValue::Number(y)) => x.partial_cmp(y).is_some_and(op),
+            (VmValue::String(x), VmValue::String(y)) => op(x.as_str().cmp(y.as_str())),
+            _ => return Err(self.runtime_error("operands must be two numbers or two strings")),
+        };
+        self.stack.push(VmValue::Bool(result));
+        Ok(())
+    }
+
+    /// Fail with "division by zero" if the top two stack values are numbers
+    /// and the divisor (top of stack) is zero.
+    fn check_divisor(&self) -> Result<(), RuntimeError> {
+        if let (VmValue::Number(_), VmValue::Number(divisor)) = (self.peek(1)?, self.peek(0)?)
+            && *divisor == 0.0
+        {
+            return Err(self.runtime_error("division by zero"));
+        }
+        Ok(())
+    }
+
+    /// Re-raise an error from a native at the current instruction so it gets
+    /// the call's line and a backtrace.
+    fn locate_native_error(&self, err: RuntimeError) -> RuntimeError {
+        match err {
+            RuntimeError::Error { message, .. } => self.runtime_error(message),
+            other => other,
+        }
+    }
+
     fn call_value(&mut self, callee: VmValue, arg_count: usize) -> Result<(), RuntimeError> {
         match callee {
             VmValue::Closure(closure) => {
@@ -585,54 +1114,37 @@ impl Vm {
                         closure.function.arity
                     )));
                 }
-                let slot_offset = self.stack.len() - arg_count - 1;
-                self.frames.push(CallFrame {
-                    closure,
-                    ip: 0,
-                    slot_offset,
-                });
-                Ok(())
+                let slot_offset = self.callee_slot(arg_count)?;
+                self.push_frame(closure, slot_offset)
             }
             VmValue::NativeFunction(native) => {
-                // Check arity for each native function.
-                let expected_arity = match native {
-                    NativeFn::Clock | NativeFn::ReadLine => 0,
-                    NativeFn::ToNumber => 1,
-                };
+                let expected_arity = native.arity();
                 if arg_count != expected_arity {
                     return Err(self.runtime_error(format!(
                         "expected {expected_arity} arguments but got {arg_count}"
                     )));
                 }
-                let result = match native {
-                    NativeFn::Clock => {
-                        let secs = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .expect("system clock should be after unix epoch")
-                            .as_secs_f64();
-                        VmValue::Number(secs)
-                    }
-                    NativeFn::ReadLine => {
-                        match crate::stdlib::read_line_from(&mut std::io::stdin().lock()) {
-                            None => VmValue::Nil,
-                            Some(s) => VmValue::String(Rc::new(s)),
-                        }
-                    }
-                    NativeFn::ToNumber => {
-                        // arg_count == 1 is guaranteed by the arity check above
-                        let arg = self.stack[self.stack.len() - 1].clone();
-                        match arg {
-                            VmValue::Number(_) => arg,
-                            VmValue::String(s) => match crate::stdlib::parse_lox_number(&s) {
-                                Some(n) => VmValue::Number(n),
-                                None => VmValue::Nil,
-                            },
-                            _ => VmValue::Nil,
-                        }
-                    }
+                if self.sandboxed && native.has_side_effects() {
+                    return Err(self.runtime_error(format!(
+                        "{}() is not available in sandbox mode",
+                        native.name()
+                    )));
+                }
+                if native == NativeFn::Env && !self.allow_env {
+                    return Err(self.runtime_error("env() is disabled"));
+                }
+                let start = self.callee_slot(arg_count)?;
+                let result = if matches!(native, NativeFn::Write | NativeFn::Writeln) {
+                    let text = self.stack.last().expect("arity checked").to_string();
+                    self.write_output(text, native == NativeFn::Writeln);
+                    VmValue::Nil
+                } else {
+                    let args = &self.stack[start + 1..];
+                    native
+                        .call(args)
+                        .map_err(|err| self.locate_native_error(err))?
                 };
                 // Remove callee + args, push result
-                let start = self.stack.len() - arg_count - 1;
                 self.stack.truncate(start);
                 self.stack.push(result);
                 Ok(())
@@ -642,7 +1154,7 @@ impl Vm {
                     class: Rc::clone(&class),
                     fields: HashMap::new(),
                 }));
-                let slot_offset = self.stack.len() - arg_count - 1;
+                let slot_offset = self.callee_slot(arg_count)?;
                 self.stack[slot_offset] = VmValue::Instance(Rc::clone(&instance));
 
                 if let Some(init) = class.borrow().methods.get("init").cloned() {
@@ -652,11 +1164,7 @@ impl Vm {
                             init.function.arity
                         )));
                     }
-                    self.frames.push(CallFrame {
-                        closure: init,
-                        ip: 0,
-                        slot_offset,
-                    });
+                    self.push_frame(init, slot_offset)?;
                 } else if arg_count != 0 {
                     return Err(
                         self.runtime_error(format!("expected 0 arguments but got {arg_count}"))
@@ -665,7 +1173,7 @@ impl Vm {
                 Ok(())
             }
             VmValue::BoundMethod(bm) => {
-                let slot_offset = self.stack.len() - arg_count - 1;
+                let slot_offset = self.callee_slot(arg_count)?;
                 self.stack[slot_offset] = bm.receiver.clone();
                 if arg_count != bm.method.function.arity {
                     return Err(self.runtime_error(format!(
@@ -673,17 +1181,34 @@ impl Vm {
                         bm.method.function.arity
                     )));
                 }
-                self.frames.push(CallFrame {
-                    closure: Rc::clone(&bm.method),
-                    ip: 0,
-                    slot_offset,
-                });
-                Ok(())
+                self.push_frame(Rc::clone(&bm.method), slot_offset)
             }
             _ => Err(self.runtime_error("can only call functions and classes")),
         }
     }
 
+    /// Replace the current frame with a call to `closure`, sliding the callee
+    /// and its arguments down into the outgoing frame's slot window.
+    fn tail_call(&mut self, closure: Rc<VmClosure>, arg_count: usize) -> Result<(), RuntimeError> {
+        if arg_count != closure.function.arity {
+            return Err(self.runtime_error(format!(
+                "expected {} arguments but got {arg_count}",
+                closure.function.arity
+            )));
+        }
+        let slot_offset = self.frames.last().expect("frame").slot_offset;
+        self.close_upvalues(slot_offset);
+        let callee_idx = self.callee_slot(arg_count)?;
+        if callee_idx < slot_offset {
+            return Err(self.stack_underflow());
+        }
+        self.stack.drain(slot_offset..callee_idx);
+        let frame = self.frames.last_mut().expect("frame");
+        frame.closure = closure;
+        frame.ip = 0;
+        Ok(())
+    }
+
     fn invoke_from_class(
         &mut self,
         class: &Rc<RefCell<VmClass>>,
@@ -696,9 +1221,28 @@ impl Vm {
             .get(name)
             .cloned()
             .ok_or_else(|| self.runtime_error(format!("undefined property '{name}'")))?;
-        let slot_offset = self.stack.len() - arg_count - 1;
+        if arg_count != method.function.arity {
+            return Err(self.runtime_error(format!(
+                "expected {} arguments but got {arg_count}",
+                method.function.arity
+            )));
+        }
+        let slot_offset = self.callee_slot(arg_count)?;
+        self.push_frame(method, slot_offset)
+    }
+
+    /// Push a new call frame, failing with a stack overflow error once the
+    /// call depth limit is reached. The script frame doesn't count.
+    fn push_frame(
+        &mut self,
+        closure: Rc<VmClosure>,
+        slot_offset: usize,
+    ) -> Result<(), RuntimeError> {
+        if self.frames.len() > self.max_call_depth {
+            return Err(self.runtime_error(stack_overflow_message(self.max_call_depth)));
+        }
         self.frames.push(CallFrame {
-            closure: method,
+            closure,
             ip: 0,
             slot_offset,
         });
@@ -769,6 +1313,11 @@ fn values_equal(a: &VmValue, b: &VmValue) -> bool {
         (VmValue::Bool(a), VmValue::Bool(b)) => a == b,
         (VmValue::Number(a), VmValue::Number(b)) => a == b,
         (VmValue::String(a), VmValue::String(b)) => a == b,
+        (VmValue::Closure(a), VmValue::Closure(b)) => Rc::ptr_eq(a, b),
+        (VmValue::NativeFunction(a), VmValue::NativeFunction(b)) => a == b,
+        (VmValue::Class(a), VmValue::Class(b)) => Rc::ptr_eq(a, b),
+        (VmValue::Instance(a), VmValue::Instance(b)) => Rc::ptr_eq(a, b),
+        (VmValue::BoundMethod(a), VmValue::BoundMethod(b)) => Rc::ptr_eq(a, b),
         _ => false,
     }
 }
@@ -778,13 +1327,16 @@ mod tests {
     use super::*;
     use crate::parser::Parser;
     use crate::scanner;
+    use crate::test_support::FlushRecorder;
     use crate::vm::compiler::Compiler;
     use rstest::rstest;
 
     fn run_vm(source: &str) -> Vec<String> {
         let tokens = scanner::scan(source).expect("scan");
         let program = Parser::new(tokens).parse().expect("parse");
-        let chunk = Compiler::new().compile(&program).expect("compile");
+        let chunk = Compiler::with_source(source)
+            .compile(&program)
+            .expect("compile");
         let mut vm = Vm::new_capturing();
         vm.interpret(chunk).expect("interpret");
         vm.output.clone()
@@ -793,7 +1345,9 @@ mod tests {
     fn run_vm_err(source: &str) -> RuntimeError {
         let tokens = scanner::scan(source).expect("scan");
         let program = Parser::new(tokens).parse().expect("parse");
-        let chunk = Compiler::new().compile(&program).expect("compile");
+        let chunk = Compiler::with_source(source)
+            .compile(&program)
+            .expect("compile");
         let mut vm = Vm::new_capturing();
         vm.interpret(chunk).unwrap_err()
     }
@@ -803,11 +1357,356 @@ mod tests {
     #[case("print 10 - 3;", "7")]
     #[case("print 2 * 3;", "6")]
     #[case("print 10 / 4;", "2.5")]
+    #[case("print 7 % 3;", "1")]
     #[case("print -5;", "-5")]
     fn vm_arithmetic(#[case] source: &str, #[case] expected: &str) {
         assert_eq!(run_vm(source), vec![expected]);
     }
 
+    #[rstest]
+    #[case::direct_class("class A {} print A() is A;", "true")]
+    #[case::superclass("class A {} class B < A {} print B() is A;", "true")]
+    #[case::grandparent("class A {} class B < A {} class C < B {} print C() is A;", "true")]
+    #[case::subclass_of_instance("class A {} class B < A {} print A() is B;", "false")]
+    #[case::unrelated("class A {} class B {} print A() is B;", "false")]
+    #[case::non_instance("class A {} print 1 is A;", "false")]
+    fn vm_is_operator(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case::many_locals(
+        "var x = 0; { var a = 1; var b = 2; var c = 3; var d = 4; var e = 5; \
+         x = a + b + c + d + e; } print x;",
+        vec!["15"]
+    )]
+    #[case::locals_in_a_loop(
+        "var sum = 0; for (var i = 0; i < 3; i = i + 1) { var a = i; var b = a * 2; sum = sum + b; } \
+         print sum;",
+        vec!["6"]
+    )]
+    #[case::captured_between_locals(
+        "var f; { var a = 1; var b = 2; fun g() { return b; } var c = 3; f = g; b = 20; } \
+         print f(); { var y = 7; var z = 8; print y + z; }",
+        vec!["20", "15"]
+    )]
+    #[case::function_body(
+        "fun f() { var a = 1; { var b = 2; var c = 3; print a + b + c; } return a; } print f();",
+        vec!["6", "1"]
+    )]
+    fn vm_block_exit_pops_locals(#[case] source: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(run_vm(source), expected);
+    }
+
+    #[rstest]
+    #[case::zero("print 0;", "0")]
+    #[case::one("print 1;", "1")]
+    #[case::arithmetic("print (1 + 1) * 0 + 1;", "1")]
+    #[case::negated_zero("print -0 == 0;", "true")]
+    #[case::loop_counter(
+        "var n = 0; for (var i = 0; i < 4; i = i + 1) n = n + i; print n;",
+        "6"
+    )]
+    fn vm_zero_and_one_opcodes_push_numbers(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[test]
+    fn vm_is_operator_requires_class() {
+        let err = run_vm_err("class A {} print A() is 1;");
+        assert!(
+            err.to_string()
+                .contains("right operand of 'is' must be a class")
+        );
+    }
+
+    #[test]
+    fn vm_dup_copies_top_of_stack() {
+        let mut chunk = Chunk::new();
+        let three = chunk.add_constant(Constant::Number(3.0));
+        chunk.write_op(OpCode::Constant, 1);
+        chunk.write_byte(three, 1);
+        chunk.write_op(OpCode::Dup, 1);
+        chunk.write_op(OpCode::Multiply, 1);
+        chunk.write_op(OpCode::Return, 1);
+        let mut vm = Vm::new_capturing();
+        let result = vm.interpret_returning(chunk).expect("interpret");
+        assert_eq!(result, VmValue::Number(9.0));
+    }
+
+    #[test]
+    fn vm_dup_shares_reference_values() {
+        let mut chunk = Chunk::new();
+        let name = chunk.add_constant(Constant::String("Box".to_string()));
+        chunk.write_op(OpCode::Class, 1);
+        chunk.write_byte(name, 1);
+        chunk.write_op(OpCode::Dup, 1);
+        chunk.write_op(OpCode::Equal, 1);
+        chunk.write_op(OpCode::Return, 1);
+        let mut vm = Vm::new_capturing();
+        let result = vm.interpret_returning(chunk).expect("interpret");
+        assert_eq!(result, VmValue::Bool(true));
+    }
+
+    #[rstest]
+    #[case::nil_jumps_and_keeps_value(None, VmValue::Nil)]
+    #[case::non_nil_falls_through(Some(7.0), VmValue::Number(-7.0))]
+    fn vm_jump_if_nil(#[case] value: Option<f64>, #[case] expected: VmValue) {
+        let mut chunk = Chunk::new();
+        match value {
+            Some(n) => {
+                let idx = chunk.add_constant(Constant::Number(n));
+                chunk.write_op(OpCode::Constant, 1);
+                chunk.write_byte(idx, 1);
+            }
+            None => chunk.write_op(OpCode::Nil, 1),
+        }
+        // Negating nil would fail, so reaching `Return` with nil proves the
+        // jump was taken without popping
+        chunk.write_op(OpCode::JumpIfNil, 1);
+        chunk.write_u16(1, 1);
+        chunk.write_op(OpCode::Negate, 1);
+        chunk.write_op(OpCode::Return, 1);
+        let mut vm = Vm::new_capturing();
+        let result = vm.interpret_returning(chunk).expect("interpret");
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn vm_print_flushes_each_line() {
+        let tokens = scanner::scan("print 1; print 2, 3;").expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::new().compile(&program).expect("compile");
+        let recorder = FlushRecorder::default();
+        let flushed = Rc::clone(&recorder.flushed);
+        let mut vm = Vm::new_capturing();
+        vm.writer = Box::new(recorder);
+        vm.interpret(chunk).expect("interpret");
+        assert_eq!(*flushed.borrow(), vec!["1\n", "2 3\n"]);
+    }
+
+    /// A chunk made of `code` alone, every byte on line 1.
+    fn raw_chunk(code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &byte in code {
+            chunk.write_byte(byte, 1);
+        }
+        chunk
+    }
+
+    #[rstest]
+    #[case::binary_op(&[OpCode::Add as u8, OpCode::Return as u8], 0)]
+    // The script closure occupies the bottom slot, so it takes two pops
+    #[case::extra_pop(&[OpCode::Pop as u8, OpCode::Pop as u8, OpCode::Return as u8], 1)]
+    #[case::call_past_bottom(&[OpCode::Nil as u8, OpCode::Call as u8, 5], 1)]
+    #[case::print_n(&[OpCode::Nil as u8, OpCode::PrintN as u8, 3], 1)]
+    #[case::pop_n(&[OpCode::Nil as u8, OpCode::PopN as u8, 3], 1)]
+    #[case::jump_if_false(&[OpCode::Pop as u8, OpCode::JumpIfFalse as u8, 0, 0], 1)]
+    #[case::return_with_nothing(&[OpCode::Pop as u8, OpCode::Return as u8], 1)]
+    fn vm_stack_underflow_is_an_error(#[case] code: &[u8], #[case] ip: usize) {
+        let err = Vm::new_capturing()
+            .interpret_returning(raw_chunk(code))
+            .expect_err("malformed bytecode should fail");
+        assert!(
+            err.to_string()
+                .contains(&format!("bytecode stack underflow at ip {ip}")),
+            "got: {err}"
+        );
+    }
+
+    /// A function constant with one upvalue, for `Closure` operand tests.
+    fn captured_function() -> Constant {
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::Return, 1);
+        Constant::Function {
+            name: "f".to_string(),
+            arity: 0,
+            upvalue_count: 1,
+            chunk,
+        }
+    }
+
+    #[test]
+    fn vm_super_invoke_past_bottom_is_an_error() {
+        let mut method = Chunk::new();
+        method.write_op(OpCode::Nil, 1);
+        method.write_op(OpCode::Return, 1);
+        let mut chunk = raw_chunk(&[
+            OpCode::Class as u8,
+            0,
+            OpCode::Closure as u8,
+            1,
+            OpCode::Method as u8,
+            0,
+            OpCode::SuperInvoke as u8,
+            0,
+            5,
+        ]);
+        chunk.add_constant(Constant::String("m".to_string()));
+        chunk.add_constant(Constant::Function {
+            name: "m".to_string(),
+            arity: 5,
+            upvalue_count: 0,
+            chunk: method,
+        });
+        let err = Vm::new_capturing()
+            .interpret_returning(chunk)
+            .expect_err("malformed bytecode should fail");
+        assert!(
+            err.to_string().contains("bytecode stack underflow at ip 6"),
+            "got: {err}"
+        );
+    }
+
+    #[rstest]
+    #[case::missing_byte(&[OpCode::Nil as u8, OpCode::Constant as u8], &[], "truncated bytecode instruction at ip 1")]
+    #[case::half_a_jump(&[OpCode::Jump as u8, 0], &[], "truncated bytecode instruction at ip 0")]
+    #[case::missing_constant(&[OpCode::Constant as u8, 3], &[], "bytecode constant 3 out of range at ip 0")]
+    #[case::name_not_a_string(
+        &[OpCode::GetGlobal as u8, 0],
+        &[Constant::Number(2.0)],
+        "bytecode constant 0 is not a string at ip 0"
+    )]
+    #[case::get_local_past_stack(&[OpCode::GetLocal as u8, 5], &[], "bytecode local slot 5 out of range at ip 0")]
+    #[case::set_local_past_stack(
+        &[OpCode::Nil as u8, OpCode::SetLocal as u8, 7],
+        &[],
+        "bytecode local slot 7 out of range at ip 1"
+    )]
+    #[case::get_missing_upvalue(&[OpCode::GetUpvalue as u8, 0], &[], "bytecode upvalue 0 out of range at ip 0")]
+    #[case::set_missing_upvalue(
+        &[OpCode::Nil as u8, OpCode::SetUpvalue as u8, 2],
+        &[],
+        "bytecode upvalue 2 out of range at ip 1"
+    )]
+    #[case::closure_captures_missing_local(
+        &[OpCode::Closure as u8, 0, 1, 9],
+        &[captured_function()],
+        "bytecode local slot 9 out of range at ip 0"
+    )]
+    #[case::closure_captures_missing_upvalue(
+        &[OpCode::Closure as u8, 0, 0, 4],
+        &[captured_function()],
+        "bytecode upvalue 4 out of range at ip 0"
+    )]
+    #[case::loop_before_start(&[OpCode::Loop as u8, 0, 10], &[], "bytecode loop before the start of the chunk at ip 0")]
+    #[case::get_unknown_global(
+        &[OpCode::GetGlobal as u8, 0],
+        &[Constant::String("g".to_string())],
+        "undefined variable 'g'"
+    )]
+    #[case::set_unknown_global(
+        &[OpCode::Nil as u8, OpCode::SetGlobal as u8, 0],
+        &[Constant::String("g".to_string())],
+        "undefined variable 'g'"
+    )]
+    fn vm_malformed_operands_are_an_error(
+        #[case] code: &[u8],
+        #[case] constants: &[Constant],
+        #[case] expected: &str,
+    ) {
+        let mut chunk = raw_chunk(code);
+        chunk.constants = constants.to_vec();
+        let err = Vm::new_capturing()
+            .interpret_returning(chunk)
+            .expect_err("malformed bytecode should fail");
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[test]
+    fn vm_truncated_chunk_underflows_cleanly() {
+        let tokens = scanner::scan("print 1 + 2;").expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let mut chunk = Compiler::new().compile(&program).expect("compile");
+        // Drop the `One` and `Constant` instructions that feed the addition
+        chunk.code.drain(..3);
+        chunk.lines.drain(..3);
+        let err = Vm::new_capturing()
+            .interpret(chunk)
+            .expect_err("truncated bytecode should fail");
+        assert!(
+            err.to_string().contains("bytecode stack underflow at ip 0"),
+            "got: {err}"
+        );
+    }
+
+    #[rstest]
+    #[case::write_joins_one_line("write(\"a\"); write(\"b\");", vec!["ab"])]
+    #[case::print_ends_a_written_line("write(\"a\"); print \"b\";", vec!["ab"])]
+    #[case::writeln_ends_the_line("write(1); writeln(2); write(3);", vec!["12", "3"])]
+    #[case::writeln_alone("writeln(\"x\"); print \"y\";", vec!["x", "y"])]
+    #[case::returns_nil("print write(\"\") == nil;", vec!["true"])]
+    fn vm_write_and_writeln(#[case] source: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(run_vm(source), expected);
+    }
+
+    #[rstest]
+    #[case::write(NativeFn::Write)]
+    #[case::writeln(NativeFn::Writeln)]
+    fn vm_writing_natives_called_directly_are_an_error(#[case] native: NativeFn) {
+        let err = native.call(&[VmValue::Nil]).expect_err("needs a VM");
+        assert!(
+            err.to_string()
+                .contains("can only be called from a running program"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn vm_write_goes_through_the_writer() {
+        let tokens = scanner::scan("write(\"a\"); writeln(\"b\");").expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::new().compile(&program).expect("compile");
+        let recorder = FlushRecorder::default();
+        let flushed = Rc::clone(&recorder.flushed);
+        let mut vm = Vm::new_capturing();
+        vm.writer = Box::new(recorder);
+        vm.interpret(chunk).expect("interpret");
+        assert_eq!(*flushed.borrow(), vec!["a", "b\n"]);
+    }
+
+    #[test]
+    fn vm_rejects_unknown_opcode() {
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_byte(250, 1);
+        let mut vm = Vm::new_capturing();
+        let err = vm
+            .interpret_returning(chunk)
+            .expect_err("corrupt bytecode should fail");
+        assert!(
+            err.to_string().contains("unknown opcode 250"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[rstest]
+    #[case("print 2 ** 10;", "1024")]
+    #[case("print 2 ** 3 ** 2;", "512")]
+    #[case("print -2 ** 2;", "-4")]
+    fn vm_power(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print nil ?? 5;", "5")]
+    #[case("print 0 ?? 5;", "0")]
+    #[case("print 0 or 5;", "0")]
+    #[case("print false ?? 5;", "false")]
+    #[case("print false or 5;", "5")]
+    #[case("fun boom() { print \"evaluated\"; } print 1 ?? boom();", "1")]
+    fn vm_nil_coalescing(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case("var i = 10; do { print i; i = i + 1; } while (i < 3);", vec!["10"])]
+    #[case("var i = 0; do { print i; i = i + 1; } while (i < 3);", vec!["0", "1", "2"])]
+    fn vm_do_while(#[case] source: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(run_vm(source), expected);
+    }
+
     #[test]
     fn vm_string_concat() {
         assert_eq!(run_vm("print \"hello\" + \" world\";"), vec!["hello world"]);
@@ -839,6 +1738,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn vm_for_increment_error_reports_the_increment_line() {
+        let source = "for (var i = 0;\n     i < 3;\n     i = i + \"x\") {\n  print i;\n}";
+        let err = run_vm_err(source);
+        assert_eq!(
+            err.display_with_line(source),
+            "Error: line 3: operands must be two numbers or two strings"
+        );
+    }
+
+    // The resolver rejects these statically; `run_vm_err` skips it, so the
+    // VM's own check is what fails.
+    #[rstest]
+    #[case::top_level("const x = 1; x = 2;")]
+    #[case::in_function("const x = 1; fun f() { x = 2; } f();")]
+    #[case::after_reading("const x = 1; print x; x = x + 1;")]
+    fn vm_assigning_a_const_global_errors(#[case] source: &str) {
+        let err = run_vm_err(source);
+        assert!(
+            err.to_string().contains("can't assign to constant 'x'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn vm_redefining_a_const_global_with_var_makes_it_assignable() {
+        assert_eq!(run_vm("const x = 1; var x = 2; x = 3; print x;"), vec!["3"]);
+    }
+
     #[test]
     fn vm_for_loop() {
         assert_eq!(
@@ -966,6 +1894,23 @@ mod tests {
         assert_eq!(run_vm("var x = 1; x = 2; print x;"), vec!["2"]);
     }
 
+    #[rstest]
+    #[case::reassigned_then_read_by_slot("var x = 1; x = 2; print x;", "2")]
+    #[case::redefinition_reuses_slot("var a = 1; var b = 2; var a = a + b; print a;", "3")]
+    #[case::forward_reference_by_name("fun f() { return later; } var later = 4; print f();", "4")]
+    #[case::recursion_by_slot(
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);",
+        "55"
+    )]
+    #[case::class_in_its_own_method(
+        "class C { make() { return C; } } print C().make() == C;",
+        "true"
+    )]
+    #[case::native_by_slot("print type(strlen);", "function")]
+    fn vm_global_slots(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
     #[test]
     fn vm_local_reassignment() {
         assert_eq!(run_vm("{ var x = 1; x = 2; print x; }"), vec!["2"]);
@@ -1046,6 +1991,127 @@ mod tests {
         );
     }
 
+    #[test]
+    fn vm_mutual_tail_recursion_deep() {
+        let source = r#"
+fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }
+fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }
+print isEven(200000);
+print isOdd(200001);
+"#;
+        assert_eq!(run_vm(source), vec!["true", "true"]);
+    }
+
+    #[test]
+    fn vm_tail_call_reuses_frame() {
+        let tokens = scanner::scan(
+            "fun loop(n) { if (n == 0) return n; return loop(n - 1); } print loop(5000);",
+        )
+        .expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::new().compile(&program).expect("compile");
+        let mut vm = Vm::new_capturing();
+        vm.interpret(chunk).expect("interpret");
+        assert_eq!(vm.output, vec!["0"]);
+        assert!(
+            vm.frames.capacity() < 5000,
+            "tail calls should not grow the frame stack"
+        );
+    }
+
+    #[test]
+    fn vm_tail_call_closes_upvalues() {
+        let source = r#"
+fun make(n) {
+  var x = n;
+  fun get() { return x; }
+  return get;
+}
+fun call(f) { return f(); }
+fun wrap(n) { var g = make(n); return call(g); }
+print wrap(7);
+"#;
+        assert_eq!(run_vm(source), vec!["7"]);
+    }
+
+    #[test]
+    fn vm_tail_call_to_class_is_regular_call() {
+        let source = r#"
+class Point { init(x) { this.x = x; } }
+fun make(x) { return Point(x); }
+print make(3).x;
+"#;
+        assert_eq!(run_vm(source), vec!["3"]);
+    }
+
+    #[test]
+    fn vm_tail_call_wrong_arity() {
+        let err = run_vm_err("fun f(a) { return f(); } f(1);");
+        assert!(err.to_string().contains("expected 1 arguments but got 0"));
+    }
+
+    #[test]
+    fn vm_clock_rejects_arguments() {
+        let err = run_vm_err("print clock(1);");
+        assert!(
+            err.to_string().contains("expected 0 arguments but got 1"),
+            "got: {err}"
+        );
+    }
+
+    #[test]
+    fn vm_clock_rejects_many_arguments() {
+        let err = run_vm_err("clock(1, 2, 3);");
+        assert!(
+            err.to_string().contains("expected 0 arguments but got 3"),
+            "got: {err}"
+        );
+    }
+
+    #[rstest]
+    #[case("toNumber(\"1\", \"2\");", "expected 1 arguments but got 2")]
+    #[case("indexOf(\"abc\", \"b\", 0);", "expected 2 arguments but got 3")]
+    #[case("substr(\"abc\");", "expected 3 arguments but got 1")]
+    fn vm_native_wrong_arity(#[case] source: &str, #[case] expected: &str) {
+        let err = run_vm_err(source);
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[test]
+    fn vm_infinite_recursion_is_stack_overflow_error() {
+        let err = run_vm_err("fun f() { f(); } f();");
+        assert!(
+            err.to_string()
+                .contains("stack overflow (max call depth 1000 exceeded)"),
+            "got: {err}"
+        );
+    }
+
+    #[test]
+    fn vm_bounded_deep_recursion_succeeds() {
+        assert_eq!(
+            run_vm(
+                "fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); } print depth(999);"
+            ),
+            vec!["999"]
+        );
+    }
+
+    #[test]
+    fn vm_custom_max_call_depth() {
+        let tokens = scanner::scan(
+            "fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); } print depth(4); print depth(5);",
+        )
+        .expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::new().compile(&program).expect("compile");
+        let mut vm = Vm::new_capturing();
+        vm.set_max_call_depth(5);
+        let err = vm.interpret(chunk).unwrap_err();
+        assert_eq!(vm.output, vec!["4"]);
+        assert!(err.to_string().contains("max call depth 5 exceeded"));
+    }
+
     #[test]
     fn vm_nested_function_calls() {
         assert_eq!(
@@ -1190,6 +2256,31 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case::stored_in_variable(
+        "class C { init(n) { this.n = n; } get() { return this.n; } } var m = C(1).get; print m();",
+        "1"
+    )]
+    #[case::passed_as_argument(
+        "class C { init(n) { this.n = n; } get() { return this.n; } } fun call(f) { return f(); } print call(C(2).get);",
+        "2"
+    )]
+    #[case::outlives_receiver_scope(
+        "class C { init(n) { this.n = n; } get() { return this.n; } } var m; { var c = C(3); m = c.get; } print m();",
+        "3"
+    )]
+    #[case::sees_later_field_writes(
+        "class C { get() { return this.n; } } var c = C(); var m = c.get; c.n = 4; print m();",
+        "4"
+    )]
+    #[case::inherited_method(
+        "class A { get() { return \"a\"; } } class B < A {} var m = B().get; print m();",
+        "a"
+    )]
+    fn vm_method_reference(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
     // ========== Error Cases ==========
 
     #[test]
@@ -1234,18 +2325,129 @@ mod tests {
         assert!(err.to_string().contains("operands must be"));
     }
 
+    #[rstest]
+    #[case("print \"apple\" < \"banana\";", "true")]
+    #[case("print \"apple\" > \"banana\";", "false")]
+    #[case("print \"b\" >= \"b\";", "true")]
+    #[case("print \"b\" <= \"a\";", "false")]
+    #[case("print 1 < 2;", "true")]
+    #[case("print 2 <= 1;", "false")]
+    fn vm_comparison(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case("1", "2")]
+    #[case("2", "1")]
+    #[case("2", "2")]
+    #[case("-0", "0")]
+    #[case("\"a\"", "\"b\"")]
+    #[case("\"b\"", "\"b\"")]
+    fn vm_fused_comparisons_match_negated_forms(#[case] a: &str, #[case] b: &str) {
+        let fused = run_vm(&format!("print {a} <= {b}, {a} >= {b}, {a} != {b};"));
+        let negated = run_vm(&format!("print !({a} > {b}), !({a} < {b}), !({a} == {b});"));
+        assert_eq!(fused, negated);
+    }
+
+    #[rstest]
+    #[case("print 1 != \"1\";", "true")]
+    #[case("print nil != false;", "true")]
+    #[case("print nil != nil;", "false")]
+    fn vm_not_equal_across_types(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[test]
+    fn vm_nan_comparisons_are_false() {
+        // inf - inf is NaN; `<=` and `>=` no longer report it as ordered,
+        // matching the tree-walk interpreter.
+        let source = "var big = 1; for (var i = 0; i < 400; i = i + 1) big = big * 10; \
+                      var nan = big - big; print nan <= 1, nan >= 1, nan != nan;";
+        assert_eq!(run_vm(source), vec!["false false true"]);
+    }
+
+    #[test]
+    fn vm_comparison_mixed_types_is_error() {
+        let err = run_vm_err("print 1 < \"2\";");
+        assert!(
+            err.to_string()
+                .contains("operands must be two numbers or two strings")
+        );
+    }
+
     #[test]
     fn vm_type_error_subtract_strings() {
         let err = run_vm_err("print \"a\" - \"b\";");
         assert!(err.to_string().contains("operands must be numbers"));
     }
 
+    #[rstest]
+    #[case("print 1 / 0;")]
+    #[case("print 0 / 0;")]
+    #[case("print 5 % 0;")]
+    fn vm_division_by_zero(#[case] source: &str) {
+        let err = run_vm_err(source);
+        assert!(err.to_string().contains("division by zero"), "got: {err}");
+    }
+
+    #[test]
+    fn vm_division_by_zero_reports_line() {
+        let err = run_vm_err("var a = 1;\nvar b = 0;\nprint a / b;\n");
+        assert!(
+            err.to_string().contains("line 3: division by zero"),
+            "got: {err}"
+        );
+    }
+
     #[test]
     fn vm_undefined_property() {
         let err = run_vm_err("class Foo {} var f = Foo(); print f.bar;");
         assert!(err.to_string().contains("undefined property"));
     }
 
+    #[rstest]
+    #[case::field_holding_closure(
+        "class Box {} var b = Box(); fun shout(s) { return upper(s); } b.f = shout; print b.f(\"hi\");",
+        "HI"
+    )]
+    #[case::field_holding_bound_method(
+        "class A { init() { this.n = 1; } get() { return this.n; } }
+         class B {} var a = A(); var b = B(); b.f = a.get; print b.f();",
+        "1"
+    )]
+    #[case::field_shadows_method(
+        "class C { m() { return \"method\"; } } var c = C(); fun f() { return \"field\"; } c.m = f; print c.m();",
+        "field"
+    )]
+    #[case::method_sees_receiver(
+        "class Counter { init() { this.n = 0; } add(k) { this.n = this.n + k; return this; } }
+         var c = Counter(); c.add(2).add(3); print c.n;",
+        "5"
+    )]
+    #[case::inherited_method(
+        "class A { hi(name) { return \"hi \" + name; } } class B < A {} print B().hi(\"bob\");",
+        "hi bob"
+    )]
+    fn vm_invoke(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case::missing_method("class C {} C().missing();", "undefined property 'missing'")]
+    #[case::wrong_arity(
+        "class C { m(a) { return a; } } C().m(1, 2);",
+        "expected 1 arguments but got 2"
+    )]
+    #[case::non_instance("var x = 42; x.foo();", "only instances have properties")]
+    #[case::field_not_callable(
+        "class C {} var c = C(); c.f = 1; c.f();",
+        "can only call functions and classes"
+    )]
+    fn vm_invoke_errors(#[case] source: &str, #[case] expected: &str) {
+        let err = run_vm_err(source);
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
     #[test]
     fn vm_property_on_non_instance() {
         let err = run_vm_err("var x = 42; print x.foo;");
@@ -1274,6 +2476,249 @@ mod tests {
         assert!(output[0].parse::<f64>().is_ok());
     }
 
+    #[test]
+    fn vm_time_millis_function() {
+        let output = run_vm("print time_millis(); print time_millis() > 0;");
+        assert!(output[0].parse::<f64>().is_ok());
+        assert_eq!(output[1], "true");
+    }
+
+    // ========== String natives ==========
+
+    #[rstest]
+    #[case(r#"print strlen("hello");"#, "5")]
+    #[case(r#"print strlen("héllo");"#, "5")]
+    #[case(r#"print substr("hello", 1, 3);"#, "el")]
+    #[case(r#"print substr("héllo", 1, 4);"#, "éll")]
+    #[case(r#"print substr("héllo", 3, 100);"#, "lo")]
+    #[case(r#"print upper("héllo");"#, "HÉLLO")]
+    #[case(r#"print lower("HeLLo");"#, "hello")]
+    #[case(r#"print indexOf("héllo", "llo");"#, "2")]
+    #[case(r#"print indexOf("hello", "z");"#, "-1")]
+    #[case("print strlen(42);", "nil")]
+    fn vm_string_natives(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[test]
+    fn vm_substr_wrong_arity() {
+        let err = run_vm_err(r#"substr("abc", 1);"#);
+        assert!(err.to_string().contains("expected 3"));
+    }
+
+    // ========== chr() / ord() ==========
+
+    #[rstest]
+    #[case(r#"print ord("A");"#, "65")]
+    #[case(r#"print ord("é");"#, "233")]
+    #[case("print chr(65);", "A")]
+    #[case(r#"print chr(ord("z"));"#, "z")]
+    fn vm_chr_ord(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[rstest]
+    #[case(r#"ord("");"#, "non-empty string")]
+    #[case("ord(65);", "must be a string")]
+    #[case("chr(-1);", "valid codepoint")]
+    #[case("chr(65.5);", "valid codepoint")]
+    #[case(r#"chr("A");"#, "must be a number")]
+    fn vm_chr_ord_errors(#[case] source: &str, #[case] expected: &str) {
+        let err = run_vm_err(source);
+        assert!(err.to_string().contains(expected), "got: {err}");
+    }
+
+    #[rstest]
+    #[case(r#"substr("hello", 1.5, 3);"#, "got 1.5")]
+    #[case(r#"substr("hello", -1, 3);"#, "got -1")]
+    #[case(
+        r#"substr("hello", 0, 100000000000000000000);"#,
+        "got 100000000000000000000"
+    )]
+    fn vm_substr_rejects_bad_indices(#[case] source: &str, #[case] detail: &str) {
+        let err = run_vm_err(source).to_string();
+        assert!(
+            err.contains("substr() index must be a non-negative integer"),
+            "got: {err}"
+        );
+        assert!(err.contains(detail), "got: {err}");
+    }
+
+    #[rstest]
+    // Cargo sets CARGO_PKG_NAME for the test process.
+    #[case(r#"print env("CARGO_PKG_NAME");"#, env!("CARGO_PKG_NAME"))]
+    #[case(r#"print env("VIBE_LOX_VM_ENV_UNSET");"#, "nil")]
+    fn vm_env_reads_process_environment(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    #[test]
+    fn vm_env_requires_string_argument() {
+        let err = run_vm_err("env(1);");
+        assert!(err.to_string().contains("env() argument must be a string"));
+    }
+
+    #[test]
+    fn vm_env_can_be_disabled() {
+        let tokens = scanner::scan(r#"print env("HOME");"#).expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::new().compile(&program).expect("compile");
+        let mut vm = Vm::new_capturing();
+        vm.set_allow_env(false);
+        let err = vm.interpret(chunk).unwrap_err();
+        assert!(err.to_string().contains("env() is disabled"), "got: {err}");
+        assert!(vm.output.is_empty());
+    }
+
+    fn run_vm_sandboxed(source: &str) -> (Vec<String>, Result<(), RuntimeError>) {
+        let tokens = scanner::scan(source).expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::new().compile(&program).expect("compile");
+        let mut vm = Vm::sandboxed();
+        vm.writer = Box::new(Vec::<u8>::new());
+        let result = vm.interpret(chunk);
+        (vm.output, result)
+    }
+
+    #[rstest]
+    #[case("clock();", "clock")]
+    #[case("readLine();", "readLine")]
+    #[case("eprint(1);", "eprint")]
+    #[case("time_millis();", "time_millis")]
+    #[case(r#"env("HOME");"#, "env")]
+    fn vm_sandbox_rejects_side_effecting_natives(#[case] source: &str, #[case] name: &str) {
+        let (_, result) = run_vm_sandboxed(source);
+        let err = result.expect_err("native should be disabled");
+        assert!(
+            err.to_string()
+                .contains(&format!("{name}() is not available in sandbox mode")),
+            "got: {err}"
+        );
+    }
+
+    #[test]
+    fn vm_sandbox_still_runs_pure_code() {
+        let (output, result) = run_vm_sandboxed(r#"print 1 + 1; print upper("ok");"#);
+        result.expect("interpret");
+        assert_eq!(output, vec!["2", "OK"]);
+    }
+
+    #[test]
+    fn vm_errors_quote_the_source_line() {
+        let source = "var a = 1;\nprint a + nil;\n";
+        let tokens = scanner::scan(source).expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::with_source(source)
+            .compile(&program)
+            .expect("compile");
+        let mut vm = Vm::new_capturing();
+        vm.set_source(source);
+        let err = vm.interpret(chunk).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error: line 2: operands must be two numbers or two strings\n    2 | print a + nil;"
+        );
+    }
+
+    #[test]
+    fn vm_errors_without_source_omit_the_line_text() {
+        let err = run_vm_err("var a = 1;\nprint a + nil;");
+        assert_eq!(
+            err.to_string(),
+            "Error: line 2: operands must be two numbers or two strings"
+        );
+    }
+
+    #[test]
+    fn vm_native_errors_report_the_call_line() {
+        let err = run_vm_err("var x = 1;\nprint chr(\"A\");");
+        assert!(
+            err.to_string()
+                .contains("line 2: chr() argument must be a number, got A"),
+            "got: {err}"
+        );
+    }
+
+    // ========== script result ==========
+
+    fn run_vm_returning(source: &str) -> VmValue {
+        let tokens = scanner::scan(source).expect("scan");
+        let program = Parser::new(tokens).parse().expect("parse");
+        let chunk = Compiler::with_source(source)
+            .compile(&program)
+            .expect("compile");
+        Vm::new_capturing()
+            .interpret_returning(chunk)
+            .expect("interpret")
+    }
+
+    #[test]
+    fn vm_interpret_returning_final_expression() {
+        assert_eq!(
+            run_vm_returning("var x = 41; x + 1;"),
+            VmValue::Number(42.0)
+        );
+    }
+
+    #[rstest]
+    #[case("var x = 41;")]
+    #[case("print 1;")]
+    #[case("1; var y;")]
+    #[case("")]
+    fn vm_interpret_returning_nil_without_trailing_expression(#[case] source: &str) {
+        assert_eq!(run_vm_returning(source), VmValue::Nil);
+    }
+
+    #[test]
+    fn vm_interpret_returning_object_value() {
+        let value = run_vm_returning("class Foo {} Foo();");
+        assert_eq!(value.type_name(), "instance");
+        assert_eq!(value.to_string(), "Foo instance");
+    }
+
+    // ========== print ==========
+
+    #[rstest]
+    #[case("print 1, 2, 3;", "1 2 3")]
+    #[case(r#"print "a", nil, true;"#, "a nil true")]
+    #[case("var x = 1; print x, x + 1;", "1 2")]
+    fn vm_print_multiple_values(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    // ========== equality ==========
+
+    #[rstest]
+    #[case("class Foo {} var a = Foo(); print a == a;", "true")]
+    #[case("class Foo {} print Foo() == Foo();", "false")]
+    #[case("class Foo {} var a = Foo(); var b = a; print a != b;", "false")]
+    #[case("class Foo {} print Foo == Foo;", "true")]
+    #[case("class Foo {} class Bar {} print Foo == Bar;", "false")]
+    #[case("fun f() {} var g = f; print f == g;", "true")]
+    #[case("fun f() {} fun g() {} print f == g;", "false")]
+    #[case("print clock == clock;", "true")]
+    #[case("print clock == readLine;", "false")]
+    #[case("class Foo {} print Foo() == nil;", "false")]
+    fn vm_reference_equality(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
+    // ========== type() ==========
+
+    #[rstest]
+    #[case("print type(1);", "number")]
+    #[case(r#"print type("s");"#, "string")]
+    #[case("print type(true);", "bool")]
+    #[case("print type(nil);", "nil")]
+    #[case("print type(clock);", "function")]
+    #[case("fun f() {} print type(f);", "function")]
+    #[case("class C { m() {} } print type(C().m);", "function")]
+    #[case("class C {} print type(C);", "class")]
+    #[case("class C {} print type(C());", "instance")]
+    fn vm_type_native(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(run_vm(source), vec![expected]);
+    }
+
     // ========== toNumber() ==========
 
     #[rstest]
@@ -1321,6 +2766,20 @@ mod tests {
         assert!(err.to_string().contains("expected 1"));
     }
 
+    // ========== eprint() ==========
+
+    #[test]
+    fn vm_eprint_bypasses_captured_output() {
+        assert_eq!(run_vm(r#"eprint("oops"); print "ok";"#), vec!["ok"]);
+        assert_eq!(run_vm("print eprint(1);"), vec!["nil"]);
+    }
+
+    #[test]
+    fn vm_eprint_wrong_arity() {
+        let err = run_vm_err("eprint();");
+        assert!(err.to_string().contains("expected 1"));
+    }
+
     #[test]
     fn vm_read_line_wrong_arity() {
         let err = run_vm_err("readLine(42);");
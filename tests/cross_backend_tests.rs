@@ -26,7 +26,7 @@ fn run_llvm(fixture_path: &str) -> Vec<String> {
     let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let lox_file = project_root.join(fixture_path);
     let ll_file = lox_file.with_extension("ll");
-    let runtime_obj = project_root.join("runtime/lox_runtime.o");
+    let runtime_obj = PathBuf::from(env!("LOX_RUNTIME_OBJ"));
 
     assert!(
         runtime_obj.exists(),
@@ -92,6 +92,13 @@ fn assert_backends_match(fixture_name: &str) {
 #[case("counter.lox")]
 #[case("strings.lox")]
 #[case("classes.lox")]
+#[case("print_multi.lox")]
+#[case("switch.lox")]
+#[case("nil_coalescing.lox")]
+#[case("const.lox")]
+#[case("power.lox")]
+#[case("is_operator.lox")]
+#[case("print_user_callables.lox")]
 fn cross_backend(#[case] fixture: &str) {
     assert_backends_match(fixture);
 }
@@ -38,6 +38,15 @@ fn run_fixture_err(source: &str) -> RuntimeError {
 #[case("counter.lox")]
 #[case("fib.lox")]
 #[case("hello.lox")]
+#[case("print_multi.lox")]
+#[case("print_callables.lox")]
+#[case("print_user_callables.lox")]
+#[case("switch.lox")]
+#[case("nil_coalescing.lox")]
+#[case("const.lox")]
+#[case("power.lox")]
+#[case("is_operator.lox")]
+#[case("super_chaining.lox")]
 #[case("shebang.lox")]
 #[case("to_number.lox")]
 fn interpreter_fixture(#[case] fixture: &str) {
@@ -141,3 +150,65 @@ fn read_line_to_number_invalid() {
     let output = run_lox_with_stdin("read_line_to_number.lox", b"banana\n");
     assert_eq!(output, "not a number\n");
 }
+
+// ---------------------------------------------------------------------------
+// --deny-unreachable only applies when the tree-walk interpreter runs a file
+// ---------------------------------------------------------------------------
+
+#[rstest]
+#[case::repl(&[])]
+#[case::compile_bytecode(&["--compile-bytecode", "fixtures/hello.lox"])]
+#[case::disassemble(&["--disassemble", "fixtures/hello.lox"])]
+#[case::emit_deps(&["--emit-deps", "fixtures/hello.lox"])]
+#[case::jit(&["--jit", "fixtures/hello.lox"])]
+fn deny_unreachable_rejects_other_modes(#[case] args: &[&str]) {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("--deny-unreachable")
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("run vibe-lox");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--deny-unreachable can only be used"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn deny_unreachable_fails_a_script_with_dead_code() {
+    let source = std::env::temp_dir().join("deny_unreachable_dead_code.lox");
+    std::fs::write(&source, "fun f() { return 1; print 2; }\nprint f();\n")
+        .expect("write temp source");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .args(["-q", "--deny-unreachable"])
+        .arg(&source)
+        .output()
+        .expect("run vibe-lox");
+    let _ = std::fs::remove_file(&source);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+// ---------------------------------------------------------------------------
+// --emit-deps prints its own report, so other output modes conflict with it
+// ---------------------------------------------------------------------------
+
+#[rstest]
+#[case::dump_tokens("--dump-tokens")]
+#[case::dump_ast("--dump-ast")]
+#[case::disassemble("--disassemble")]
+#[case::compile_bytecode("--compile-bytecode")]
+#[case::stats("--stats")]
+fn emit_deps_conflicts_with_other_output_modes(#[case] flag: &str) {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--emit-deps", flag, "fixtures/hello.lox"])
+        .output()
+        .expect("run vibe-lox");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"), "stderr: {stderr}");
+}
@@ -12,7 +12,7 @@ fn run_llvm_fixture(fixture_name: &str) -> String {
     let ll_file = project_root
         .join("fixtures")
         .join(fixture_name.replace(".lox", ".ll"));
-    let runtime_obj = project_root.join("runtime/lox_runtime.o");
+    let runtime_obj = PathBuf::from(env!("LOX_RUNTIME_OBJ"));
 
     // Ensure tmp/ and runtime exist
     std::fs::create_dir_all(project_root.join("tmp")).expect("create tmp dir");
@@ -62,7 +62,7 @@ fn run_llvm_fixture_with_stdin(fixture_name: &str, stdin_data: &[u8]) -> String
     let ll_file = project_root
         .join("fixtures")
         .join(fixture_name.replace(".lox", ".ll"));
-    let runtime_obj = project_root.join("runtime/lox_runtime.o");
+    let runtime_obj = PathBuf::from(env!("LOX_RUNTIME_OBJ"));
 
     std::fs::create_dir_all(project_root.join("tmp")).expect("create tmp dir");
     assert!(
@@ -123,7 +123,7 @@ fn run_llvm_error_fixture(fixture_name: &str) -> String {
     let ll_file = project_root
         .join("fixtures")
         .join(fixture_name.replace(".lox", ".ll"));
-    let runtime_obj = project_root.join("runtime/lox_runtime.o");
+    let runtime_obj = PathBuf::from(env!("LOX_RUNTIME_OBJ"));
 
     std::fs::create_dir_all(project_root.join("tmp")).expect("create tmp dir");
     assert!(
@@ -171,6 +171,13 @@ fn run_llvm_error_fixture(fixture_name: &str) -> String {
 #[case("strings.lox")]
 #[case("classes.lox")]
 #[case("to_number.lox")]
+#[case("print_multi.lox")]
+#[case("switch.lox")]
+#[case("nil_coalescing.lox")]
+#[case("const.lox")]
+#[case("power.lox")]
+#[case("is_operator.lox")]
+#[case("print_user_callables.lox")]
 fn llvm_fixture(#[case] fixture: &str) {
     let output = run_llvm_fixture(fixture);
     let expected_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -197,6 +204,40 @@ fn llvm_error_fixture(#[case] fixture: &str) {
     assert_eq!(stderr, expected);
 }
 
+/// Run a .lox fixture in-process with `--jit` and return stdout.
+fn run_jit_fixture(fixture_name: &str) -> String {
+    let lox_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(fixture_name);
+    let output = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .args(["--jit", lox_file.to_str().unwrap()])
+        .output()
+        .expect("run vibe-lox --jit");
+    assert!(
+        output.status.success(),
+        "jit run failed (exit {}): {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("jit output is valid UTF-8")
+}
+
+#[rstest]
+#[case("arithmetic.lox")]
+#[case("hello.lox")]
+#[case("fib.lox")]
+#[case("counter.lox")]
+#[case("classes.lox")]
+fn jit_fixture(#[case] fixture: &str) {
+    let output = run_jit_fixture(fixture);
+    let expected_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(fixture.replace(".lox", ".expected"));
+    let expected = std::fs::read_to_string(&expected_path)
+        .unwrap_or_else(|_| panic!("read expected file {}", expected_path.display()));
+    assert_eq!(output, expected);
+}
+
 /// readLine() on empty stdin returns nil.
 #[test]
 fn llvm_read_line_eof() {
@@ -163,3 +163,72 @@ fn native_compile_rejects_blox() {
     let _ = std::fs::remove_file(&blox_file);
     let _ = std::fs::remove_file(&exe_path);
 }
+
+#[test]
+fn native_compile_obj_writes_object_file() {
+    let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let tmp_dir = project_root.join("tmp");
+    std::fs::create_dir_all(&tmp_dir).expect("create tmp dir");
+
+    let lox_file = project_root.join("fixtures/hello.lox");
+    let obj_path = tmp_dir.join("test_compile_obj.o");
+    let output = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .args([
+            "--compile-obj",
+            lox_file.to_str().unwrap(),
+            "-o",
+            obj_path.to_str().unwrap(),
+        ])
+        .output()
+        .expect("run --compile-obj");
+    assert!(
+        output.status.success(),
+        "compile failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let size = std::fs::metadata(&obj_path)
+        .expect("object file exists")
+        .len();
+    assert!(size > 0, "object file is empty");
+
+    let _ = std::fs::remove_file(&obj_path);
+}
+
+#[test]
+fn native_target_requires_compile_flag() {
+    let lox_file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/hello.lox");
+    let output = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .args([
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            lox_file.to_str().unwrap(),
+        ])
+        .output()
+        .expect("run with --target");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--target can only be used"),
+        "expected --target usage error, got: {stderr}"
+    );
+}
+
+#[rstest]
+#[case::jit(&["--jit", "--opt-level", "2"])]
+#[case::tree_walk(&["--opt-level", "1"])]
+#[case::bytecode(&["--compile-bytecode", "--opt-level", "2"])]
+fn native_opt_level_requires_llvm_compile_flag(#[case] args: &[&str]) {
+    let lox_file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/hello.lox");
+    let output = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .args(args)
+        .arg(&lox_file)
+        .output()
+        .expect("run with --opt-level");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--opt-level can only be used"),
+        "expected --opt-level usage error, got: {stderr}"
+    );
+}
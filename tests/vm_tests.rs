@@ -1,10 +1,14 @@
 use std::path::PathBuf;
 
 use rstest::rstest;
-use vibe_lox::error::RuntimeError;
+use vibe_lox::error::{EvalError, RuntimeError};
+use vibe_lox::interpreter::Interpreter;
+use vibe_lox::interpreter::resolver::Resolver;
+use vibe_lox::parser::Parser;
+use vibe_lox::scanner;
 use vibe_lox::vm::chunk;
-use vibe_lox::vm::compile_to_chunk;
 use vibe_lox::vm::vm::Vm;
+use vibe_lox::vm::{compile_to_chunk, interpret_vm};
 
 fn run_vm_fixture(source: &str) -> Vec<String> {
     let compiled = compile_to_chunk(source).expect("compile should succeed");
@@ -22,6 +26,14 @@ fn run_vm_roundtrip(source: &str) -> Vec<String> {
     vm.output().to_vec()
 }
 
+fn run_vm_optimized(source: &str) -> Vec<String> {
+    let mut compiled = compile_to_chunk(source).expect("compile should succeed");
+    compiled.optimize();
+    let mut vm = Vm::new();
+    vm.interpret(compiled).expect("interpret should succeed");
+    vm.output().to_vec()
+}
+
 fn run_vm_err(source: &str) -> RuntimeError {
     let compiled = compile_to_chunk(source).expect("compile should succeed");
     let mut vm = Vm::new();
@@ -35,6 +47,15 @@ fn run_vm_err(source: &str) -> RuntimeError {
 #[case("counter.lox")]
 #[case("fib.lox")]
 #[case("hello.lox")]
+#[case("print_multi.lox")]
+#[case("print_callables.lox")]
+#[case("print_user_callables.lox")]
+#[case("switch.lox")]
+#[case("nil_coalescing.lox")]
+#[case("const.lox")]
+#[case("power.lox")]
+#[case("is_operator.lox")]
+#[case("super_chaining.lox")]
 fn vm_fixture(#[case] fixture: &str) {
     let fixture_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures");
     let source = std::fs::read_to_string(fixture_dir.join(fixture))
@@ -45,6 +66,25 @@ fn vm_fixture(#[case] fixture: &str) {
     assert_eq!(run_vm_fixture(&source), expected_lines);
 }
 
+#[rstest]
+#[case("arithmetic.lox")]
+#[case("scoping.lox")]
+#[case("classes.lox")]
+#[case("counter.lox")]
+#[case("fib.lox")]
+#[case("switch.lox")]
+#[case("nil_coalescing.lox")]
+#[case("super_chaining.lox")]
+fn vm_optimized_fixture(#[case] fixture: &str) {
+    let fixture_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let source = std::fs::read_to_string(fixture_dir.join(fixture))
+        .unwrap_or_else(|_| panic!("read fixture {fixture}"));
+    let expected = std::fs::read_to_string(fixture_dir.join(fixture.replace(".lox", ".expected")))
+        .unwrap_or_else(|_| panic!("read expected for {fixture}"));
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    assert_eq!(run_vm_optimized(&source), expected_lines);
+}
+
 #[rstest]
 #[case("fib.lox")]
 #[case("classes.lox")]
@@ -74,6 +114,80 @@ fn vm_backtrace_nested_calls() {
     assert_eq!(frames[2].function_name, "outer");
 }
 
+#[test]
+fn vm_backtrace_two_function_frames() {
+    let source = "fun inner() {\n  -\"x\";\n}\nfun outer() {\n  inner();\n}\nouter();\n";
+    let err = run_vm_err(source);
+    let frames: Vec<(&str, usize)> = err
+        .backtrace_frames()
+        .iter()
+        .map(|f| (f.function_name.as_str(), f.line))
+        .collect();
+    assert_eq!(frames, vec![("inner", 2), ("outer", 5), ("<script>", 7)]);
+}
+
+#[test]
+fn vm_backtrace_printed_for_bytecode_when_enabled() {
+    use std::process::Command;
+    let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let tmp_dir = project_root.join("tmp");
+    std::fs::create_dir_all(&tmp_dir).expect("create tmp dir");
+    let blox_file = tmp_dir.join("vm_backtrace_nested.blox");
+    let lox_file = project_root.join("fixtures/backtrace_nested.lox");
+
+    let compile = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .args(["-q", "--compile-bytecode", "-o"])
+        .arg(&blox_file)
+        .arg(&lox_file)
+        .output()
+        .expect("run vibe-lox --compile-bytecode");
+    assert!(
+        compile.status.success(),
+        "compile failed: {}",
+        String::from_utf8_lossy(&compile.stderr)
+    );
+
+    let run = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .arg(&blox_file)
+        .env("LOX_BACKTRACE", "1")
+        .output()
+        .expect("run vibe-lox on bytecode");
+    let _ = std::fs::remove_file(&blox_file);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(!run.status.success());
+    assert!(stderr.contains("stack backtrace:"), "stderr: {stderr}");
+    assert!(stderr.contains("0: inner()"), "stderr: {stderr}");
+    assert!(stderr.contains("2: outer()"), "stderr: {stderr}");
+}
+
+#[test]
+fn stats_flag_summarizes_bytecode() {
+    use std::process::Command;
+    let project_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .arg("--stats")
+        .arg(project_root.join("fixtures/fib.lox"))
+        .output()
+        .expect("run vibe-lox --stats");
+    assert!(
+        output.status.success(),
+        "stats failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let source =
+        std::fs::read_to_string(project_root.join("fixtures/fib.lox")).expect("read fixture");
+    let compiled = compile_to_chunk(&source).expect("compile should succeed");
+    let expected = chunk::ChunkStats::collect(&compiled);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, expected.to_string());
+    assert!(
+        stdout.contains(&format!("instructions:     {}\n", expected.instructions)),
+        "stdout: {stdout}"
+    );
+    assert!(stdout.contains("nested functions: 1\n"), "stdout: {stdout}");
+}
+
 #[test]
 fn vm_backtrace_includes_line_in_error_message() {
     let source = "var x = -\"bad\";\n";
@@ -94,24 +208,51 @@ fn vm_backtrace_top_level_has_script_frame() {
     assert_eq!(frames[0].function_name, "<script>");
 }
 
+#[test]
+fn vm_rejects_assignment_to_const() {
+    let err = compile_to_chunk("const limit = 10; limit = 11;").expect_err("should not compile");
+    assert!(
+        err.to_string().contains("can't assign to constant 'limit'"),
+        "got: {err}"
+    );
+}
+
 #[test]
 fn vm_bytecode_roundtrip_with_magic_header() {
     let compiled = compile_to_chunk("print 1 + 2;").expect("compile should succeed");
-    let payload = rmp_serde::to_vec(&compiled).expect("serialize should succeed");
-
-    let mut bytes = Vec::with_capacity(4 + payload.len());
-    bytes.extend_from_slice(b"blox");
-    bytes.extend_from_slice(&payload);
+    let bytes = compiled.to_blox().expect("encode should succeed");
 
     assert_eq!(&bytes[..4], b"blox", "file should start with magic header");
 
-    let loaded: chunk::Chunk =
-        rmp_serde::from_slice(&bytes[4..]).expect("deserialize should succeed");
+    let loaded = chunk::Chunk::from_blox(&bytes).expect("decode should succeed");
     let mut vm = Vm::new();
     vm.interpret(loaded).expect("interpret should succeed");
     assert_eq!(vm.output(), &["3"]);
 }
 
+#[test]
+fn vm_rejects_bytecode_from_another_format_version() {
+    let compiled = compile_to_chunk("print 1;").expect("compile should succeed");
+    let mut bytes = compiled.to_blox().expect("encode should succeed");
+    bytes[4..8].copy_from_slice(&(chunk::BLOX_VERSION - 1).to_le_bytes());
+    let blox_path = std::env::temp_dir().join("vm_old_format_version.blox");
+    std::fs::write(&blox_path, &bytes).expect("write blox temp file");
+
+    let run = std::process::Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .arg("-q")
+        .arg(&blox_path)
+        .output()
+        .expect("run vibe-lox on bytecode");
+    let _ = std::fs::remove_file(&blox_path);
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(!run.status.success());
+    assert!(
+        stderr.contains(&format!("blox format {}", chunk::BLOX_VERSION - 1)),
+        "stderr: {stderr}"
+    );
+    assert!(run.stdout.is_empty());
+}
+
 // ========== VM toNumber() via inline execution ==========
 
 fn run_vm_source(source: &str) -> Vec<String> {
@@ -154,6 +295,31 @@ fn vm_to_number_non_string_types() {
     assert_eq!(run_vm_source("print toNumber(false);"), vec!["nil"]);
 }
 
+// ========== Number display agrees with the interpreter ==========
+
+fn run_interpreter_source(source: &str) -> Vec<String> {
+    let tokens = scanner::scan(source).expect("scan should succeed");
+    let program = Parser::new(tokens).parse().expect("parse should succeed");
+    let locals = Resolver::new()
+        .resolve(&program)
+        .expect("resolve should succeed");
+    let mut interp = Interpreter::new();
+    interp
+        .interpret(&program, locals)
+        .expect("interpret should succeed");
+    interp.output().to_vec()
+}
+
+#[rstest]
+#[case("print 0;", "0")]
+#[case("print -0;", "0")]
+#[case("print 1000000000000000000000;", "1000000000000000000000")]
+#[case("print 0.5;", "0.5")]
+fn number_display_matches_interpreter(#[case] source: &str, #[case] expected: &str) {
+    assert_eq!(run_vm_source(source), vec![expected]);
+    assert_eq!(run_interpreter_source(source), vec![expected]);
+}
+
 // ========== to_number.lox fixture via VM ==========
 
 #[test]
@@ -181,13 +347,7 @@ fn run_vm_subprocess(fixture: &str, stdin_data: &[u8]) -> String {
         std::fs::read_to_string(&fixture_path).unwrap_or_else(|_| panic!("read fixture {fixture}"));
     let compiled =
         compile_to_chunk(&source).unwrap_or_else(|_| panic!("compile fixture {fixture}"));
-    let blox_bytes = {
-        let payload = rmp_serde::to_vec(&compiled).expect("serialize");
-        let mut b = Vec::with_capacity(4 + payload.len());
-        b.extend_from_slice(b"blox");
-        b.extend_from_slice(&payload);
-        b
-    };
+    let blox_bytes = compiled.to_blox().expect("encode blox");
     let blox_path = std::env::temp_dir().join(fixture.replace(".lox", ".blox"));
     std::fs::write(&blox_path, &blox_bytes).expect("write blox temp file");
 
@@ -234,3 +394,64 @@ fn vm_read_line_to_number_invalid() {
     let output = run_vm_subprocess("read_line_to_number.lox", b"hello\n");
     assert_eq!(output.trim(), "not a number");
 }
+
+#[rstest]
+#[case::scan("print @;")]
+#[case::parse("print 1")]
+#[case::resolve("{ var a = 1; var a = 2; }")]
+#[case::initializer_return_value("class A { init() { return 1; } }")]
+fn both_backends_report_compile_errors_alike(#[case] source: &str) {
+    let interpreted = Interpreter::new().run_source(source).unwrap_err();
+    let compiled = interpret_vm(source).unwrap_err();
+    assert!(
+        matches!(interpreted, EvalError::Compile(_)),
+        "{interpreted:?}"
+    );
+    assert!(matches!(compiled, EvalError::Compile(_)), "{compiled:?}");
+    assert_eq!(compiled.to_string(), interpreted.to_string());
+}
+
+#[rstest]
+#[case::type_error("print -\"x\";", "operand must be a number")]
+#[case::undefined_global("print missing;", "undefined variable 'missing'")]
+#[case::arity("fun f(a) {} f();", "expected 1 arguments but got 0")]
+fn both_backends_report_runtime_errors_alike(#[case] source: &str, #[case] expected: &str) {
+    let interpreted = Interpreter::new().run_source(source).unwrap_err();
+    let compiled = interpret_vm(source).unwrap_err();
+    for err in [&interpreted, &compiled] {
+        let EvalError::Runtime(runtime) = err else {
+            panic!("expected a runtime error, got {err:?}");
+        };
+        assert!(!runtime.is_return());
+        assert!(err.to_string().to_lowercase().contains(expected), "{err}");
+    }
+    let EvalError::Runtime(compiled) = compiled else {
+        unreachable!("checked above");
+    };
+    assert!(
+        !compiled.backtrace_frames().is_empty(),
+        "VM runtime errors carry a backtrace"
+    );
+}
+
+#[test]
+fn version_reports_blox_format_and_opcode_count() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vibe-lox"))
+        .arg("--version")
+        .output()
+        .expect("run vibe-lox --version");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(env!("CARGO_PKG_VERSION")), "{stdout}");
+    assert!(
+        stdout.contains(&format!("blox format {}", chunk::BLOX_VERSION)),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!(
+            "{} opcodes",
+            <chunk::OpCode as strum::EnumCount>::COUNT
+        )),
+        "{stdout}"
+    );
+}